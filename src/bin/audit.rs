@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+use clap::Parser;
+use rdkafka::producer::FutureProducer;
+use serde::Serialize;
+use tracing::{error, info};
+use cross_partition_order_book::transport::SnapshotStore;
+use cross_partition_order_book::transport::kafka::KafkaSnapshotStore;
+use cross_partition_order_book::types::order_book::BookViolation;
+use cross_partition_order_book::utils::config::{Config, ConfigArgs};
+use cross_partition_order_book::utils::logging;
+use cross_partition_order_book::utils::snapshot::EngineSnapshot;
+
+/// Checks the order book invariants of engine snapshots, those on the
+/// snapshots topic or the given files, printing the violations as JSON.
+/// Exits with 1 when there are any.
+#[derive(Debug, Parser)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Snapshot files to audit instead of the snapshots topic
+    files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct Violation {
+    partition: i32,
+    offset: Option<i64>,
+    instrument: String,
+    #[serde(flatten)]
+    violation: BookViolation,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let config = Config::from_args(&args.config);
+    logging::init(&config.logging);
+
+    let mut snapshots = Vec::new();
+    if args.files.is_empty() {
+        let producer: FutureProducer = config.kafka_client().create().expect("Producer creation failed");
+        let store = KafkaSnapshotStore::new(producer, config.kafka_client(), &config.topics.snapshots, config.message_timeout());
+        for partition in 0..config.kafka.partition_count {
+            match store.load(partition).await {
+                Ok(Some(payload)) => snapshots.push((format!("partition {}", partition), payload)),
+                Ok(None) => info!(partition, "No snapshot"),
+                Err(e) => error!(partition, error = %e, "Failed to load snapshot"),
+            }
+        }
+    } else {
+        for path in &args.files {
+            match std::fs::read_to_string(path) {
+                Ok(payload) => snapshots.push((path.display().to_string(), payload)),
+                Err(e) => error!(file = %path.display(), error = %e, "Failed to read snapshot"),
+            }
+        }
+    }
+
+    let mut violations = Vec::new();
+    let mut unreadable = false;
+    for (source, payload) in snapshots {
+        let snapshot = match serde_json::from_str::<EngineSnapshot>(&payload) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                error!(%source, error = %e, "Failed to parse snapshot");
+                unreadable = true;
+                continue;
+            }
+        };
+        let found = snapshot.engine.check_invariants();
+        info!(%source, books = snapshot.engine.order_books.len(), violations = found.len(), "Audited snapshot");
+        violations.extend(found.into_iter().map(|(instrument, violation)| Violation {
+            partition: snapshot.partition,
+            offset: snapshot.offset,
+            instrument,
+            violation,
+        }));
+    }
+
+    println!("{}", serde_json::to_string_pretty(&violations).expect("Failed to serialize violations"));
+    if unreadable || !violations.is_empty() {
+        std::process::exit(1);
+    }
+}
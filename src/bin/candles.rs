@@ -0,0 +1,55 @@
+use clap::Parser;
+use rdkafka::producer::FutureProducer;
+use tracing::info;
+use cross_partition_order_book::services::candles::{self, CandleBuilder};
+use cross_partition_order_book::transport::kafka::{KafkaSink, KafkaSource};
+use cross_partition_order_book::utils::config::{Config, ConfigArgs};
+use cross_partition_order_book::utils::logging;
+
+/// Builds OHLCV candles from match-events and publishes completed bars.
+#[derive(Debug, Parser)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Rebuild and republish every bar from the beginning of match-events,
+    /// then carry on with new trades
+    #[arg(long)]
+    backfill: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let config = Config::from_args(&args.config);
+    logging::init(&config.logging);
+
+    info!("Starting Candles...");
+
+    // Validated when the config was loaded
+    let intervals = config.candle_intervals().expect("valid candle intervals");
+
+    // A backfill reads in a group of its own, which has no offsets yet
+    let group = if args.backfill {
+        format!("{}-backfill-{}", config.groups.candles, uuid::Uuid::new_v4())
+    } else {
+        config.groups.candles.clone()
+    };
+    let source = KafkaSource::subscribe(
+        config.kafka_client()
+            .set("auto.offset.reset", "earliest")
+            .set("enable.auto.commit", "false"),
+        &group,
+        &[&config.topics.match_events],
+    )
+    .expect("Consumer creation failed");
+
+    let producer: FutureProducer = config.kafka_client()
+        .set("message.timeout.ms", config.kafka.message_timeout_ms.to_string())
+        .create()
+        .expect("Producer creation error");
+    let sink = KafkaSink::new(producer, config.publish_timeout());
+
+    info!(group = %group, intervals = ?config.candles.intervals, "Building candles...");
+
+    candles::run(source, sink, CandleBuilder::new(intervals), config).await;
+}
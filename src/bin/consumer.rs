@@ -1,52 +1,49 @@
-use futures_util::StreamExt;
-use rdkafka::consumer::{Consumer, StreamConsumer};
-use rdkafka::Message;
-use rdkafka::ClientConfig;
-use serde_json;
+use tracing::{error, info, warn};
+use cross_partition_order_book::transport::OrderSource;
+use cross_partition_order_book::transport::kafka::KafkaSource;
 use cross_partition_order_book::types::order::Order;
+use cross_partition_order_book::utils::config::Config;
+use cross_partition_order_book::utils::logging::{self, TRACE_ID_HEADER};
 
 #[tokio::main]
 async fn main() {
-    let consumer: StreamConsumer = ClientConfig::new()
-        .set("bootstrap.servers", "localhost:9092")
-        .set("group.id", "order-consumer-group")
-        .set("auto.offset.reset", "earliest")
-        .create()
-        .expect("Consumer creation failed");
+    let config = Config::from_cli();
+    logging::init(&config.logging);
 
-    consumer
-        .subscribe(&["orders"])
-        .expect("Can't subscribe to specified topic");
+    let mut source = KafkaSource::subscribe(
+        config.kafka_client().set("auto.offset.reset", "earliest"),
+        &config.groups.order_consumer,
+        &[&config.topics.orders],
+    )
+    .expect("Consumer creation failed");
 
-    println!("Waiting for orders...");
+    info!("Waiting for orders...");
 
-    let mut message_stream = consumer.stream();
-    while let Some(message) = message_stream.next().await {
+    while let Some(message) = source.next().await {
         match message {
             Ok(m) => {
-                let payload = match m.payload_view::<str>() {
-                    Some(Ok(s)) => s,
-                    Some(Err(_)) => "<invalid utf8>",
-                    None => "<empty payload>",
-                };
+                let payload = m.payload.as_deref().unwrap_or("<empty payload>");
 
-                if payload != "<empty payload>" && payload != "<invalid utf8>" {
+                if payload != "<empty payload>" {
                     match serde_json::from_str::<Order>(payload) {
                         Ok(order) => {
-                            println!(
-                                "Received order: {:?} (partition={}, offset={})",
-                                order,
-                                m.partition(),
-                                m.offset()
+                            info!(
+                                trace_id = m.header(TRACE_ID_HEADER),
+                                order_id = %order.id,
+                                instrument = %order.instrument,
+                                partition = m.partition,
+                                offset = m.offset,
+                                order = ?order,
+                                "Received order"
                             );
                         }
-                        Err(e) => eprintln!("Failed to parse order JSON: {}", e),
+                        Err(e) => warn!(partition = m.partition, offset = m.offset, error = %e, "Failed to parse order JSON"),
                     }
                 } else {
-                    println!("Received non-order payload: {}", payload);
+                    warn!(partition = m.partition, offset = m.offset, "Received non-order payload");
                 }
             }
-            Err(e) => eprintln!("Kafka error: {}", e),
+            Err(e) => error!(error = %e, "Kafka error"),
         }
     }
 }
@@ -0,0 +1,59 @@
+use clap::Parser;
+use rdkafka::producer::FutureProducer;
+use tracing::{error, info};
+use cross_partition_order_book::transport::EventSink;
+use cross_partition_order_book::transport::kafka::KafkaSink;
+use cross_partition_order_book::types::control_message::ControlMessage;
+use cross_partition_order_book::utils::config::{Config, ConfigArgs};
+use cross_partition_order_book::utils::logging::{self, new_trace_id, TRACE_ID_HEADER};
+
+/// Publishes one control-plane command given as JSON, e.g.
+/// control '{"command":"set_trading_phase","instrument":"AAPL","phase":"auction"}'
+#[derive(Debug, Parser)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Control message JSON
+    #[arg(required_unless_present = "print_config")]
+    command: Option<String>,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let config = Config::from_args(&args.config);
+    logging::init(&config.logging);
+    let payload = args.command.unwrap_or_default();
+
+    // Validate before publishing so engines never see a malformed command
+    let command = match serde_json::from_str::<ControlMessage>(&payload) {
+        Ok(command) => command,
+        Err(e) => {
+            error!(error = %e, "Invalid control message");
+            std::process::exit(2);
+        }
+    };
+
+    let producer: FutureProducer = config.kafka_client()
+        .set("message.timeout.ms", config.kafka.message_timeout_ms.to_string())
+        .create()
+        .expect("Producer creation error");
+    let sink = KafkaSink::new(producer, config.publish_timeout());
+
+    let payload = serde_json::to_string(&command).expect("Failed to serialize control message");
+    let trace_id = new_trace_id();
+    let headers = [(TRACE_ID_HEADER, trace_id.as_str())];
+    match sink.send_with_headers(&config.topics.control_plane, "control", &payload, None, &headers).await {
+        Ok(delivery) => info!(
+            trace_id = %trace_id,
+            command = ?command,
+            partition = delivery.partition,
+            offset = delivery.offset,
+            "Sent control message"
+        ),
+        Err(e) => {
+            error!(trace_id = %trace_id, error = %e, "Failed to send control message");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+use cross_partition_order_book::services::ledger::{self, Ledger};
+use cross_partition_order_book::transport::kafka::KafkaSource;
+use cross_partition_order_book::utils::config::Config;
+use cross_partition_order_book::utils::instrument_registry::InstrumentRegistry;
+use cross_partition_order_book::utils::logging;
+
+#[tokio::main]
+async fn main() {
+    let config = Config::from_cli();
+    logging::init(&config.logging);
+
+    info!("Starting Ledger...");
+
+    // Cash is booked in each instrument's currency
+    let registry = InstrumentRegistry::load(&config.engine.instruments_file).expect("Failed to load instrument registry");
+    let ledger = Ledger::open(&config.ledger.database).expect("Failed to open ledger");
+    let ledger = Arc::new(Mutex::new(ledger));
+
+    let source = KafkaSource::subscribe(
+        config.kafka_client()
+            .set("auto.offset.reset", "earliest")
+            .set("enable.auto.commit", "false"),
+        &config.groups.ledger,
+        &[&config.topics.match_events],
+    )
+    .expect("Consumer creation failed");
+
+    if let Some(addr) = config.ledger_addr() {
+        // Validated when the config was loaded
+        let addr = addr.expect("valid ledger address");
+        let served = ledger.clone();
+        tokio::spawn(async move {
+            if let Err(e) = ledger::serve(addr, served).await {
+                error!(error = %e, "Ledger API failed");
+            }
+        });
+        info!(%addr, "Serving ledger queries");
+    }
+
+    tokio::spawn(ledger::export_daily(ledger.clone(), PathBuf::from(&config.ledger.statements_dir)));
+
+    info!(database = %config.ledger.database, "Booking trades...");
+
+    ledger::run(source, ledger, registry).await;
+}
@@ -1,65 +1,41 @@
-use futures_util::StreamExt;
-use rdkafka::consumer::{Consumer, StreamConsumer};
-use rdkafka::{Message, ClientConfig};
-use serde_json;
-use cross_partition_order_book::types::match_event::MatchEvent;
+use tracing::info;
+use cross_partition_order_book::services::match_monitor;
+use cross_partition_order_book::transport::kafka::KafkaSource;
+use cross_partition_order_book::utils::config::Config;
+use cross_partition_order_book::utils::logging::{self, TRACE_ID_HEADER};
 
 #[tokio::main]
 async fn main() {
-    println!("Starting Match Event Monitor...");
+    let config = Config::from_cli();
+    logging::init(&config.logging);
 
-    let consumer: StreamConsumer = ClientConfig::new()
-        .set("bootstrap.servers", "localhost:9092")
-        .set("group.id", "match-event-monitor-group")
-        .set("auto.offset.reset", "earliest")
-        .create()
-        .expect("Consumer creation failed");
+    info!("Starting Match Event Monitor...");
 
-    consumer
-        .subscribe(&["match-events"])
-        .expect("Can't subscribe to match-events topic");
+    let source = KafkaSource::subscribe(
+        config.kafka_client().set("auto.offset.reset", "earliest"),
+        &config.groups.match_monitor,
+        &[&config.topics.match_events],
+    )
+    .expect("Consumer creation failed");
 
-    println!("Monitoring match events...");
+    info!("Monitoring match events...");
 
-    let mut message_stream = consumer.stream();
-    while let Some(message) = message_stream.next().await {
-        match message {
-            Ok(m) => {
-                let payload = match m.payload_view::<str>() {
-                    Some(Ok(s)) => s,
-                    Some(Err(_)) => {
-                        eprintln!("Invalid UTF-8 in message payload");
-                        continue;
-                    }
-                    None => {
-                        eprintln!("Empty message payload");
-                        continue;
-                    }
-                };
-
-                match serde_json::from_str::<MatchEvent>(payload) {
-                    Ok(match_event) => {
-                        println!(
-                            "TRADE EXECUTED: {} | {} shares @ ${:.2} | Buyer: {} | Seller: {} | Time: {} (partition={}, offset={})",
-                            match_event.instrument,
-                            match_event.quantity,
-                            match_event.price,
-                            &match_event.buyer_order_id[..8], // Show first 8 chars of UUID
-                            &match_event.seller_order_id[..8],
-                            match_event.timestamp,
-                            m.partition(),
-                            m.offset()
-                        );
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to parse match event JSON: {}", e);
-                        eprintln!("Raw payload: {}", payload);
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("Kafka error: {}", e);
-            }
-        }
-    }
+    match_monitor::run(source, |match_event, record| {
+        info!(
+            trace_id = record.header(TRACE_ID_HEADER),
+            trade_id = %match_event.id,
+            instrument = %match_event.instrument,
+            quantity = match_event.quantity,
+            price = match_event.price,
+            buyer_order_id = %match_event.buyer_order_id,
+            seller_order_id = %match_event.seller_order_id,
+            aggressor_side = match_event.aggressor_side.as_deref(),
+            sequence = match_event.sequence,
+            timestamp_ns = match_event.timestamp_ns,
+            partition = record.partition,
+            offset = record.offset,
+            "Trade executed"
+        );
+    })
+    .await;
 }
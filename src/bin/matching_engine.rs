@@ -1,143 +1,105 @@
-use futures_util::StreamExt;
-use rdkafka::consumer::{Consumer, StreamConsumer};
-use rdkafka::producer::{FutureProducer, FutureRecord};
-use rdkafka::{Message, ClientConfig};
-use std::collections::HashMap;
-use std::time::Duration;
-use serde_json;
-use cross_partition_order_book::types::order::Order;
-use cross_partition_order_book::types::match_event::MatchEvent;
-use cross_partition_order_book::utils::matching_engine::MatchingEngine;
+use rdkafka::producer::FutureProducer;
+use tracing::{error, info};
+use uuid::Uuid;
+use cross_partition_order_book::services::{matching_engine, metrics, routing};
+use cross_partition_order_book::services::metrics::EngineMetrics;
+use cross_partition_order_book::transport::kafka::{KafkaPartitionedSource, KafkaSink, KafkaSnapshotStore, KafkaSource};
+use cross_partition_order_book::utils::config::Config;
+use cross_partition_order_book::utils::fee_schedule::FeeSchedule;
+use cross_partition_order_book::utils::session_calendar::SessionCalendar;
+use cross_partition_order_book::utils::instrument_registry::InstrumentRegistry;
+use cross_partition_order_book::utils::logging;
+use cross_partition_order_book::utils::routing::RoutingTable;
 
 #[tokio::main]
 async fn main() {
-    println!("Starting Order Matching Engine...");
+    let config = Config::from_cli();
+    logging::init(&config.logging);
 
-    // Create Kafka consumer for orders
-    let consumer: StreamConsumer = ClientConfig::new()
-        .set("bootstrap.servers", "localhost:9092")
-        .set("group.id", "matching-engine-group")
-        .set("auto.offset.reset", "earliest")
-        .set("enable.auto.commit", "false")
-        .create()
-        .expect("Consumer creation failed");
-
-    // Create Kafka producer for match events
-    let producer: FutureProducer = ClientConfig::new()
-        .set("bootstrap.servers", "localhost:9092")
-        .set("message.timeout.ms", "5000")
-        .create()
-        .expect("Producer creation failed");
-
-    // Subscribe to orders topic
-    consumer
-        .subscribe(&["orders"])
-        .expect("Can't subscribe to orders topic");
-
-    // Initialize matching engines per partition
-    let mut matching_engines: HashMap<i32, MatchingEngine> = HashMap::new();
+    info!("Starting Order Matching Engine...");
 
-    println!("Matching engine ready. Waiting for orders...");
+    // Engines only accept orders for instruments in the registry
+    let registry = InstrumentRegistry::load(&config.engine.instruments_file).expect("Failed to load instrument registry");
+    info!(count = registry.instruments.len(), "Loaded instruments");
 
-    let mut message_stream = consumer.stream();
-    while let Some(message) = message_stream.next().await {
-        match message {
-            Ok(m) => {
-                let partition = m.partition();
-                
-                // Get or create matching engine for this partition
-                let matching_engine = matching_engines
-                    .entry(partition)
-                    .or_insert_with(MatchingEngine::new);
+    let fees = if config.engine.fee_schedule_file.is_empty() {
+        FeeSchedule::default()
+    } else {
+        FeeSchedule::load(&config.engine.fee_schedule_file).expect("Failed to load fee schedule")
+    };
 
-                let payload = match m.payload_view::<str>() {
-                    Some(Ok(s)) => s,
-                    Some(Err(_)) => {
-                        eprintln!("Invalid UTF-8 in message payload");
-                        continue;
-                    }
-                    None => {
-                        eprintln!("Empty message payload");
-                        continue;
-                    }
-                };
+    let calendar = if config.engine.calendar_file.is_empty() {
+        SessionCalendar::default()
+    } else {
+        SessionCalendar::load(&config.engine.calendar_file).expect("Failed to load session calendar")
+    };
 
-                // Parse the order
-                match serde_json::from_str::<Order>(payload) {
-                    Ok(order) => {
-                        println!(
-                            "Processing order: {} {} {}@{} qty:{} (partition={})",
-                            order.instrument,
-                            order.side,
-                            order.id,
-                            order.price,
-                            order.quantity,
-                            partition
-                        );
+    // Create Kafka consumer for orders; each assigned partition is
+    // processed by a task of its own
+    let orders = KafkaPartitionedSource::subscribe(
+        config.kafka_client()
+            .set("auto.offset.reset", "earliest")
+            .set("enable.auto.commit", "false"),
+        &config.groups.matching_engine,
+        &config.topics.orders,
+    )
+    .expect("Consumer creation failed");
 
-                        // Process the order through the matching engine
-                        let matches = matching_engine.process_order(order);
+    // Every engine instance needs every control-plane command, so this
+    // consumer gets a group of its own
+    let control = KafkaSource::subscribe(
+        config.kafka_client().set("auto.offset.reset", "latest"),
+        &format!("{}-{}", config.groups.control_prefix, Uuid::new_v4()),
+        &[&config.topics.control_plane],
+    )
+    .expect("Control consumer creation failed");
 
-                        // Publish match events
-                        for match_event in matches {
-                            match serde_json::to_string(&match_event) {
-                                Ok(match_payload) => {
-                                    let delivery_result = producer
-                                        .send(
-                                            FutureRecord::to("match-events")
-                                                .key(&match_event.instrument)
-                                                .payload(&match_payload)
-                                                .partition(partition), // Same partition as the order
-                                            Duration::from_secs(1),
-                                        )
-                                        .await;
+    // Commands for migrated instruments go to their new partition. The
+    // routes of migrations made before this instance started are replayed
+    // from the start of the control-plane
+    let routes = RoutingTable::new(config.kafka.partition_count);
+    let route_history = KafkaSource::subscribe(
+        config.kafka_client().set("auto.offset.reset", "earliest"),
+        &format!("{}-{}", config.groups.control_prefix, Uuid::new_v4()),
+        &[&config.topics.control_plane],
+    )
+    .expect("Control consumer creation failed");
+    tokio::spawn(routing::follow(route_history, routes.clone()));
 
-                                    match delivery_result {
-                                        Ok((partition, offset)) => {
-                                            println!(
-                                                "Published match: {} traded {} {} @ {} (partition={}, offset={})",
-                                                match_event.instrument,
-                                                match_event.quantity,
-                                                match_event.buyer_order_id,
-                                                match_event.price,
-                                                partition,
-                                                offset
-                                            );
-                                        }
-                                        Err(e) => {
-                                            eprintln!("Failed to publish match event: {}", e);
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("Failed to serialize match event: {}", e);
-                                }
-                            }
-                        }
+    // Create Kafka producer for match events. A batch's events are in
+    // flight together, so idempotence is needed to keep retries in order
+    let producer: FutureProducer = config.kafka_client()
+        .set("message.timeout.ms", config.kafka.message_timeout_ms.to_string())
+        .set("enable.idempotence", "true")
+        // Snapshots of large books go through this producer too
+        .set("message.max.bytes", "10485760")
+        .create()
+        .expect("Producer creation failed");
+    let sink = KafkaSink::new(producer.clone(), config.publish_timeout());
 
-                        // Print order book status
-                        if let Some(order_book) = matching_engine.order_books.get(&order.instrument) {
-                            let best_bid = order_book.get_best_bid();
-                            let best_ask = order_book.get_best_ask();
-                            println!(
-                                "Order book {}: Bid={:?} Ask={:?} (partition={})",
-                                order_book.instrument, best_bid, best_ask, partition
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to parse order JSON: {}", e);
-                    }
-                }
+    // Books of revoked partitions are handed to their next owner through
+    // the snapshots topic
+    let snapshots = KafkaSnapshotStore::new(
+        producer,
+        config.kafka_client(),
+        &config.topics.snapshots,
+        config.message_timeout(),
+    );
 
-                // Commit the message
-                if let Err(e) = consumer.commit_message(&m, rdkafka::consumer::CommitMode::Async) {
-                    eprintln!("Failed to commit message: {}", e);
-                }
-            }
-            Err(e) => {
-                eprintln!("Kafka error: {}", e);
+    let engine_metrics = EngineMetrics::new();
+    if let Some(addr) = config.metrics_addr() {
+        // Validated when the config was loaded
+        let addr = addr.expect("valid metrics address");
+        let served = engine_metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr, served).await {
+                error!(error = %e, "Metrics endpoint failed");
             }
-        }
+        });
+        info!(%addr, "Serving metrics on /metrics");
     }
+
+    info!("Matching engine ready. Waiting for orders...");
+
+    matching_engine::run(orders, control, sink, snapshots, registry, fees, calendar, routes, config, engine_metrics).await;
 }
@@ -1,20 +1,41 @@
-use rdkafka::producer::{FutureProducer, FutureRecord};
-use rdkafka::ClientConfig;
+use rdkafka::producer::FutureProducer;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
 use uuid::Uuid;
-use serde_json;
+use cross_partition_order_book::services::producer::publish_order;
+use cross_partition_order_book::services::routing;
+use cross_partition_order_book::transport::kafka::{KafkaSink, KafkaSource};
 use cross_partition_order_book::types::order::Order;
-use cross_partition_order_book::utils::partitioner::custom_partition;
+use cross_partition_order_book::utils::config::Config;
+use cross_partition_order_book::utils::logging;
+use cross_partition_order_book::utils::routing::RoutingTable;
 
 #[tokio::main]
 async fn main() {
+    let config = Config::from_cli();
+    logging::init(&config.logging);
+
     // Connect to Kafka
-    let producer: FutureProducer = ClientConfig::new()
-        .set("bootstrap.servers", "localhost:9092")
+    let producer: FutureProducer = config.kafka_client()
+        .set("message.timeout.ms", config.kafka.message_timeout_ms.to_string())
+        // Retries after a lost acknowledgement must not write an order twice
+        .set("enable.idempotence", "true")
         .create()
         .expect("Producer creation error");
+    let sink = KafkaSink::new(producer, config.publish_timeout());
+
+    // Follow instrument migrations. Orders sent on a stale route are
+    // forwarded by the engine, so this only saves them a hop
+    let routes = RoutingTable::new(config.kafka.partition_count);
+    let control = KafkaSource::subscribe(
+        config.kafka_client().set("auto.offset.reset", "earliest"),
+        &format!("{}-{}", config.groups.control_prefix, Uuid::new_v4()),
+        &[&config.topics.control_plane],
+    )
+    .expect("Control consumer creation failed");
+    tokio::spawn(routing::follow(control, routes.clone()));
 
-    println!("Producing test orders to 'orders' topic...");
+    info!(topic = %config.topics.orders, "Producing test orders");
 
     // Create a mix of buy and sell orders that can potentially match
     let test_orders = vec![
@@ -55,36 +76,23 @@ async fn main() {
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs() as i64,
-        );
-
-        let payload = serde_json::to_string(&order).expect("Failed to serialize order");
-        let partition = custom_partition(&order.instrument, 8);
-
-        let delivery_status = producer
-            .send(
-                FutureRecord::to("orders")
-                    .key(&order.instrument)
-                    .payload(&payload)
-                    .partition(partition),
-                Duration::from_secs(0),
-            )
-            .await;
+        )
+        .with_client_order_id("demo", &format!("demo-{}", i + 1));
 
-        println!(
-            "Sent order {}: {} {} {}@{} qty:{} -> partition:{}, status={:?}",
-            i + 1,
-            order.instrument,
-            order.side,
-            &order.id[..8], // Show first 8 chars of UUID
-            order.price,
-            order.quantity,
-            partition,
-            delivery_status.map(|(p, o)| format!("{}:{}", p, o)).unwrap_or_else(|e| format!("Error: {}", e))
-        );
+        // Successful sends are logged with their trace id by publish_order
+        if let Err(e) = publish_order(&sink, &order, &routes, &config).await {
+            error!(
+                sequence = i + 1,
+                order_id = %order.id,
+                instrument = %order.instrument,
+                error = %e,
+                "Failed to send order"
+            );
+        }
 
         // Small delay between orders to make it easier to follow
         tokio::time::sleep(Duration::from_millis(100)).await;
     }
 
-    println!("Done producing {} orders!", test_orders.len());
+    info!(count = test_orders.len(), "Done producing orders");
 }
\ No newline at end of file
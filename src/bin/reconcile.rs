@@ -0,0 +1,80 @@
+use std::time::Duration;
+use clap::Parser;
+use tracing::info;
+use uuid::Uuid;
+use cross_partition_order_book::services::reconciliation;
+use cross_partition_order_book::transport::kafka::KafkaSource;
+use cross_partition_order_book::utils::config::{Config, ConfigArgs};
+use cross_partition_order_book::utils::instrument_registry::InstrumentRegistry;
+use cross_partition_order_book::utils::logging;
+use cross_partition_order_book::utils::session_calendar::SessionCalendar;
+
+/// Replays the orders topic through a fresh engine and checks match-events
+/// against it, printing the discrepancies as JSON. Exits with 1 when there
+/// are any.
+#[derive(Debug, Parser)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Start of the range to check, seconds since the epoch
+    #[arg(long, required_unless_present = "print_config")]
+    from: Option<i64>,
+    /// End of the range to check, exclusive; now if not given
+    #[arg(long)]
+    to: Option<i64>,
+    /// How long a topic must stay quiet to count as read to the end
+    #[arg(long, default_value_t = 5000)]
+    idle_ms: u64,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let config = Config::from_args(&args.config);
+    logging::init(&config.logging);
+
+    let from = args.from.unwrap_or_default();
+    let to = args.to.unwrap_or_else(|| chrono::Utc::now().timestamp());
+    let idle = Duration::from_millis(args.idle_ms);
+
+    // The replaying engine needs the same reference data as the real ones
+    let registry = InstrumentRegistry::load(&config.engine.instruments_file).expect("Failed to load instrument registry");
+    let calendar = if config.engine.calendar_file.is_empty() {
+        SessionCalendar::default()
+    } else {
+        SessionCalendar::load(&config.engine.calendar_file).expect("Failed to load session calendar")
+    };
+
+    // Groups of their own, so every topic is read from the beginning and
+    // no offsets are committed for anyone else
+    let subscribe = |topics: &[&str]| {
+        KafkaSource::subscribe(
+            config.kafka_client()
+                .set("auto.offset.reset", "earliest")
+                .set("enable.auto.commit", "false"),
+            &format!("reconcile-{}", Uuid::new_v4()),
+            topics,
+        )
+        .expect("Consumer creation failed")
+    };
+
+    info!(from, to, "Reading orders and control-plane...");
+    let inputs = reconciliation::read_until_idle(&mut subscribe(&[&config.topics.orders, &config.topics.control_plane]), idle).await;
+    info!(records = inputs.len(), "Reading match-events...");
+    let published = reconciliation::read_until_idle(&mut subscribe(&[&config.topics.match_events]), idle).await;
+
+    let matching_engine = reconciliation::replay_engine(registry, calendar, &config);
+    let report = reconciliation::reconcile(matching_engine, inputs, published, &config.topics, from, to);
+    info!(
+        orders_replayed = report.orders_replayed,
+        trades_expected = report.trades_expected,
+        trades_published = report.trades_published,
+        discrepancies = report.discrepancies.len(),
+        "Reconciled"
+    );
+    println!("{}", serde_json::to_string_pretty(&report).expect("Failed to serialize report"));
+
+    if !report.is_clean() {
+        std::process::exit(1);
+    }
+}
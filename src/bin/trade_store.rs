@@ -0,0 +1,101 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use clap::{Parser, Subcommand};
+use tracing::{error, info};
+use cross_partition_order_book::services::trade_store::{self, ExportFormat, TradeQuery, TradeStore};
+use cross_partition_order_book::transport::kafka::KafkaSource;
+use cross_partition_order_book::utils::config::{Config, ConfigArgs};
+use cross_partition_order_book::utils::logging;
+
+/// Stores every trade from match-events and serves trade history, or with
+/// `query` prints or exports it, e.g.
+/// trade_store query --instrument AAPL --from 1700000000 --format parquet --output aapl.parquet
+#[derive(Debug, Parser)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Query the stored trades and exit
+    Query {
+        #[arg(long)]
+        instrument: Option<String>,
+        /// Seconds since the epoch, inclusive
+        #[arg(long)]
+        from: Option<i64>,
+        /// Seconds since the epoch, exclusive
+        #[arg(long)]
+        to: Option<i64>,
+        /// Trades with this account on either side
+        #[arg(long)]
+        account: Option<String>,
+        /// Trades of this order on either side
+        #[arg(long)]
+        order_id: Option<String>,
+        #[arg(long)]
+        limit: Option<u32>,
+        #[arg(long, value_enum, default_value_t)]
+        format: ExportFormat,
+        /// File to write; standard output if not given
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let config = Config::from_args(&args.config);
+    logging::init(&config.logging);
+
+    let store = TradeStore::open(&config.trade_store.database).expect("Failed to open trade store");
+
+    if let Some(Command::Query { instrument, from, to, account, order_id, limit, format, output }) = args.command {
+        let query = TradeQuery { instrument, from, to, account, order_id, limit };
+        let exported = match &output {
+            Some(path) => File::create(path)
+                .map_err(Into::into)
+                .and_then(|file| store.export(&query, format, BufWriter::new(file))),
+            None => store.export(&query, format, std::io::stdout()),
+        };
+        if let Err(e) = exported {
+            error!(error = %e, "Failed to export trades");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    info!("Starting Trade Store...");
+
+    let store = Arc::new(Mutex::new(store));
+    let source = KafkaSource::subscribe(
+        config.kafka_client()
+            .set("auto.offset.reset", "earliest")
+            .set("enable.auto.commit", "false"),
+        &config.groups.trade_store,
+        &[&config.topics.match_events],
+    )
+    .expect("Consumer creation failed");
+
+    if let Some(addr) = config.trade_store_addr() {
+        // Validated when the config was loaded
+        let addr = addr.expect("valid trade store address");
+        let served = store.clone();
+        tokio::spawn(async move {
+            if let Err(e) = trade_store::serve(addr, served).await {
+                error!(error = %e, "Trade store API failed");
+            }
+        });
+        info!(%addr, "Serving trade queries");
+    }
+
+    info!(database = %config.trade_store.database, "Storing trades...");
+
+    trade_store::run(source, store).await;
+}
@@ -1,2 +1,4 @@
 pub mod types;
-pub mod utils;
\ No newline at end of file
+pub mod utils;
+pub mod transport;
+pub mod services;
\ No newline at end of file
@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use chrono::Utc;
+use tracing::{error, info, warn};
+use crate::transport::{EventSink, OrderSource, Record};
+use crate::types::candle::Candle;
+use crate::types::match_event::MatchEvent;
+use crate::utils::config::Config;
+
+// How long after a bar's end the clock closes it, for trades still on
+// their way from the engine
+const CLOSE_DELAY_SECS: i64 = 2;
+
+/// A bar still taking trades, with the offsets of its first and last trade
+/// in the partition it is read from.
+struct OpenCandle {
+    candle: Candle,
+    partition: i32,
+    first_offset: i64,
+    last_offset: i64,
+}
+
+/// Builds OHLCV bars of every instrument at every configured interval. A
+/// bar completes when a trade of its instrument falls after its end, or
+/// when the clock passes its end.
+pub struct CandleBuilder {
+    intervals: Vec<(String, i64)>,
+    open: HashMap<(String, usize), OpenCandle>,
+    // End of the last completed bar of each instrument and interval; later
+    // trades before it are too late to be counted
+    closed_until: HashMap<(String, usize), i64>,
+    // First and last offsets of completed bars, per partition, that may
+    // still span the restart offset
+    spans: HashMap<i32, Vec<(i64, i64)>>,
+}
+
+impl CandleBuilder {
+    /// A builder for bars of the given names and lengths in seconds, as
+    /// returned by `Config::candle_intervals`.
+    pub fn new(intervals: Vec<(String, i64)>) -> Self {
+        Self { intervals, open: HashMap::new(), closed_until: HashMap::new(), spans: HashMap::new() }
+    }
+
+    /// Adds a trade read at `offset` of `partition`, returning the bars of
+    /// its instrument that end at or before it.
+    pub fn add_trade(&mut self, trade: &MatchEvent, partition: i32, offset: i64) -> Vec<Candle> {
+        let mut completed = Vec::new();
+        for index in 0..self.intervals.len() {
+            let key = (trade.instrument.clone(), index);
+            if self.open.get(&key).is_some_and(|open| open.candle.end <= trade.timestamp) {
+                let open = self.open.remove(&key).expect("bar is open");
+                completed.push(self.close(key.clone(), open));
+            }
+
+            let (interval, length) = &self.intervals[index];
+            let closed_until = self.closed_until.get(&key).copied().unwrap_or(i64::MIN);
+            match self.open.get_mut(&key) {
+                _ if trade.timestamp < closed_until => {
+                    warn!(trade_id = %trade.id, instrument = %trade.instrument, interval = %interval, "Trade after its bar was closed; not counted");
+                }
+                Some(open) if open.candle.contains(trade.timestamp) => {
+                    open.candle.add(trade);
+                    if open.partition == partition {
+                        open.last_offset = offset;
+                    }
+                }
+                Some(_) => {
+                    warn!(trade_id = %trade.id, instrument = %trade.instrument, interval = %interval, "Trade older than its open bar; not counted");
+                }
+                None => {
+                    let candle = Candle::new(interval, *length, trade);
+                    self.open.insert(key, OpenCandle { candle, partition, first_offset: offset, last_offset: offset });
+                }
+            }
+        }
+        completed
+    }
+
+    /// Completes every bar that ends at or before `now`, in instrument
+    /// and start order.
+    pub fn close_due(&mut self, now: i64) -> Vec<Candle> {
+        let mut due: Vec<(String, usize)> = self.open
+            .iter()
+            .filter(|(_, open)| open.candle.end <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        due.sort();
+
+        let mut completed: Vec<Candle> = due
+            .into_iter()
+            .map(|key| {
+                let open = self.open.remove(&key).expect("bar is open");
+                self.close(key, open)
+            })
+            .collect();
+        completed.sort_by(|a, b| (&a.instrument, a.start).cmp(&(&b.instrument, b.start)));
+        completed
+    }
+
+    /// The first offset of `partition` that has to be read again to
+    /// rebuild its open bars in full, or `None` if it has none. Completed
+    /// bars spanning that offset move it back to their first trade, so
+    /// reading from it never publishes part of a bar again.
+    pub fn restart_offset(&mut self, partition: i32) -> Option<i64> {
+        let spans = self.spans.entry(partition).or_default();
+        let Some(mut restart) = self.open
+            .values()
+            .filter(|open| open.partition == partition)
+            .map(|open| open.first_offset)
+            .min()
+        else {
+            spans.clear();
+            return None;
+        };
+
+        while let Some(first) = spans
+            .iter()
+            .filter(|&&(first, last)| first < restart && last >= restart)
+            .map(|&(first, _)| first)
+            .min()
+        {
+            restart = first;
+        }
+        // Bars only open after the ones already read, so the restart
+        // offset never moves back past these
+        spans.retain(|&(_, last)| last >= restart);
+        Some(restart)
+    }
+
+    fn close(&mut self, key: (String, usize), open: OpenCandle) -> Candle {
+        self.closed_until.insert(key, open.candle.end);
+        self.spans.entry(open.partition).or_default().push((open.first_offset, open.last_offset));
+        open.candle
+    }
+}
+
+/// Publishes `candles` to the candles topic keyed by instrument, removing
+/// each as it is delivered. Stops at the first that is not, so bars of an
+/// instrument never overtake each other; returns whether all of them were
+/// delivered.
+async fn publish<S: EventSink>(sink: &S, topic: &str, candles: &mut Vec<Candle>) -> bool {
+    let mut delivered = 0;
+    for candle in candles.iter() {
+        let payload = match serde_json::to_string(candle) {
+            Ok(payload) => payload,
+            Err(e) => {
+                // No later attempt would do any better
+                error!(instrument = %candle.instrument, error = %e, "Failed to serialize candle; dropping it");
+                delivered += 1;
+                continue;
+            }
+        };
+        match sink.send(topic, &candle.instrument, &payload, None).await {
+            Ok(delivery) => {
+                info!(
+                    instrument = %candle.instrument,
+                    interval = %candle.interval,
+                    start = candle.start,
+                    partition = delivery.partition,
+                    offset = delivery.offset,
+                    "Published candle"
+                );
+                delivered += 1;
+            }
+            Err(e) => {
+                error!(instrument = %candle.instrument, interval = %candle.interval, start = candle.start, error = %e, "Failed to publish candle");
+                break;
+            }
+        }
+    }
+    candles.drain(..delivered);
+    candles.is_empty()
+}
+
+// Commits `last`'s partition up to the record before its restart offset
+fn commit_partition<O: OrderSource>(
+    source: &O,
+    builder: &mut CandleBuilder,
+    last: &Record,
+    committed: &mut HashMap<i32, i64>,
+) {
+    let offset = builder.restart_offset(last.partition).map_or(last.offset, |restart| restart - 1);
+    if offset < 0 || committed.get(&last.partition).is_some_and(|&done| done >= offset) {
+        return;
+    }
+    match source.commit(&Record { offset, ..last.clone() }) {
+        Ok(()) => {
+            committed.insert(last.partition, offset);
+        }
+        Err(e) => error!(partition = last.partition, offset, error = %e, "Failed to commit message"),
+    }
+}
+
+/// Builds bars from every trade read from `match-events` until the source
+/// closes, publishing each completed bar to the candles topic. Offsets are
+/// committed only up to the first trade of the oldest open bar, so a
+/// restart rebuilds open bars in full; a bar published again is identical
+/// to the first copy. Once the source has caught up, bars of instruments
+/// that stopped trading are completed by the clock. Bars that fail to
+/// publish are sent again every second, ahead of any completed after
+/// them, and partitions they may have come from are committed only once
+/// they are delivered.
+pub async fn run<O: OrderSource, S: EventSink>(mut source: O, sink: S, mut builder: CandleBuilder, config: Config) {
+    let topic = &config.topics.candles;
+    let mut last_read: HashMap<i32, Record> = HashMap::new();
+    let mut committed: HashMap<i32, i64> = HashMap::new();
+    // Completed bars not delivered yet, oldest first
+    let mut undelivered: Vec<Candle> = Vec::new();
+    // Partitions with an undelivered bar are not committed until it is
+    // delivered, so a restart meanwhile rebuilds the bar
+    let mut stalled: HashSet<i32> = HashSet::new();
+
+    let mut timer = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            _ = timer.tick() => {
+                // No lag known yet, e.g. before the first statistics, may
+                // still mean a backlog
+                let lag = source.lag();
+                if !lag.is_empty() && lag.values().all(|&lag| lag <= 0) {
+                    let completed = builder.close_due(Utc::now().timestamp() - CLOSE_DELAY_SECS);
+                    if !completed.is_empty() {
+                        // Bars closed by the clock may hold trades of any
+                        // partition read
+                        stalled.extend(last_read.keys());
+                        undelivered.extend(completed);
+                    }
+                }
+                if undelivered.is_empty() {
+                    continue;
+                }
+                if publish(&sink, topic, &mut undelivered).await {
+                    stalled.clear();
+                }
+                for (partition, last) in &last_read {
+                    if !stalled.contains(partition) {
+                        commit_partition(&source, &mut builder, last, &mut committed);
+                    }
+                }
+            }
+            record = source.next() => {
+                let record = match record {
+                    Some(Ok(record)) => record,
+                    Some(Err(e)) => {
+                        error!(error = %e, "Transport error on match-events");
+                        continue;
+                    }
+                    None => break,
+                };
+
+                let trade = match record.payload.as_deref().map(serde_json::from_str::<MatchEvent>) {
+                    Some(Ok(trade)) => Some(trade),
+                    Some(Err(e)) => {
+                        warn!(partition = record.partition, offset = record.offset, error = %e, "Failed to parse match event JSON");
+                        None
+                    }
+                    None => {
+                        warn!(partition = record.partition, offset = record.offset, "Empty or invalid message payload");
+                        None
+                    }
+                };
+
+                if let Some(trade) = trade {
+                    let completed = builder.add_trade(&trade, record.partition, record.offset);
+                    if !completed.is_empty() {
+                        stalled.insert(record.partition);
+                        undelivered.extend(completed);
+                        if publish(&sink, topic, &mut undelivered).await {
+                            stalled.clear();
+                        }
+                    }
+                }
+
+                if stalled.contains(&record.partition) {
+                    warn!(partition = record.partition, offset = record.offset, "Not committing after failed publishes");
+                } else {
+                    commit_partition(&source, &mut builder, &record, &mut committed);
+                }
+                last_read.insert(record.partition, record);
+            }
+        }
+    }
+}
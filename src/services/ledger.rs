@@ -0,0 +1,490 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::Context;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{Days, NaiveDate, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{Serialize, Deserialize};
+use tracing::{error, info, warn};
+use crate::services::http::{self, ApiError};
+use crate::transport::OrderSource;
+use crate::types::match_event::MatchEvent;
+use crate::types::position::{Balance, Position};
+use crate::utils::backoff::Backoff;
+use crate::utils::instrument_registry::InstrumentRegistry;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS trades (
+        id TEXT PRIMARY KEY,
+        instrument TEXT NOT NULL,
+        buyer_account TEXT,
+        seller_account TEXT,
+        price REAL NOT NULL,
+        quantity INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        aggressor_side TEXT,
+        buyer_fee REAL NOT NULL DEFAULT 0,
+        seller_fee REAL NOT NULL DEFAULT 0
+    );
+    CREATE INDEX IF NOT EXISTS trades_timestamp ON trades (timestamp);
+    CREATE TABLE IF NOT EXISTS positions (
+        account TEXT NOT NULL,
+        instrument TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        quantity INTEGER NOT NULL,
+        average_price REAL NOT NULL,
+        realized_pnl REAL NOT NULL,
+        fees REAL NOT NULL DEFAULT 0,
+        PRIMARY KEY (account, instrument)
+    );
+    CREATE TABLE IF NOT EXISTS balances (
+        account TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        cash REAL NOT NULL,
+        PRIMARY KEY (account, currency)
+    );
+    CREATE TABLE IF NOT EXISTS last_prices (
+        instrument TEXT PRIMARY KEY,
+        price REAL NOT NULL,
+        timestamp INTEGER NOT NULL
+    );
+";
+
+// Columns added since the tables were first created, added to older
+// databases on open
+const ADDED_COLUMNS: [(&str, &str, &str); 4] = [
+    ("trades", "aggressor_side", "TEXT"),
+    ("trades", "buyer_fee", "REAL NOT NULL DEFAULT 0"),
+    ("trades", "seller_fee", "REAL NOT NULL DEFAULT 0"),
+    ("positions", "fees", "REAL NOT NULL DEFAULT 0"),
+];
+
+// ISO 4217 code for "no currency", for trades in instruments missing from
+// the ledger's registry
+const UNKNOWN_CURRENCY: &str = "XXX";
+
+// Bounds of the backoff between attempts to book a trade
+const BOOK_RETRY_MIN: Duration = Duration::from_millis(100);
+const BOOK_RETRY_MAX: Duration = Duration::from_secs(10);
+
+/// One account's holding in one instrument at the end of a day, with that
+/// day's activity.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatementLine {
+    pub account: String,
+    pub instrument: String,
+    pub currency: String,
+    pub bought: u64,
+    pub sold: u64,
+    pub quantity: i64,
+    pub average_price: f64,
+    pub last_price: Option<f64>,
+    pub realized_pnl: f64,
+    pub unrealized_pnl: f64,
+    pub fees: f64,
+}
+
+/// Every account's positions and balances as of the end of `date`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Statement {
+    pub date: NaiveDate,
+    pub positions: Vec<StatementLine>,
+    pub balances: Vec<Balance>,
+}
+
+/// Positions, cash balances and booked trades of every account, kept in
+/// SQLite so they survive restarts.
+pub struct Ledger {
+    connection: Connection,
+}
+
+impl Ledger {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path).with_context(|| format!("opening ledger {}", path.display()))?;
+        Self::with_connection(connection)
+    }
+
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> anyhow::Result<Self> {
+        connection.execute_batch(SCHEMA).context("creating ledger tables")?;
+        for (table, column, definition) in ADDED_COLUMNS {
+            let exists = connection.prepare(&format!("SELECT {} FROM {} LIMIT 0", column, table)).is_ok();
+            if !exists {
+                connection
+                    .execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+                    .with_context(|| format!("adding {}.{}", table, column))?;
+            }
+        }
+        Ok(Self { connection })
+    }
+
+    /// Books a trade to the buyer's and the seller's account: the position
+    /// moves by the traded quantity and cash by its value and the side's
+    /// fee in `currency`. Returns `false` for a trade that was already
+    /// booked, so replayed match events are harmless.
+    pub fn apply_trade(&mut self, trade: &MatchEvent, currency: &str) -> anyhow::Result<bool> {
+        let transaction = self.connection.transaction()?;
+        let inserted = transaction.execute(
+            "INSERT OR IGNORE INTO trades
+                 (id, instrument, buyer_account, seller_account, price, quantity, timestamp, aggressor_side, buyer_fee, seller_fee)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                trade.id,
+                trade.instrument,
+                trade.buyer_account,
+                trade.seller_account,
+                trade.price,
+                trade.quantity,
+                trade.timestamp,
+                trade.aggressor_side,
+                trade.buyer_fee,
+                trade.seller_fee
+            ],
+        )?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+
+        transaction.execute(
+            "INSERT INTO last_prices (instrument, price, timestamp) VALUES (?1, ?2, ?3)
+             ON CONFLICT (instrument) DO UPDATE SET price = excluded.price, timestamp = excluded.timestamp",
+            params![trade.instrument, trade.price, trade.timestamp],
+        )?;
+
+        let quantity = trade.quantity as i64;
+        let value = trade.price * trade.quantity as f64;
+        let sides = [
+            (&trade.buyer_account, quantity, -value, trade.buyer_fee),
+            (&trade.seller_account, -quantity, value, trade.seller_fee),
+        ];
+        for (account, quantity, cash, fee) in sides {
+            let Some(account) = account else {
+                continue;
+            };
+
+            let mut position = load_position(&transaction, account, &trade.instrument)?
+                .unwrap_or_else(|| Position::new(account.clone(), trade.instrument.clone(), currency.to_string()));
+            position.fill(quantity, trade.price);
+            position.fees += fee;
+            transaction.execute(
+                "INSERT INTO positions (account, instrument, currency, quantity, average_price, realized_pnl, fees)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT (account, instrument) DO UPDATE SET
+                     quantity = excluded.quantity,
+                     average_price = excluded.average_price,
+                     realized_pnl = excluded.realized_pnl,
+                     fees = excluded.fees",
+                params![
+                    position.account,
+                    position.instrument,
+                    position.currency,
+                    position.quantity,
+                    position.average_price,
+                    position.realized_pnl,
+                    position.fees
+                ],
+            )?;
+            transaction.execute(
+                "INSERT INTO balances (account, currency, cash) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (account, currency) DO UPDATE SET cash = cash + excluded.cash",
+                params![account, position.currency, cash - fee],
+            )?;
+        }
+
+        transaction.commit()?;
+        Ok(true)
+    }
+
+    /// An account's positions, valued at the last trade price.
+    pub fn positions(&self, account: &str) -> anyhow::Result<Vec<Position>> {
+        self.query_positions(Some(account))
+    }
+
+    pub fn balances(&self, account: &str) -> anyhow::Result<Vec<Balance>> {
+        self.query_balances(Some(account))
+    }
+
+    /// Every account's positions and balances as of the end of `date`
+    /// (UTC), with the quantities each account bought and sold that day.
+    /// Built by booking again, in the order they were booked, the trades
+    /// stamped before the end of the day, and valued at the last of them in
+    /// each instrument.
+    pub fn statement(&self, date: NaiveDate) -> anyhow::Result<Statement> {
+        let start = date.and_hms_opt(0, 0, 0).expect("midnight exists").and_utc().timestamp();
+        let end = start + 24 * 60 * 60;
+
+        // Positions keep the currency they were first booked in
+        let mut query = self.connection.prepare("SELECT account, instrument, currency FROM positions")?;
+        let currencies = query
+            .query_map([], |row| Ok(((row.get::<_, String>(0)?, row.get::<_, String>(1)?), row.get::<_, String>(2)?)))?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        let mut positions: BTreeMap<(String, String), Position> = BTreeMap::new();
+        let mut activity: HashMap<(String, String), (u64, u64)> = HashMap::new();
+        let mut balances: BTreeMap<(String, String), f64> = BTreeMap::new();
+        let mut last_prices: HashMap<String, f64> = HashMap::new();
+
+        let mut query = self.connection.prepare(
+            "SELECT instrument, buyer_account, seller_account, price, quantity, timestamp, buyer_fee, seller_fee
+             FROM trades WHERE timestamp < ?1 ORDER BY rowid",
+        )?;
+        let mut rows = query.query(params![end])?;
+        while let Some(row) = rows.next()? {
+            let instrument: String = row.get(0)?;
+            let price: f64 = row.get(3)?;
+            let quantity: i64 = row.get(4)?;
+            let timestamp: i64 = row.get(5)?;
+            last_prices.insert(instrument.clone(), price);
+
+            let sides = [
+                (row.get::<_, Option<String>>(1)?, quantity, -price * quantity as f64, row.get::<_, f64>(6)?),
+                (row.get::<_, Option<String>>(2)?, -quantity, price * quantity as f64, row.get::<_, f64>(7)?),
+            ];
+            for (account, quantity, cash, fee) in sides {
+                let Some(account) = account else {
+                    continue;
+                };
+                let key = (account.clone(), instrument.clone());
+                let currency = currencies.get(&key).cloned().unwrap_or_else(|| UNKNOWN_CURRENCY.to_string());
+                if timestamp >= start {
+                    let (bought, sold) = activity.entry(key.clone()).or_default();
+                    if quantity > 0 {
+                        *bought += quantity as u64;
+                    } else {
+                        *sold += quantity.unsigned_abs();
+                    }
+                }
+                let position = positions
+                    .entry(key)
+                    .or_insert_with(|| Position::new(account.clone(), instrument.clone(), currency.clone()));
+                position.fill(quantity, price);
+                position.fees += fee;
+                *balances.entry((account, currency)).or_default() += cash - fee;
+            }
+        }
+
+        let positions = positions
+            .into_iter()
+            .map(|(key, mut position)| {
+                position.mark(last_prices.get(&position.instrument).copied());
+                let (bought, sold) = activity.get(&key).copied().unwrap_or_default();
+                StatementLine {
+                    account: position.account,
+                    instrument: position.instrument,
+                    currency: position.currency,
+                    bought,
+                    sold,
+                    quantity: position.quantity,
+                    average_price: position.average_price,
+                    last_price: position.last_price,
+                    realized_pnl: position.realized_pnl,
+                    unrealized_pnl: position.unrealized_pnl,
+                    fees: position.fees,
+                }
+            })
+            .collect();
+        let balances = balances
+            .into_iter()
+            .map(|((account, currency), cash)| Balance { account, currency, cash })
+            .collect();
+
+        Ok(Statement { date, positions, balances })
+    }
+
+    /// Writes the statement for `date` to `positions-<date>.csv` and
+    /// `balances-<date>.csv` in `dir`, returning the files written.
+    pub fn export_statement(&self, date: NaiveDate, dir: impl AsRef<Path>) -> anyhow::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        let statement = self.statement(date)?;
+
+        let positions = dir.join(format!("positions-{}.csv", date));
+        write_csv(&positions, &statement.positions)?;
+        let balances = dir.join(format!("balances-{}.csv", date));
+        write_csv(&balances, &statement.balances)?;
+        Ok(vec![positions, balances])
+    }
+
+    fn query_positions(&self, account: Option<&str>) -> anyhow::Result<Vec<Position>> {
+        let mut query = self.connection.prepare(
+            "SELECT p.account, p.instrument, p.currency, p.quantity, p.average_price, p.realized_pnl, p.fees, l.price
+             FROM positions p LEFT JOIN last_prices l ON l.instrument = p.instrument
+             WHERE ?1 IS NULL OR p.account = ?1
+             ORDER BY p.account, p.instrument",
+        )?;
+        let positions = query
+            .query_map(params![account], |row| {
+                let mut position = Position::new(row.get(0)?, row.get(1)?, row.get(2)?);
+                position.quantity = row.get(3)?;
+                position.average_price = row.get(4)?;
+                position.realized_pnl = row.get(5)?;
+                position.fees = row.get(6)?;
+                position.mark(row.get(7)?);
+                Ok(position)
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(positions)
+    }
+
+    fn query_balances(&self, account: Option<&str>) -> anyhow::Result<Vec<Balance>> {
+        let mut query = self.connection.prepare(
+            "SELECT account, currency, cash FROM balances
+             WHERE ?1 IS NULL OR account = ?1
+             ORDER BY account, currency",
+        )?;
+        let balances = query
+            .query_map(params![account], |row| {
+                Ok(Balance { account: row.get(0)?, currency: row.get(1)?, cash: row.get(2)? })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(balances)
+    }
+}
+
+fn load_position(transaction: &Transaction, account: &str, instrument: &str) -> rusqlite::Result<Option<Position>> {
+    transaction
+        .query_row(
+            "SELECT currency, quantity, average_price, realized_pnl, fees FROM positions WHERE account = ?1 AND instrument = ?2",
+            params![account, instrument],
+            |row| {
+                let mut position = Position::new(account.to_string(), instrument.to_string(), row.get(0)?);
+                position.quantity = row.get(1)?;
+                position.average_price = row.get(2)?;
+                position.realized_pnl = row.get(3)?;
+                position.fees = row.get(4)?;
+                Ok(position)
+            },
+        )
+        .optional()
+}
+
+fn write_csv<T: Serialize>(path: &Path, rows: &[T]) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_path(path).with_context(|| format!("creating {}", path.display()))?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// A ledger shared between the trade consumer and the query API.
+pub type SharedLedger = Arc<Mutex<Ledger>>;
+
+/// Books every trade read from `match-events` until the source closes,
+/// committing each once it is stored; a booking that fails is tried again,
+/// with a growing backoff, until it succeeds. Cash moves in the currency of
+/// the instrument's definition in `registry`.
+pub async fn run<O: OrderSource>(mut source: O, ledger: SharedLedger, registry: InstrumentRegistry) {
+    while let Some(record) = source.next().await {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                error!(error = %e, "Transport error on match-events");
+                continue;
+            }
+        };
+
+        let Some(payload) = &record.payload else {
+            warn!(partition = record.partition, offset = record.offset, "Empty or invalid message payload");
+            continue;
+        };
+
+        let trade = match serde_json::from_str::<MatchEvent>(payload) {
+            Ok(trade) => trade,
+            Err(e) => {
+                warn!(partition = record.partition, offset = record.offset, error = %e, "Failed to parse match event JSON");
+                continue;
+            }
+        };
+
+        if trade.buyer_account.is_none() || trade.seller_account.is_none() {
+            warn!(trade_id = %trade.id, "Trade without an account on both sides; booking the known side only");
+        }
+        let currency = match registry.get(&trade.instrument) {
+            Some(instrument) => instrument.currency.as_str(),
+            None => {
+                warn!(instrument = %trade.instrument, "Unknown instrument; booking cash in {}", UNKNOWN_CURRENCY);
+                UNKNOWN_CURRENCY
+            }
+        };
+
+        // Commits cover every earlier offset of the partition, so nothing
+        // is read past a trade until it is booked
+        let mut backoff = Backoff::new(BOOK_RETRY_MIN, BOOK_RETRY_MAX);
+        let booked = loop {
+            let booked = ledger.lock().unwrap().apply_trade(&trade, currency);
+            match booked {
+                Ok(booked) => break booked,
+                Err(e) => {
+                    error!(trade_id = %trade.id, error = %e, backoff_ms = backoff.delay().as_millis() as u64, "Failed to book trade; retrying");
+                    backoff.wait().await;
+                }
+            }
+        };
+        if booked {
+            info!(trade_id = %trade.id, instrument = %trade.instrument, offset = record.offset, "Trade booked");
+        } else {
+            info!(trade_id = %trade.id, offset = record.offset, "Trade already booked");
+        }
+
+        if let Err(e) = source.commit(&record) {
+            error!(partition = record.partition, offset = record.offset, error = %e, "Failed to commit message");
+        }
+    }
+}
+
+/// Exports the previous day's statement to `dir` every midnight (UTC),
+/// until the process exits.
+pub async fn export_daily(ledger: SharedLedger, dir: PathBuf) {
+    loop {
+        let now = Utc::now();
+        let today = now.date_naive();
+        let midnight = (today + Days::new(1)).and_hms_opt(0, 0, 0).expect("midnight exists").and_utc();
+        tokio::time::sleep((midnight - now).to_std().unwrap_or_default()).await;
+
+        let exported = ledger.lock().unwrap().export_statement(today, &dir);
+        match exported {
+            Ok(files) => info!(date = %today, files = ?files, "Exported statement"),
+            Err(e) => error!(date = %today, error = %e, "Failed to export statement"),
+        }
+    }
+}
+
+async fn positions(State(ledger): State<SharedLedger>, UrlPath(account): UrlPath<String>) -> Result<Json<Vec<Position>>, ApiError> {
+    Ok(Json(ledger.lock().unwrap().positions(&account)?))
+}
+
+async fn balances(State(ledger): State<SharedLedger>, UrlPath(account): UrlPath<String>) -> Result<Json<Vec<Balance>>, ApiError> {
+    Ok(Json(ledger.lock().unwrap().balances(&account)?))
+}
+
+async fn statement(State(ledger): State<SharedLedger>, UrlPath(date): UrlPath<String>) -> Result<Json<Statement>, ApiError> {
+    let date = date
+        .parse::<NaiveDate>()
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, format!("invalid date {}: {}", date, e)))?;
+    Ok(Json(ledger.lock().unwrap().statement(date)?))
+}
+
+/// The ledger's query API.
+pub fn router(ledger: SharedLedger) -> Router {
+    Router::new()
+        .route("/accounts/{account}/positions", get(positions))
+        .route("/accounts/{account}/balances", get(balances))
+        .route("/statements/{date}", get(statement))
+        .with_state(ledger)
+}
+
+/// Serves the query API on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, ledger: SharedLedger) -> anyhow::Result<()> {
+    http::serve(addr, router(ledger)).await
+}
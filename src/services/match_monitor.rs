@@ -0,0 +1,39 @@
+use tracing::{error, warn};
+use crate::transport::{OrderSource, Record};
+use crate::types::match_event::MatchEvent;
+
+/// Reads `match-events` until the source closes, handing every trade and the
+/// record it came from to `on_trade`.
+pub async fn run<O, F>(mut source: O, mut on_trade: F)
+where
+    O: OrderSource,
+    F: FnMut(MatchEvent, &Record),
+{
+    while let Some(record) = source.next().await {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                error!(error = %e, "Transport error on match-events");
+                continue;
+            }
+        };
+
+        let Some(payload) = &record.payload else {
+            warn!(partition = record.partition, offset = record.offset, "Empty or invalid message payload");
+            continue;
+        };
+
+        match serde_json::from_str::<MatchEvent>(payload) {
+            Ok(match_event) => on_trade(match_event, &record),
+            Err(e) => {
+                warn!(
+                    partition = record.partition,
+                    offset = record.offset,
+                    error = %e,
+                    payload = %payload,
+                    "Failed to parse match event JSON"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,815 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use futures_util::FutureExt;
+use futures_util::future::join_all;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::field::Empty;
+use tracing::{debug, error, info, info_span, warn, Span};
+use crate::services::metrics::EngineMetrics;
+use crate::transport::{Assignment, EventSink, OrderSource, PartitionedSource, Record, SnapshotStore};
+use crate::types::control_message::ControlMessage;
+use crate::types::engine_event::EngineEvent;
+use crate::types::instrument_state::InstrumentHandover;
+use crate::types::mass_cancel::{MassCancelFilter, MassCancelReport};
+use crate::types::order::Order;
+use crate::types::order_request::OrderRequest;
+use crate::utils::backoff::Backoff;
+use crate::utils::config::{Config, TopicConfig};
+use crate::utils::fee_schedule::FeeSchedule;
+use crate::utils::instrument_registry::InstrumentRegistry;
+use crate::utils::logging::TRACE_ID_HEADER;
+use crate::utils::matching_engine::MatchingEngine;
+use crate::utils::routing::RoutingTable;
+use crate::utils::session_calendar::SessionCalendar;
+use crate::utils::snapshot::EngineSnapshot;
+
+// Bounds of the backoff between attempts to deliver failed events
+const PUBLISH_RETRY_MIN: Duration = Duration::from_millis(100);
+const PUBLISH_RETRY_MAX: Duration = Duration::from_secs(10);
+
+/// An engine event serialized and addressed, waiting to be published.
+#[derive(Clone)]
+struct Outgoing {
+    topic: String,
+    instrument: String,
+    payload: String,
+    /// Target partition; `None` places the message by key
+    target_partition: Option<i32>,
+    /// Orders partition of the engine that produced the event
+    partition: i32,
+    trace_id: Option<String>,
+    trade: bool,
+    order_timestamp: Option<i64>,
+}
+
+// Route trades to match-events, market data (phases, session states and
+// indicative prices) to market-data and order state
+// changes to order-events, all on the same partition as the order.
+// Volatility interruptions go to the single control-plane partition, and
+// orders and requests for migrated instruments back to orders on their new
+// partition.
+// Trades are charged fees from `fees` on the way out. `order_timestamp` is
+// the timestamp of the order that caused the events, if any, for the order
+// to trade latency, and `trace_id` is carried over from the message that
+// caused them.
+fn route_events(
+    topics: &TopicConfig,
+    metrics: &EngineMetrics,
+    fees: &FeeSchedule,
+    events: Vec<EngineEvent>,
+    partition: i32,
+    order_timestamp: Option<i64>,
+    trace_id: Option<&str>,
+) -> Vec<Outgoing> {
+    let mut outgoing = Vec::with_capacity(events.len());
+    for mut event in events {
+        match &mut event {
+            EngineEvent::Rejected { instrument, reason, .. } | EngineEvent::RequestRejected { instrument, reason, .. } => {
+                metrics.record_reject(partition, instrument, reason)
+            }
+            EngineEvent::Trade(trade) => fees.apply(trade),
+            _ => {}
+        }
+
+        let (topic, target_partition, serialized) = match &event {
+            EngineEvent::Trade(match_event) => (&topics.match_events, Some(partition), serde_json::to_string(match_event)),
+            EngineEvent::PhaseChanged { .. } | EngineEvent::SessionState { .. } | EngineEvent::IndicativePrice { .. } => {
+                (&topics.market_data, Some(partition), serde_json::to_string(&event))
+            }
+            EngineEvent::VolatilityInterruption { .. } => (&topics.control_plane, None, serde_json::to_string(&event)),
+            EngineEvent::Forwarded { order, partition: target } => {
+                (&topics.orders, Some(*target), serde_json::to_string(order))
+            }
+            EngineEvent::ForwardedRequest { request, partition: target } => {
+                (&topics.orders, Some(*target), serde_json::to_string(request))
+            }
+            _ => (&topics.order_events, Some(partition), serde_json::to_string(&event)),
+        };
+
+        match serialized {
+            Ok(payload) => outgoing.push(Outgoing {
+                topic: topic.clone(),
+                instrument: event.instrument().to_string(),
+                payload,
+                target_partition,
+                partition,
+                trace_id: trace_id.map(str::to_string),
+                trade: matches!(event, EngineEvent::Trade(_)),
+                order_timestamp,
+            }),
+            Err(e) => {
+                metrics.publish_failures.with_label_values(&[partition.to_string().as_str(), event.instrument()]).inc();
+                error!(instrument = event.instrument(), error = %e, "Failed to serialize engine event");
+            }
+        }
+    }
+    outgoing
+}
+
+/// Publishes `outgoing` without waiting on each message in turn. Sends are
+/// started in order, so messages for a partition reach the producer queue
+/// in order, and then awaited together. Returns the messages that were not
+/// delivered.
+async fn publish_all<S: EventSink>(sink: &S, metrics: &EngineMetrics, outgoing: &[Outgoing]) -> Vec<Outgoing> {
+    let headers: Vec<Vec<(&str, &str)>> = outgoing
+        .iter()
+        .map(|message| message.trace_id.as_deref().map(|trace_id| (TRACE_ID_HEADER, trace_id)).into_iter().collect())
+        .collect();
+    let deliveries = join_all(outgoing.iter().zip(&headers).map(|(message, headers)| {
+        sink.send_with_headers(&message.topic, &message.instrument, &message.payload, message.target_partition, headers)
+    }))
+    .await;
+
+    let mut failed = Vec::new();
+    for (message, delivery) in outgoing.iter().zip(deliveries) {
+        let labels = [message.partition.to_string(), message.instrument.clone()];
+        match delivery {
+            Ok(delivery) => {
+                if message.trade {
+                    metrics.trades.with_label_values(&labels).inc();
+                    if let Some(order_timestamp) = message.order_timestamp {
+                        let latency = seconds_since_epoch() - order_timestamp as f64;
+                        metrics.order_to_trade_latency.with_label_values(&labels).observe(latency.max(0.0));
+                    }
+                }
+                info!(
+                    trace_id = message.trace_id.as_deref(),
+                    topic = %message.topic,
+                    instrument = %message.instrument,
+                    partition = delivery.partition,
+                    offset = delivery.offset,
+                    payload = %message.payload,
+                    "Published event"
+                );
+            }
+            Err(e) => {
+                metrics.publish_failures.with_label_values(&labels).inc();
+                failed.push(message.clone());
+                error!(
+                    trace_id = message.trace_id.as_deref(),
+                    topic = %message.topic,
+                    instrument = %message.instrument,
+                    error = %e,
+                    "Failed to publish event"
+                );
+            }
+        }
+    }
+    failed
+}
+
+/// Publishes `outgoing`, sending whatever was not delivered again, after a
+/// backoff of up to `PUBLISH_RETRY_MAX`, until all of it is or the
+/// partition is released. Nothing else happens on the partition meanwhile,
+/// so its offsets are never committed past undelivered events. Retried
+/// events land after the rest of their batch. Returns whether everything
+/// was delivered.
+async fn publish_until_delivered<S: EventSink>(
+    sink: &S,
+    metrics: &EngineMetrics,
+    partition: i32,
+    outgoing: &[Outgoing],
+    released: &mut watch::Receiver<bool>,
+) -> bool {
+    let mut pending = publish_all(sink, metrics, outgoing).await;
+    let mut backoff = Backoff::new(PUBLISH_RETRY_MIN, PUBLISH_RETRY_MAX);
+    while !pending.is_empty() {
+        if *released.borrow() {
+            return false;
+        }
+        warn!(partition, pending = pending.len(), backoff_ms = backoff.delay().as_millis() as u64, "Retrying undelivered events");
+        tokio::select! {
+            _ = backoff.wait() => {}
+            _ = released.changed() => return false,
+        }
+        pending = publish_all(sink, metrics, &pending).await;
+    }
+    true
+}
+
+/// The partition's answer to a mass cancel, for the control-plane topic
+/// after the cancellations themselves.
+fn mass_cancel_report(
+    partition: i32,
+    filter: &MassCancelFilter,
+    events: &[EngineEvent],
+    topics: &TopicConfig,
+    trace_id: Option<&str>,
+) -> Option<Outgoing> {
+    let report = MassCancelReport::new(partition, filter, events, seconds_since_epoch() as i64);
+    info!(partition, cancelled = report.cancelled, "Mass cancel applied");
+
+    match serde_json::to_string(&report) {
+        Ok(payload) => Some(Outgoing {
+            topic: topics.control_plane.clone(),
+            instrument: "control".to_string(),
+            payload,
+            target_partition: None,
+            partition,
+            trace_id: trace_id.map(str::to_string),
+            trade: false,
+            order_timestamp: None,
+        }),
+        Err(e) => {
+            error!(partition, error = %e, "Failed to serialize mass cancel report");
+            None
+        }
+    }
+}
+
+fn seconds_since_epoch() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64()
+}
+
+fn record_books(metrics: &EngineMetrics, partition: i32, matching_engine: &MatchingEngine) {
+    for order_book in matching_engine.order_books.values() {
+        metrics.record_book(partition, order_book);
+    }
+}
+
+/// A control-plane command on its way to a partition task.
+struct ControlCommand {
+    command: Arc<ControlMessage>,
+    trace_id: Option<String>,
+}
+
+/// What every partition task of an engine instance shares.
+struct Shared<S, T> {
+    sink: S,
+    snapshots: T,
+    fees: FeeSchedule,
+    calendar: SessionCalendar,
+    config: Config,
+    metrics: EngineMetrics,
+}
+
+/// The task owning one assigned orders partition.
+struct PartitionTask {
+    commands: mpsc::UnboundedSender<ControlCommand>,
+    release: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+}
+
+impl PartitionTask {
+    /// Lets the task finish its batch, save its snapshot and commit, and
+    /// waits for it. A task still retrying undelivered events gives up
+    /// instead, leaving its batch to be replayed by the next owner.
+    async fn stop(self, partition: i32) {
+        let _ = self.release.send(true);
+        drop(self.commands);
+        if let Err(e) = self.handle.await {
+            error!(partition, error = %e, "Partition task failed");
+        }
+    }
+}
+
+/// Runs the matching engine. Every assigned orders partition gets a task
+/// with a `MatchingEngine` of its own, restored from the partition's
+/// snapshot when there is one. A revoked partition's task finishes its
+/// batch, saves a snapshot, commits and drops its engine before the
+/// revocation completes, so no two instances ever own the same book; if
+/// its events cannot be delivered it drops the engine without committing.
+/// Control-plane commands are forwarded to the tasks of the partitions
+/// `routes` has for the instruments they concern, and `calendar` moves
+/// instruments through their trading sessions. Returns when the order
+/// source closes, after releasing every partition still owned.
+#[allow(clippy::too_many_arguments)]
+pub async fn run<P, C, S, T>(
+    mut orders: P,
+    mut control: C,
+    sink: S,
+    snapshots: T,
+    mut registry: InstrumentRegistry,
+    fees: FeeSchedule,
+    calendar: SessionCalendar,
+    routes: RoutingTable,
+    config: Config,
+    metrics: EngineMetrics,
+) where
+    P: PartitionedSource,
+    C: OrderSource,
+    S: EventSink + Send + Sync + 'static,
+    T: SnapshotStore + Send + Sync + 'static,
+{
+    let shared = Arc::new(Shared { sink, snapshots, fees, calendar, config, metrics });
+    let mut tasks: HashMap<i32, PartitionTask> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            // Ownership changes first, so commands reach the current owners
+            biased;
+
+            assignment = orders.next_assignment() => match assignment {
+                Some(Assignment::Assigned { partition, source }) => {
+                    info!(partition, "Partition assigned");
+                    if let Some(task) = tasks.remove(&partition) {
+                        task.stop(partition).await;
+                    }
+
+                    let (commands, receiver) = mpsc::unbounded_channel();
+                    let (release, released) = watch::channel(false);
+                    let handle = tokio::spawn(run_partition(partition, source, receiver, released, shared.clone(), registry.clone()));
+                    tasks.insert(partition, PartitionTask { commands, release, handle });
+                }
+                Some(Assignment::Revoked { partition, done }) => {
+                    info!(partition, "Partition revoked");
+                    if let Some(task) = tasks.remove(&partition) {
+                        task.stop(partition).await;
+                    }
+                    let _ = done.send(());
+                }
+                None => break,
+            },
+            Some(record) = control.next() => {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) => {
+                        error!(error = %e, "Transport error on control-plane");
+                        continue;
+                    }
+                };
+
+                let trace_id = record.header(TRACE_ID_HEADER);
+                let span = info_span!("control", trace_id, offset = record.offset);
+                let Some(command) = span.in_scope(|| parse_control_record(&record, &mut registry, &routes)) else {
+                    continue;
+                };
+
+                // Instrument-scoped commands go to the partition that owns
+                // the instrument, the rest to every partition. Partitions
+                // owned by other instances are theirs to apply.
+                let partitions: Vec<i32> = match command.instrument() {
+                    Some(instrument) => vec![routes.partition_for(instrument)],
+                    None => tasks.keys().cloned().collect(),
+                };
+
+                let command = Arc::new(command);
+                for partition in partitions {
+                    if let Some(task) = tasks.get(&partition) {
+                        let _ = task.commands.send(ControlCommand {
+                            command: command.clone(),
+                            trace_id: trace_id.map(str::to_string),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    for (partition, task) in tasks.drain() {
+        task.stop(partition).await;
+    }
+}
+
+fn parse_control_record(record: &Record, registry: &mut InstrumentRegistry, routes: &RoutingTable) -> Option<ControlMessage> {
+    let Some(payload) = &record.payload else {
+        warn!("Invalid control-plane payload");
+        return None;
+    };
+
+    let command = match serde_json::from_str::<ControlMessage>(payload) {
+        Ok(command) => command,
+        // Engines publish their own events and reports here too; those
+        // are for operators, not commands
+        Err(_) if serde_json::from_str::<EngineEvent>(payload).is_ok() => return None,
+        Err(_) if serde_json::from_str::<MassCancelReport>(payload).is_ok() => return None,
+        Err(e) => {
+            warn!(error = %e, "Failed to parse control message JSON");
+            return None;
+        }
+    };
+
+    match &command {
+        // Routes are followed here rather than by the partition tasks
+        ControlMessage::RouteInstrument { instrument, partition } => {
+            info!(%instrument, partition, "Instrument rerouted");
+            routes.apply(&command);
+            return None;
+        }
+        ControlMessage::MigrateInstrument { instrument, target_partition } => {
+            let source_partition = routes.partition_for(instrument);
+            if !(0..routes.partition_count()).contains(target_partition) || *target_partition == source_partition {
+                warn!(%instrument, source_partition, target_partition, "Ignoring migration to an invalid partition");
+                return None;
+            }
+        }
+        ControlMessage::MassCancel { filter } if !filter.is_valid() => {
+            warn!(filter = ?filter, "Ignoring mass cancel without a participant, account or session, or with an invalid side");
+            return None;
+        }
+        _ => {}
+    }
+
+    info!(command = ?command, "Applying control message");
+
+    // Engines created later start from the updated registry
+    if let ControlMessage::UpdateInstrument { definition } = &command
+        && let Err(e) = registry.upsert(definition.clone())
+    {
+        warn!(instrument = %definition.symbol, error = %e, "Ignoring invalid instrument definition");
+        return None;
+    }
+
+    Some(command)
+}
+
+/// Processes one partition until its command channel closes. Orders are
+/// taken in batches of whatever is already available, up to
+/// `engine.batch_size`; a batch's events are published together, the
+/// undelivered ones again until they are acknowledged. Offsets are
+/// committed only behind a saved snapshot, taken at most every
+/// `engine.snapshot_interval_secs`, so a new owner restoring that snapshot
+/// replays everything after it. Once `released`, the task stops retrying
+/// and drops its engine without committing what was not delivered.
+async fn run_partition<O, S, T>(
+    partition: i32,
+    mut source: O,
+    mut commands: mpsc::UnboundedReceiver<ControlCommand>,
+    mut released: watch::Receiver<bool>,
+    shared: Arc<Shared<S, T>>,
+    registry: InstrumentRegistry,
+) where
+    O: OrderSource,
+    S: EventSink,
+    T: SnapshotStore,
+{
+    let Shared { sink, snapshots, fees, calendar, config, metrics } = &*shared;
+    let (mut matching_engine, mut last_offset) = restore_engine(partition, snapshots, registry).await;
+    matching_engine.duplicates.window_secs = config.engine.duplicate_window_secs;
+    matching_engine.sessions.timeout_secs = config.engine.session_timeout_secs;
+    matching_engine.expiries.day_close_secs = config.day_close_secs().unwrap_or_default();
+    matching_engine.calendar = calendar.clone();
+    matching_engine.debug_checks = config.engine.check_invariants;
+    // Heartbeats sent while nobody owned the partition went unheard
+    matching_engine.sessions.resume(matching_engine.clock);
+    // The first record read shows whether the snapshot and the committed
+    // offset agree
+    let mut restored_offset = last_offset;
+    let mut last_record: Option<Record> = None;
+    // Whether records were read since the last snapshot
+    let mut uncommitted = false;
+    let mut last_snapshot = Instant::now();
+    let snapshot_interval = Duration::from_secs(config.engine.snapshot_interval_secs);
+    // Set when the partition is released with events still undelivered
+    let mut abandoned = false;
+
+    let mut timer = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            _ = timer.tick() => {
+                // The engine clock only follows record timestamps, so expiries,
+                // session transitions and disconnects of an idle partition
+                // wait for its next record
+                let events = matching_engine.on_timer();
+                let lag = source.lag();
+
+                let outgoing = route_events(&config.topics, metrics, fees, events, partition, None, None);
+                if !publish_until_delivered(sink, metrics, partition, &outgoing, &mut released).await {
+                    abandoned = true;
+                    break;
+                }
+                record_books(metrics, partition, &matching_engine);
+
+                for ((_, partition), lag) in lag {
+                    metrics.consumer_lag.with_label_values(&[partition.to_string()]).set(lag);
+                }
+
+                // An idle partition still gets its last records committed
+                if uncommitted
+                    && last_snapshot.elapsed() >= snapshot_interval
+                    && let Some(record) = &last_record
+                    && save_snapshot(partition, snapshots, &mut matching_engine, last_offset).await
+                {
+                    commit(&source, metrics, record);
+                    uncommitted = false;
+                    last_snapshot = Instant::now();
+                }
+            }
+            command = commands.recv() => {
+                let Some(ControlCommand { command, trace_id }) = command else {
+                    break;
+                };
+                if let ControlMessage::MigrateInstrument { instrument, target_partition } = &*command {
+                    migrate_instrument(&mut matching_engine, partition, instrument, *target_partition, &shared, trace_id.as_deref()).await;
+                    continue;
+                }
+                let events = matching_engine.apply_control(&command);
+                let report = match &*command {
+                    ControlMessage::MassCancel { filter } => mass_cancel_report(partition, filter, &events, &config.topics, trace_id.as_deref()),
+                    _ => None,
+                };
+                let mut outgoing = route_events(&config.topics, metrics, fees, events, partition, None, trace_id.as_deref());
+                outgoing.extend(report);
+                if !publish_until_delivered(sink, metrics, partition, &outgoing, &mut released).await {
+                    abandoned = true;
+                    break;
+                }
+                record_books(metrics, partition, &matching_engine);
+            }
+            record = source.next() => {
+                let mut batch = Vec::with_capacity(config.engine.batch_size);
+                let mut closed = false;
+                let mut next = Some(record);
+
+                // Take whatever else is already available without waiting
+                while let Some(record) = next.take() {
+                    match record {
+                        Some(Ok(record)) => batch.push(record),
+                        Some(Err(e)) => error!(partition, error = %e, "Transport error on orders"),
+                        None => {
+                            closed = true;
+                            break;
+                        }
+                    }
+                    if batch.len() < config.engine.batch_size {
+                        next = source.next().now_or_never();
+                    }
+                }
+
+                let mut outgoing = Vec::new();
+                for record in &batch {
+                    let span = info_span!(
+                        "order",
+                        trace_id = record.header(TRACE_ID_HEADER),
+                        partition,
+                        offset = record.offset,
+                        order_id = Empty,
+                        instrument = Empty,
+                    );
+                    let _entered = span.enter();
+
+                    if let Some(snapshot_offset) = restored_offset.take()
+                        && record.offset > snapshot_offset + 1
+                    {
+                        warn!(snapshot_offset, "Orders between the snapshot and the committed offset are missing from the books");
+                    }
+                    // Already in the books of a snapshot newer than the commit
+                    if last_offset.is_some_and(|offset| record.offset <= offset) {
+                        continue;
+                    }
+
+                    // Expiries due by the time the record was written happen
+                    // before it is processed
+                    if let Some(timestamp) = record.timestamp {
+                        let events = matching_engine.advance_clock(timestamp / 1000);
+                        outgoing.extend(route_events(&config.topics, metrics, fees, events, partition, None, record.header(TRACE_ID_HEADER)));
+                    }
+                    outgoing.extend(process_record(&mut matching_engine, record, &config.topics, metrics, fees));
+                    last_offset = Some(record.offset);
+                }
+
+                // Only fully acknowledged batches are committed
+                if !publish_until_delivered(sink, metrics, partition, &outgoing, &mut released).await {
+                    abandoned = true;
+                    break;
+                }
+
+                // Records arrive in offset order, so the last one covers the
+                // rest of the batch
+                if let Some(record) = batch.last() {
+                    last_record = Some(record.clone());
+                    uncommitted = true;
+                    if last_snapshot.elapsed() >= snapshot_interval
+                        && save_snapshot(partition, snapshots, &mut matching_engine, last_offset).await
+                    {
+                        commit(&source, metrics, record);
+                        uncommitted = false;
+                        last_snapshot = Instant::now();
+                    }
+                }
+
+                if closed {
+                    break;
+                }
+            }
+        }
+    }
+
+    if abandoned {
+        warn!(partition, offset = last_offset, "Released with undelivered events; the next owner replays them from the last snapshot");
+        return;
+    }
+
+    // Hand the partition over: the next owner starts after the last record
+    // read, from the books as they are now
+    if save_snapshot(partition, snapshots, &mut matching_engine, last_offset).await
+        && let Some(record) = &last_record
+        && let Err(e) = source.commit_sync(record)
+    {
+        metrics.commit_failures.with_label_values(&[partition.to_string()]).inc();
+        error!(partition, offset = record.offset, error = %e, "Failed to commit before releasing the partition");
+    }
+}
+
+/// Saves the books as of `offset`. The engine is moved into the snapshot
+/// for serializing and back again, sparing a copy of every book.
+async fn save_snapshot<T: SnapshotStore>(
+    partition: i32,
+    snapshots: &T,
+    matching_engine: &mut MatchingEngine,
+    offset: Option<i64>,
+) -> bool {
+    let snapshot = EngineSnapshot {
+        partition,
+        offset,
+        timestamp: seconds_since_epoch() as i64,
+        engine: std::mem::take(matching_engine),
+    };
+    let payload = serde_json::to_string(&snapshot);
+    *matching_engine = snapshot.engine;
+
+    let saved = match payload {
+        Ok(payload) => snapshots.save(partition, &payload).await,
+        Err(e) => Err(e.into()),
+    };
+    match saved {
+        Ok(()) => {
+            debug!(partition, offset, "Saved snapshot");
+            true
+        }
+        Err(e) => {
+            error!(partition, error = %e, "Failed to save snapshot");
+            false
+        }
+    }
+}
+
+fn commit<O: OrderSource>(source: &O, metrics: &EngineMetrics, record: &Record) {
+    if let Err(e) = source.commit(record) {
+        metrics.commit_failures.with_label_values(&[record.partition.to_string()]).inc();
+        error!(partition = record.partition, offset = record.offset, error = %e, "Failed to commit message");
+    }
+}
+
+/// Moves `instrument` out of this partition's engine. The exported state
+/// is published on the target's orders partition, ahead of the orders
+/// forwarded after it, and only once it is acknowledged are producers told
+/// to route there. If the hand-over cannot be published the instrument
+/// stays here.
+async fn migrate_instrument<S: EventSink, T>(
+    matching_engine: &mut MatchingEngine,
+    partition: i32,
+    instrument: &str,
+    target_partition: i32,
+    shared: &Shared<S, T>,
+    trace_id: Option<&str>,
+) {
+    let Shared { sink, config, metrics, .. } = shared;
+    let state = matching_engine.export_instrument(instrument, target_partition);
+    let handover = InstrumentHandover {
+        instrument: instrument.to_string(),
+        source_partition: partition,
+        state: state.clone(),
+    };
+    let route = ControlMessage::RouteInstrument { instrument: instrument.to_string(), partition: target_partition };
+
+    let message = |topic: &str, target: Option<i32>, payload: serde_json::Result<String>| {
+        payload.map(|payload| Outgoing {
+            topic: topic.to_string(),
+            instrument: instrument.to_string(),
+            payload,
+            target_partition: target,
+            partition,
+            trace_id: trace_id.map(str::to_string),
+            trade: false,
+            order_timestamp: None,
+        })
+    };
+
+    let handed_over = match message(&config.topics.orders, Some(target_partition), serde_json::to_string(&handover)) {
+        Ok(outgoing) => publish_all(sink, metrics, &[outgoing]).await.is_empty(),
+        Err(e) => {
+            error!(instrument, error = %e, "Failed to serialize instrument hand-over");
+            false
+        }
+    };
+    if !handed_over {
+        error!(instrument, target_partition, "Instrument hand-over not delivered; keeping it here");
+        matching_engine.import_instrument(instrument, state);
+        return;
+    }
+    info!(instrument, target_partition, "Instrument handed over");
+    record_books(metrics, partition, matching_engine);
+
+    // Until producers follow the new route, their orders for the instrument
+    // are forwarded from here
+    match message(&config.topics.control_plane, None, serde_json::to_string(&route)) {
+        Ok(outgoing) => {
+            publish_all(sink, metrics, &[outgoing]).await;
+        }
+        Err(e) => error!(instrument, error = %e, "Failed to serialize instrument route"),
+    }
+}
+
+async fn restore_engine<T: SnapshotStore>(
+    partition: i32,
+    snapshots: &T,
+    registry: InstrumentRegistry,
+) -> (MatchingEngine, Option<i64>) {
+    let snapshot = match snapshots.load(partition).await {
+        Ok(Some(payload)) => serde_json::from_str::<EngineSnapshot>(&payload).map_err(anyhow::Error::from),
+        Ok(None) => {
+            info!(partition, "No snapshot; starting with empty books");
+            return (MatchingEngine::with_registry(registry), None);
+        }
+        Err(e) => Err(e),
+    };
+
+    match snapshot {
+        Ok(snapshot) => {
+            info!(
+                partition,
+                offset = snapshot.offset,
+                books = snapshot.engine.order_books.len(),
+                "Restored snapshot"
+            );
+            (snapshot.engine, snapshot.offset)
+        }
+        Err(e) => {
+            error!(partition, error = %e, "Failed to restore snapshot; starting with empty books");
+            (MatchingEngine::with_registry(registry), None)
+        }
+    }
+}
+
+fn process_record(
+    matching_engine: &mut MatchingEngine,
+    record: &Record,
+    topics: &TopicConfig,
+    metrics: &EngineMetrics,
+    fees: &FeeSchedule,
+) -> Vec<Outgoing> {
+    let partition = record.partition;
+
+    let Some(payload) = &record.payload else {
+        warn!("Empty or invalid message payload");
+        return Vec::new();
+    };
+
+    // Parse the order
+    match serde_json::from_str::<Order>(payload) {
+        Ok(order) => {
+            Span::current()
+                .record("order_id", order.id.as_str())
+                .record("instrument", order.instrument.as_str());
+            info!(side = %order.side, price = order.price, quantity = order.quantity, order_type = %order.order_type, "Processing order");
+
+            let instrument = order.instrument.clone();
+            let order_timestamp = order.timestamp;
+            metrics.orders_processed.with_label_values(&[partition.to_string(), instrument.clone()]).inc();
+
+            // Process the order through the matching engine
+            let events = matching_engine.process_order(order);
+            let outgoing = route_events(
+                topics,
+                metrics,
+                fees,
+                events,
+                partition,
+                Some(order_timestamp),
+                record.header(TRACE_ID_HEADER),
+            );
+
+            // Print order book status
+            if let Some(order_book) = matching_engine.order_books.get(&instrument) {
+                metrics.record_book(partition, order_book);
+                let best_bid = order_book.get_best_bid();
+                let best_ask = order_book.get_best_ask();
+                debug!(best_bid = ?best_bid, best_ask = ?best_ask, "Order book");
+            }
+
+            outgoing
+        }
+        Err(e) => {
+            // Cancels and amends share the topic with new orders
+            if let Ok(request) = serde_json::from_str::<OrderRequest>(payload) {
+                let instrument = request.instrument().to_string();
+                Span::current().record("instrument", instrument.as_str());
+                info!(participant = request.participant(), client_order_id = request.client_order_id(), "Processing order request");
+
+                let events = matching_engine.process_request(request);
+                let outgoing = route_events(topics, metrics, fees, events, partition, None, record.header(TRACE_ID_HEADER));
+                if let Some(order_book) = matching_engine.order_books.get(&instrument) {
+                    metrics.record_book(partition, order_book);
+                }
+                return outgoing;
+            }
+
+            // Instruments migrating here arrive with their state ahead of
+            // their orders
+            if let Ok(InstrumentHandover { instrument, source_partition, state }) = serde_json::from_str(payload) {
+                Span::current().record("instrument", instrument.as_str());
+                info!(source_partition, "Importing instrument");
+                matching_engine.import_instrument(&instrument, state);
+                if let Some(order_book) = matching_engine.order_books.get(&instrument) {
+                    metrics.record_book(partition, order_book);
+                }
+            } else {
+                warn!(error = %e, "Failed to parse order JSON");
+            }
+            Vec::new()
+        }
+    }
+}
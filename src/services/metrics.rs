@@ -0,0 +1,118 @@
+use std::net::SocketAddr;
+use axum::Router;
+use axum::http::header::CONTENT_TYPE;
+use axum::routing::get;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use crate::services::http;
+use crate::types::engine_event::RejectReason;
+use crate::types::order_book::OrderBook;
+
+// Order timestamps have one second resolution, so finer buckets would
+// never be hit
+const LATENCY_BUCKETS: [f64; 8] = [0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+/// Prometheus metrics of a matching engine process. Cloning is cheap and
+/// every clone updates the same series.
+#[derive(Clone)]
+pub struct EngineMetrics {
+    registry: Registry,
+    pub orders_processed: IntCounterVec,
+    pub trades: IntCounterVec,
+    pub rejects: IntCounterVec,
+    pub consumer_lag: IntGaugeVec,
+    pub order_to_trade_latency: HistogramVec,
+    pub book_levels: IntGaugeVec,
+    pub book_quantity: IntGaugeVec,
+    pub publish_failures: IntCounterVec,
+    pub commit_failures: IntCounterVec,
+}
+
+impl EngineMetrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("matching_engine".to_string()), None)
+            .expect("valid metrics prefix");
+
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            let counter = IntCounterVec::new(Opts::new(name, help), labels).expect("valid counter");
+            registry.register(Box::new(counter.clone())).expect("unique counter");
+            counter
+        };
+        let gauge = |name: &str, help: &str, labels: &[&str]| {
+            let gauge = IntGaugeVec::new(Opts::new(name, help), labels).expect("valid gauge");
+            registry.register(Box::new(gauge.clone())).expect("unique gauge");
+            gauge
+        };
+
+        let order_to_trade_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "order_to_trade_latency_seconds",
+                "Time from the order timestamp until its trade was published",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["partition", "instrument"],
+        )
+        .expect("valid histogram");
+        registry.register(Box::new(order_to_trade_latency.clone())).expect("unique histogram");
+
+        Self {
+            orders_processed: counter("orders_processed_total", "Orders read and processed", &["partition", "instrument"]),
+            trades: counter("trades_total", "Trades published", &["partition", "instrument"]),
+            rejects: counter("rejects_total", "Orders rejected", &["partition", "instrument", "reason"]),
+            consumer_lag: gauge("consumer_lag", "Orders waiting to be read", &["partition"]),
+            order_to_trade_latency,
+            book_levels: gauge("book_levels", "Price levels resting in the book", &["partition", "instrument", "side"]),
+            book_quantity: gauge("book_quantity", "Quantity resting in the book", &["partition", "instrument", "side"]),
+            publish_failures: counter("publish_failures_total", "Events that could not be published", &["partition", "instrument"]),
+            commit_failures: counter("commit_failures_total", "Failed offset commits", &["partition"]),
+            registry,
+        }
+    }
+
+    pub fn record_reject(&self, partition: i32, instrument: &str, reason: &RejectReason) {
+        // Reasons serialize as their snake_case label
+        let reason = serde_json::to_value(reason)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_default();
+        self.rejects.with_label_values(&[&partition.to_string(), instrument, &reason]).inc();
+    }
+
+    pub fn record_book(&self, partition: i32, order_book: &OrderBook) {
+        let partition = partition.to_string();
+        for (side, levels) in [("bid", &order_book.bids), ("ask", &order_book.asks)] {
+            let labels = [partition.as_str(), order_book.instrument.as_str(), side];
+            let quantity: u64 = levels.values().map(|level| level.total_quantity as u64).sum();
+            self.book_levels.with_label_values(&labels).set(levels.len() as i64);
+            self.book_quantity.with_label_values(&labels).set(quantity as i64);
+        }
+    }
+
+    /// Renders every series in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics encode as text");
+        String::from_utf8(buffer).expect("metrics text is UTF-8")
+    }
+}
+
+impl Default for EngineMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serves `GET /metrics` on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, metrics: EngineMetrics) -> anyhow::Result<()> {
+    let app = Router::new().route(
+        "/metrics",
+        get(move || {
+            let metrics = metrics.clone();
+            async move { ([(CONTENT_TYPE, TextEncoder::new().format_type().to_string())], metrics.encode()) }
+        }),
+    );
+    http::serve(addr, app).await
+}
@@ -0,0 +1,11 @@
+pub mod matching_engine;
+pub mod match_monitor;
+pub mod metrics;
+pub mod producer;
+
+pub mod routing;
+pub mod ledger;
+pub mod candles;
+pub mod trade_store;
+pub mod reconciliation;
+pub mod http;
\ No newline at end of file
@@ -0,0 +1,98 @@
+use tracing::{debug, info, warn};
+use crate::transport::{Delivery, EventSink};
+use crate::types::control_message::ControlMessage;
+use crate::types::order::Order;
+use crate::types::order_request::OrderRequest;
+use crate::utils::config::Config;
+use crate::utils::logging::{new_trace_id, TRACE_ID_HEADER};
+use crate::utils::routing::RoutingTable;
+
+/// Publishes an order to the orders topic on the partition `routes` has
+/// for its instrument, under a new trace id that downstream services log
+/// with.
+pub async fn publish_order<S: EventSink>(
+    sink: &S,
+    order: &Order,
+    routes: &RoutingTable,
+    config: &Config,
+) -> anyhow::Result<Delivery> {
+    let payload = serde_json::to_string(order)?;
+    let (delivery, trace_id) = publish(sink, &order.instrument, &payload, routes, config).await?;
+
+    info!(
+        trace_id = %trace_id,
+        order_id = %order.id,
+        instrument = %order.instrument,
+        partition = delivery.partition,
+        offset = delivery.offset,
+        "Published order"
+    );
+    Ok(delivery)
+}
+
+/// Publishes a cancel or amend request the same way as an order, so it
+/// reaches the partition holding the order it refers to.
+pub async fn publish_request<S: EventSink>(
+    sink: &S,
+    request: &OrderRequest,
+    routes: &RoutingTable,
+    config: &Config,
+) -> anyhow::Result<Delivery> {
+    let payload = serde_json::to_string(request)?;
+    let (delivery, trace_id) = publish(sink, request.instrument(), &payload, routes, config).await?;
+
+    info!(
+        trace_id = %trace_id,
+        participant = request.participant(),
+        client_order_id = request.client_order_id(),
+        instrument = request.instrument(),
+        partition = delivery.partition,
+        offset = delivery.offset,
+        "Published order request"
+    );
+    Ok(delivery)
+}
+
+/// Keeps an order-entry session alive by sending its heartbeat on the
+/// control plane every `config.heartbeat_interval()`. Runs until dropped,
+/// or returns at once when cancel-on-disconnect is off. Orders tagged with
+/// the session are cancelled by the engines once the heartbeats stop.
+pub async fn send_heartbeats<S: EventSink>(sink: &S, session: &str, config: &Config) {
+    let Some(interval) = config.heartbeat_interval() else {
+        return;
+    };
+    let heartbeat = ControlMessage::Heartbeat { session: session.to_string() };
+    let payload = serde_json::to_string(&heartbeat).expect("Failed to serialize heartbeat");
+
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        // A lost heartbeat is covered by the next ones within the timeout
+        match sink.send(&config.topics.control_plane, "control", &payload, None).await {
+            Ok(_) => debug!(session, "Sent heartbeat"),
+            Err(e) => warn!(session, error = %e, "Failed to send heartbeat"),
+        }
+    }
+}
+
+async fn publish<S: EventSink>(
+    sink: &S,
+    instrument: &str,
+    payload: &str,
+    routes: &RoutingTable,
+    config: &Config,
+) -> anyhow::Result<(Delivery, String)> {
+    let partition = routes.partition_for(instrument);
+    let trace_id = new_trace_id();
+
+    let delivery = sink
+        .send_with_headers(
+            &config.topics.orders,
+            instrument,
+            payload,
+            Some(partition),
+            &[(TRACE_ID_HEADER, &trace_id)],
+        )
+        .await?;
+    Ok((delivery, trace_id))
+}
@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use tracing::{error, warn};
+use crate::transport::{OrderSource, Record};
+use crate::types::control_message::ControlMessage;
+use crate::types::engine_event::EngineEvent;
+use crate::types::match_event::MatchEvent;
+use crate::types::order::Order;
+use crate::types::order_request::OrderRequest;
+use crate::utils::config::{Config, TopicConfig};
+use crate::utils::instrument_registry::InstrumentRegistry;
+use crate::utils::matching_engine::MatchingEngine;
+use crate::utils::session_calendar::SessionCalendar;
+
+/// Something the engine published that its inputs do not account for.
+/// Trades are told apart by instrument, buyer and seller order and price;
+/// quantities are the totals of all trades with the same four.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Discrepancy {
+    /// Replaying the orders produced a trade match-events does not have
+    MissingTrade {
+        instrument: String,
+        buyer_order_id: String,
+        seller_order_id: String,
+        price: f64,
+        quantity: u64,
+    },
+    /// match-events has a trade replaying the orders does not produce
+    ExtraTrade {
+        instrument: String,
+        buyer_order_id: String,
+        seller_order_id: String,
+        price: f64,
+        quantity: u64,
+        trade_ids: Vec<String>,
+    },
+    QuantityMismatch {
+        instrument: String,
+        buyer_order_id: String,
+        seller_order_id: String,
+        price: f64,
+        expected: u64,
+        published: u64,
+    },
+    /// Published trades fill an order beyond its original quantity
+    OverFill {
+        order_id: String,
+        instrument: String,
+        original_quantity: u32,
+        filled: u64,
+    },
+}
+
+/// The outcome of reconciling trades from `from` up to `to`, in seconds
+/// since the epoch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReconciliationReport {
+    pub from: i64,
+    pub to: i64,
+    pub orders_replayed: usize,
+    pub trades_expected: usize,
+    pub trades_published: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl ReconciliationReport {
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+type TradeKey = (String, String, String, u64);
+
+// The trades with one key, from one side of the reconciliation
+#[derive(Default)]
+struct TradeTotal {
+    quantity: u64,
+    trade_ids: Vec<String>,
+    in_range: bool,
+}
+
+fn trade_key(trade: &MatchEvent) -> TradeKey {
+    (trade.instrument.clone(), trade.buyer_order_id.clone(), trade.seller_order_id.clone(), trade.price.to_bits())
+}
+
+fn add_trade(totals: &mut HashMap<TradeKey, TradeTotal>, trade: &MatchEvent, in_range: bool) {
+    let total = totals.entry(trade_key(trade)).or_default();
+    total.quantity += trade.quantity as u64;
+    total.trade_ids.push(trade.id.clone());
+    total.in_range |= in_range;
+}
+
+/// A fresh engine set up like the ones that produced the trades. Sessions
+/// are never cancelled for missing heartbeats, which a replay cannot hear
+/// in time.
+pub fn replay_engine(registry: InstrumentRegistry, calendar: SessionCalendar, config: &Config) -> MatchingEngine {
+    let mut matching_engine = MatchingEngine::with_registry(registry);
+    matching_engine.duplicates.window_secs = config.engine.duplicate_window_secs;
+    matching_engine.sessions.timeout_secs = 0;
+    matching_engine.expiries.day_close_secs = config.day_close_secs().unwrap_or_default();
+    matching_engine.calendar = calendar;
+    matching_engine.debug_checks = config.engine.check_invariants;
+    matching_engine
+}
+
+/// Reads `source` until nothing new arrives for `idle`.
+pub async fn read_until_idle<O: OrderSource>(source: &mut O, idle: Duration) -> Vec<Record> {
+    let mut records = Vec::new();
+    while let Ok(Some(record)) = tokio::time::timeout(idle, source.next()).await {
+        match record {
+            Ok(record) => records.push(record),
+            Err(e) => error!(error = %e, "Transport error while reading"),
+        }
+    }
+    records
+}
+
+// Orders and control-plane records in the order an engine could have seen
+// them: each partition in offset order, partitions interleaved by record
+// timestamp
+fn merge_by_timestamp(records: Vec<Record>) -> Vec<Record> {
+    let mut logs: BTreeMap<(String, i32), Vec<Record>> = BTreeMap::new();
+    for record in records {
+        logs.entry((record.topic.clone(), record.partition)).or_default().push(record);
+    }
+    let mut logs: Vec<VecDeque<Record>> = logs
+        .into_values()
+        .map(|mut log| {
+            log.sort_by_key(|record| record.offset);
+            log.into()
+        })
+        .collect();
+
+    let mut merged = Vec::new();
+    while let Some(next) = logs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, log)| log.front().map(|record| (record.timestamp.unwrap_or_default(), index)))
+        .min()
+        .map(|(_, index)| index)
+    {
+        merged.extend(logs[next].pop_front());
+    }
+    merged
+}
+
+/// Replays `inputs`, records of the orders and control-plane topics,
+/// through `matching_engine` and checks the trades of `published`, records
+/// of match-events, against the trades the replay produces. Only
+/// discrepancies touching trades from `from` up to `to` (seconds since the
+/// epoch) are reported, but every input before `to` is replayed so books
+/// are as they were when the range starts.
+pub fn reconcile(
+    mut matching_engine: MatchingEngine,
+    inputs: Vec<Record>,
+    published: Vec<Record>,
+    topics: &TopicConfig,
+    from: i64,
+    to: i64,
+) -> ReconciliationReport {
+    let mut expected: HashMap<TradeKey, TradeTotal> = HashMap::new();
+    // Amends move an order's original quantity, by what they add to or
+    // take from what is left
+    let mut original_quantities: HashMap<String, u32> = HashMap::new();
+    let mut replay_filled: HashMap<String, u32> = HashMap::new();
+    let mut orders_replayed = 0;
+
+    for record in merge_by_timestamp(inputs) {
+        // Record times are milliseconds; trades are stamped in seconds
+        let timestamp = record.timestamp.map(|millis| millis.div_euclid(1000));
+        if timestamp.is_some_and(|timestamp| timestamp >= to) {
+            continue;
+        }
+        let Some(payload) = &record.payload else {
+            continue;
+        };
+
+        let mut events = timestamp.map(|now| matching_engine.advance_clock(now)).unwrap_or_default();
+        if record.topic == topics.control_plane {
+            match serde_json::from_str::<ControlMessage>(payload) {
+                Ok(ControlMessage::MassCancel { filter }) if !filter.is_valid() => {}
+                Ok(command) => events.extend(matching_engine.apply_control(&command)),
+                // Engine events and mass cancel reports share the topic
+                Err(_) => {}
+            }
+        } else if let Ok(order) = serde_json::from_str::<Order>(payload) {
+            original_quantities.entry(order.id.clone()).or_insert(order.original_quantity);
+            orders_replayed += 1;
+            events.extend(matching_engine.process_order(order));
+        } else if let Ok(request) = serde_json::from_str::<OrderRequest>(payload) {
+            events.extend(matching_engine.process_request(request));
+        }
+
+        let in_range = timestamp.is_none_or(|timestamp| timestamp >= from);
+        for event in events {
+            match event {
+                EngineEvent::Trade(trade) => {
+                    for order_id in [&trade.buyer_order_id, &trade.seller_order_id] {
+                        *replay_filled.entry(order_id.clone()).or_default() += trade.quantity;
+                    }
+                    add_trade(&mut expected, &trade, in_range);
+                }
+                EngineEvent::Amended { order_id, remaining_quantity, .. } => {
+                    let filled = replay_filled.get(&order_id).copied().unwrap_or_default();
+                    original_quantities.insert(order_id, filled + remaining_quantity);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut actual: HashMap<TradeKey, TradeTotal> = HashMap::new();
+    let mut filled: HashMap<String, (String, u64, bool)> = HashMap::new();
+    let mut trades_published = 0;
+    for record in &published {
+        let Some(trade) = record.payload.as_deref().and_then(|payload| serde_json::from_str::<MatchEvent>(payload).ok()) else {
+            warn!(partition = record.partition, offset = record.offset, "Skipping record that is not a match event");
+            continue;
+        };
+        let in_range = from <= trade.timestamp && trade.timestamp < to;
+        trades_published += in_range as usize;
+        add_trade(&mut actual, &trade, in_range);
+        for order_id in [&trade.buyer_order_id, &trade.seller_order_id] {
+            let (_, quantity, touched) = filled.entry(order_id.clone()).or_insert((trade.instrument.clone(), 0, false));
+            *quantity += trade.quantity as u64;
+            *touched |= in_range;
+        }
+    }
+
+    let mut discrepancies = Vec::new();
+    let mut keys: Vec<&TradeKey> = expected.keys().chain(actual.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let (instrument, buyer_order_id, seller_order_id, price) =
+            (key.0.clone(), key.1.clone(), key.2.clone(), f64::from_bits(key.3));
+        match (expected.get(key), actual.get(key)) {
+            (Some(expected), None) if expected.in_range => discrepancies.push(Discrepancy::MissingTrade {
+                instrument,
+                buyer_order_id,
+                seller_order_id,
+                price,
+                quantity: expected.quantity,
+            }),
+            (None, Some(actual)) if actual.in_range => discrepancies.push(Discrepancy::ExtraTrade {
+                instrument,
+                buyer_order_id,
+                seller_order_id,
+                price,
+                quantity: actual.quantity,
+                trade_ids: actual.trade_ids.clone(),
+            }),
+            (Some(expected), Some(actual))
+                if (expected.in_range || actual.in_range) && expected.quantity != actual.quantity =>
+            {
+                discrepancies.push(Discrepancy::QuantityMismatch {
+                    instrument,
+                    buyer_order_id,
+                    seller_order_id,
+                    price,
+                    expected: expected.quantity,
+                    published: actual.quantity,
+                })
+            }
+            _ => {}
+        }
+    }
+
+    let mut filled: Vec<_> = filled.into_iter().collect();
+    filled.sort_by(|a, b| a.0.cmp(&b.0));
+    for (order_id, (instrument, quantity, touched)) in filled {
+        if let Some(&original_quantity) = original_quantities.get(&order_id)
+            && touched
+            && quantity > original_quantity as u64
+        {
+            discrepancies.push(Discrepancy::OverFill { order_id, instrument, original_quantity, filled: quantity });
+        }
+    }
+
+    ReconciliationReport {
+        from,
+        to,
+        orders_replayed,
+        trades_expected: expected.values().filter(|total| total.in_range).map(|total| total.trade_ids.len()).sum(),
+        trades_published,
+        discrepancies,
+    }
+}
@@ -0,0 +1,30 @@
+use tracing::{error, info};
+use crate::transport::OrderSource;
+use crate::types::control_message::ControlMessage;
+use crate::utils::routing::RoutingTable;
+
+/// Keeps `routes` up to date with the `RouteInstrument` commands on the
+/// control-plane until the source closes. Reading the control-plane from
+/// the beginning replays earlier migrations.
+pub async fn follow<C: OrderSource>(mut control: C, routes: RoutingTable) {
+    while let Some(record) = control.next().await {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                error!(error = %e, "Transport error on control-plane");
+                continue;
+            }
+        };
+
+        // Everything else on the control-plane is for the engines
+        let Some(command) = record.payload.as_deref().and_then(|payload| serde_json::from_str::<ControlMessage>(payload).ok())
+        else {
+            continue;
+        };
+
+        if let ControlMessage::RouteInstrument { instrument, partition } = &command {
+            routes.apply(&command);
+            info!(%instrument, partition, offset = record.offset, "Instrument rerouted");
+        }
+    }
+}
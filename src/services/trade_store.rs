@@ -0,0 +1,383 @@
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::Context;
+use axum::extract::{Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use clap::ValueEnum;
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, DoubleType, Int64Type};
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection};
+use serde::{Serialize, Deserialize};
+use tracing::{error, info, warn};
+use crate::services::http::{self, ApiError};
+use crate::transport::OrderSource;
+use crate::types::match_event::MatchEvent;
+use crate::utils::backoff::Backoff;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS trades (
+        id TEXT PRIMARY KEY,
+        instrument TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        sequence INTEGER NOT NULL,
+        buyer_order_id TEXT NOT NULL,
+        seller_order_id TEXT NOT NULL,
+        buyer_account TEXT,
+        seller_account TEXT,
+        event TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS trades_instrument_timestamp ON trades (instrument, timestamp);
+    CREATE INDEX IF NOT EXISTS trades_timestamp ON trades (timestamp);
+    CREATE INDEX IF NOT EXISTS trades_buyer_order_id ON trades (buyer_order_id);
+    CREATE INDEX IF NOT EXISTS trades_seller_order_id ON trades (seller_order_id);
+    CREATE INDEX IF NOT EXISTS trades_buyer_account ON trades (buyer_account);
+    CREATE INDEX IF NOT EXISTS trades_seller_account ON trades (seller_account);
+";
+
+// Columns of a Parquet export, in the order of `TradeRow`'s fields
+const PARQUET_SCHEMA: &str = "
+    message trade {
+        REQUIRED BYTE_ARRAY id (UTF8);
+        REQUIRED BYTE_ARRAY instrument (UTF8);
+        REQUIRED INT64 sequence;
+        REQUIRED INT64 timestamp;
+        REQUIRED INT64 timestamp_ns;
+        REQUIRED DOUBLE price;
+        REQUIRED INT64 quantity;
+        REQUIRED BYTE_ARRAY buyer_order_id (UTF8);
+        REQUIRED BYTE_ARRAY seller_order_id (UTF8);
+        OPTIONAL BYTE_ARRAY buyer_account (UTF8);
+        OPTIONAL BYTE_ARRAY seller_account (UTF8);
+        OPTIONAL BYTE_ARRAY aggressor_side (UTF8);
+        REQUIRED DOUBLE buyer_fee;
+        REQUIRED DOUBLE seller_fee;
+    }
+";
+
+/// Which trades to return. Every field left out matches all trades; times
+/// are seconds since the epoch, `from` inclusive and `to` exclusive.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TradeQuery {
+    pub instrument: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    /// Trades with this account on either side
+    pub account: Option<String>,
+    /// Trades of this order on either side
+    pub order_id: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// The match events as published
+    #[default]
+    Json,
+    /// One row per trade
+    Csv,
+    /// The same columns as CSV
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+/// The columns of a CSV or Parquet export.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct TradeRow<'a> {
+    id: &'a str,
+    instrument: &'a str,
+    sequence: u64,
+    timestamp: i64,
+    timestamp_ns: i64,
+    price: f64,
+    quantity: u32,
+    buyer_order_id: &'a str,
+    seller_order_id: &'a str,
+    buyer_account: Option<&'a str>,
+    seller_account: Option<&'a str>,
+    aggressor_side: Option<&'a str>,
+    buyer_fee: f64,
+    seller_fee: f64,
+}
+
+impl<'a> From<&'a MatchEvent> for TradeRow<'a> {
+    fn from(trade: &'a MatchEvent) -> Self {
+        Self {
+            id: &trade.id,
+            instrument: &trade.instrument,
+            sequence: trade.sequence,
+            timestamp: trade.timestamp,
+            timestamp_ns: trade.timestamp_ns,
+            price: trade.price,
+            quantity: trade.quantity,
+            buyer_order_id: &trade.buyer_order_id,
+            seller_order_id: &trade.seller_order_id,
+            buyer_account: trade.buyer_account.as_deref(),
+            seller_account: trade.seller_account.as_deref(),
+            aggressor_side: trade.aggressor_side.as_deref(),
+            buyer_fee: trade.buyer_fee,
+            seller_fee: trade.seller_fee,
+        }
+    }
+}
+
+/// Every trade read from `match-events`, kept in SQLite as published and
+/// indexed by instrument, time, order and account.
+pub struct TradeStore {
+    connection: Connection,
+}
+
+impl TradeStore {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path).with_context(|| format!("opening trade store {}", path.display()))?;
+        Self::with_connection(connection)
+    }
+
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> anyhow::Result<Self> {
+        connection.execute_batch(SCHEMA).context("creating trade store tables")?;
+        Ok(Self { connection })
+    }
+
+    /// Stores a trade. Returns `false` for a trade already stored, so
+    /// replayed match events are harmless.
+    pub fn insert(&mut self, trade: &MatchEvent) -> anyhow::Result<bool> {
+        let event = serde_json::to_string(trade)?;
+        let inserted = self.connection.execute(
+            "INSERT OR IGNORE INTO trades
+                 (id, instrument, timestamp, sequence, buyer_order_id, seller_order_id, buyer_account, seller_account, event)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                trade.id,
+                trade.instrument,
+                trade.timestamp,
+                trade.sequence as i64,
+                trade.buyer_order_id,
+                trade.seller_order_id,
+                trade.buyer_account,
+                trade.seller_account,
+                event
+            ],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    /// The trades matching `query` in time order, each instrument's in
+    /// sequence order.
+    pub fn query(&self, query: &TradeQuery) -> anyhow::Result<Vec<MatchEvent>> {
+        // Only the conditions asked for are added, so SQLite can pick the
+        // index that fits them
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if let Some(instrument) = &query.instrument {
+            conditions.push("instrument = ?");
+            values.push(Value::Text(instrument.clone()));
+        }
+        if let Some(from) = query.from {
+            conditions.push("timestamp >= ?");
+            values.push(Value::Integer(from));
+        }
+        if let Some(to) = query.to {
+            conditions.push("timestamp < ?");
+            values.push(Value::Integer(to));
+        }
+        if let Some(account) = &query.account {
+            conditions.push("(buyer_account = ? OR seller_account = ?)");
+            values.extend([Value::Text(account.clone()), Value::Text(account.clone())]);
+        }
+        if let Some(order_id) = &query.order_id {
+            conditions.push("(buyer_order_id = ? OR seller_order_id = ?)");
+            values.extend([Value::Text(order_id.clone()), Value::Text(order_id.clone())]);
+        }
+
+        let mut sql = "SELECT event FROM trades".to_string();
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY timestamp, instrument, sequence");
+        if let Some(limit) = query.limit {
+            sql.push_str(" LIMIT ?");
+            values.push(Value::Integer(limit as i64));
+        }
+
+        let mut statement = self.connection.prepare(&sql)?;
+        let events = statement
+            .query_map(params_from_iter(values), |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        events
+            .iter()
+            .map(|event| serde_json::from_str(event).context("decoding stored match event"))
+            .collect()
+    }
+
+    /// Writes the trades matching `query` to `writer` in `format`.
+    pub fn export<W: Write + Send>(&self, query: &TradeQuery, format: ExportFormat, writer: W) -> anyhow::Result<()> {
+        let trades = self.query(query)?;
+        match format {
+            ExportFormat::Json => serde_json::to_writer(writer, &trades)?,
+            ExportFormat::Csv => {
+                let mut csv = csv::Writer::from_writer(writer);
+                for trade in &trades {
+                    csv.serialize(TradeRow::from(trade))?;
+                }
+                csv.flush()?;
+            }
+            ExportFormat::Parquet => write_parquet(writer, &trades)?,
+        }
+        Ok(())
+    }
+}
+
+fn write_parquet<W: Write + Send>(writer: W, trades: &[MatchEvent]) -> anyhow::Result<()> {
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let mut file = SerializedFileWriter::new(writer, schema, Default::default())?;
+    let mut row_group = file.next_row_group()?;
+
+    let text = |value: &str| Some(ByteArray::from(value));
+    let optional_text = |value: &Option<String>| value.as_deref().map(ByteArray::from);
+    write_column::<ByteArrayType, _>(&mut row_group, trades.iter().map(|t| text(&t.id)))?;
+    write_column::<ByteArrayType, _>(&mut row_group, trades.iter().map(|t| text(&t.instrument)))?;
+    write_column::<Int64Type, _>(&mut row_group, trades.iter().map(|t| Some(t.sequence as i64)))?;
+    write_column::<Int64Type, _>(&mut row_group, trades.iter().map(|t| Some(t.timestamp)))?;
+    write_column::<Int64Type, _>(&mut row_group, trades.iter().map(|t| Some(t.timestamp_ns)))?;
+    write_column::<DoubleType, _>(&mut row_group, trades.iter().map(|t| Some(t.price)))?;
+    write_column::<Int64Type, _>(&mut row_group, trades.iter().map(|t| Some(t.quantity as i64)))?;
+    write_column::<ByteArrayType, _>(&mut row_group, trades.iter().map(|t| text(&t.buyer_order_id)))?;
+    write_column::<ByteArrayType, _>(&mut row_group, trades.iter().map(|t| text(&t.seller_order_id)))?;
+    write_column::<ByteArrayType, _>(&mut row_group, trades.iter().map(|t| optional_text(&t.buyer_account)))?;
+    write_column::<ByteArrayType, _>(&mut row_group, trades.iter().map(|t| optional_text(&t.seller_account)))?;
+    write_column::<ByteArrayType, _>(&mut row_group, trades.iter().map(|t| optional_text(&t.aggressor_side)))?;
+    write_column::<DoubleType, _>(&mut row_group, trades.iter().map(|t| Some(t.buyer_fee)))?;
+    write_column::<DoubleType, _>(&mut row_group, trades.iter().map(|t| Some(t.seller_fee)))?;
+
+    row_group.close()?;
+    file.close()?;
+    Ok(())
+}
+
+// Writes the next column of the row group; `None` values are nulls of an
+// optional column
+fn write_column<T: DataType, W: Write + Send>(
+    row_group: &mut SerializedRowGroupWriter<'_, W>,
+    values: impl Iterator<Item = Option<T::T>>,
+) -> anyhow::Result<()> {
+    let mut column = row_group.next_column()?.context("Parquet schema has fewer columns than a trade")?;
+    let writer = column.typed::<T>();
+    let values: Vec<Option<T::T>> = values.collect();
+    let present: Vec<T::T> = values.iter().flatten().cloned().collect();
+    if writer.get_descriptor().max_def_level() > 0 {
+        let levels: Vec<i16> = values.iter().map(|value| value.is_some() as i16).collect();
+        writer.write_batch(&present, Some(&levels), None)?;
+    } else {
+        writer.write_batch(&present, None, None)?;
+    }
+    column.close()?;
+    Ok(())
+}
+
+// Bounds of the backoff between attempts to store a trade
+const STORE_RETRY_MIN: Duration = Duration::from_millis(100);
+const STORE_RETRY_MAX: Duration = Duration::from_secs(10);
+
+/// A trade store shared between the trade consumer and the query API.
+pub type SharedTradeStore = Arc<Mutex<TradeStore>>;
+
+/// Stores every trade read from `match-events` until the source closes,
+/// committing each once it is stored. The next trade is not read before
+/// the current one is in the database, however many attempts that takes.
+pub async fn run<O: OrderSource>(mut source: O, store: SharedTradeStore) {
+    while let Some(record) = source.next().await {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                error!(error = %e, "Transport error on match-events");
+                continue;
+            }
+        };
+
+        let Some(payload) = &record.payload else {
+            warn!(partition = record.partition, offset = record.offset, "Empty or invalid message payload");
+            continue;
+        };
+
+        let trade = match serde_json::from_str::<MatchEvent>(payload) {
+            Ok(trade) => trade,
+            Err(e) => {
+                warn!(partition = record.partition, offset = record.offset, error = %e, "Failed to parse match event JSON");
+                continue;
+            }
+        };
+
+        let mut backoff = Backoff::new(STORE_RETRY_MIN, STORE_RETRY_MAX);
+        let stored = loop {
+            let stored = store.lock().unwrap().insert(&trade);
+            match stored {
+                Ok(stored) => break stored,
+                Err(e) => {
+                    error!(trade_id = %trade.id, error = %e, backoff_ms = backoff.delay().as_millis() as u64, "Failed to store trade; retrying");
+                    backoff.wait().await;
+                }
+            }
+        };
+        if stored {
+            info!(trade_id = %trade.id, instrument = %trade.instrument, offset = record.offset, "Trade stored");
+        } else {
+            info!(trade_id = %trade.id, offset = record.offset, "Trade already stored");
+        }
+
+        if let Err(e) = source.commit(&record) {
+            error!(partition = record.partition, offset = record.offset, error = %e, "Failed to commit message");
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FormatParam {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+async fn trades(
+    State(store): State<SharedTradeStore>,
+    Query(query): Query<TradeQuery>,
+    Query(FormatParam { format }): Query<FormatParam>,
+) -> Result<Response, ApiError> {
+    let mut body = Vec::new();
+    store.lock().unwrap().export(&query, format, &mut body)?;
+    Ok(([(CONTENT_TYPE, format.content_type())], body).into_response())
+}
+
+/// The trade store's query API: `/trades` with the fields of `TradeQuery`
+/// and an optional `format` as query parameters.
+pub fn router(store: SharedTradeStore) -> Router {
+    Router::new()
+        .route("/trades", get(trades))
+        .with_state(store)
+}
+
+/// Serves the query API on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, store: SharedTradeStore) -> anyhow::Result<()> {
+    http::serve(addr, router(store)).await
+}
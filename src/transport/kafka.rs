@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::sync::{mpsc as std_mpsc, Arc, Mutex, OnceLock, Weak};
+use std::time::Duration;
+use anyhow::Context;
+use rdkafka::consumer::stream_consumer::StreamPartitionQueue;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer, ConsumerContext, Rebalance, StreamConsumer};
+use rdkafka::message::{BorrowedMessage, Header, Headers, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::statistics::Statistics;
+use rdkafka::{ClientConfig, ClientContext, Message, Offset, TopicPartitionList};
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+use crate::transport::{Assignment, Delivery, EventSink, OrderSource, PartitionedSource, Record, SnapshotStore};
+
+// How long a revocation waits for the partition's owner before letting the
+// rebalance go ahead anyway
+const REVOKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Keeps the consumer lag librdkafka reports in its periodic statistics.
+#[derive(Default)]
+pub struct LagContext {
+    lag: Mutex<HashMap<(String, i32), i64>>,
+}
+
+impl ClientContext for LagContext {
+    fn stats(&self, statistics: Statistics) {
+        // Partition -1 is librdkafka's internal partition and a lag of -1
+        // means the partition has not been fetched yet
+        let lag = statistics.topics
+            .into_iter()
+            .flat_map(|(topic, stats)| {
+                stats.partitions
+                    .into_iter()
+                    .filter(|(partition, stats)| *partition >= 0 && stats.consumer_lag >= 0)
+                    .map(move |(partition, stats)| ((topic.clone(), partition), stats.consumer_lag))
+            })
+            .collect();
+        *self.lag.lock().unwrap() = lag;
+    }
+}
+
+impl ConsumerContext for LagContext {}
+
+pub struct KafkaSource {
+    consumer: StreamConsumer<LagContext>,
+}
+
+impl KafkaSource {
+    pub fn new(consumer: StreamConsumer<LagContext>) -> Self {
+        Self { consumer }
+    }
+
+    /// Creates a consumer in `group_id` subscribed to `topics`.
+    pub fn subscribe(config: &ClientConfig, group_id: &str, topics: &[&str]) -> anyhow::Result<Self> {
+        let consumer: StreamConsumer<LagContext> = config
+            .clone()
+            .set("group.id", group_id)
+            // Statistics carry the consumer lag reported by `lag`
+            .set("statistics.interval.ms", "1000")
+            .create_with_context(LagContext::default())?;
+        consumer.subscribe(topics)?;
+        Ok(Self::new(consumer))
+    }
+
+    pub fn consumer(&self) -> &StreamConsumer<LagContext> {
+        &self.consumer
+    }
+}
+
+fn to_record(m: &BorrowedMessage<'_>) -> Record {
+    Record {
+        topic: m.topic().to_string(),
+        partition: m.partition(),
+        offset: m.offset(),
+        key: m.key().map(|key| String::from_utf8_lossy(key).into_owned()),
+        payload: m.payload_view::<str>().and_then(Result::ok).map(str::to_string),
+        headers: m
+            .headers()
+            .map(|headers| {
+                headers
+                    .iter()
+                    .filter_map(|header| {
+                        let value = std::str::from_utf8(header.value?).ok()?;
+                        Some((header.key.to_string(), value.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        timestamp: m.timestamp().to_millis(),
+    }
+}
+
+fn commit_record<C: ConsumerContext>(consumer: &StreamConsumer<C>, record: &Record, mode: CommitMode) -> anyhow::Result<()> {
+    // Kafka commits the offset of the next message to read
+    let mut offsets = TopicPartitionList::new();
+    offsets.add_partition_offset(&record.topic, record.partition, Offset::Offset(record.offset + 1))?;
+    consumer.commit(&offsets, mode)?;
+    Ok(())
+}
+
+impl OrderSource for KafkaSource {
+    async fn next(&mut self) -> Option<anyhow::Result<Record>> {
+        let record = self.consumer.recv().await.map(|m| to_record(&m));
+        Some(record.map_err(Into::into))
+    }
+
+    fn commit(&self, record: &Record) -> anyhow::Result<()> {
+        commit_record(&self.consumer, record, CommitMode::Async)
+    }
+
+    fn commit_sync(&self, record: &Record) -> anyhow::Result<()> {
+        commit_record(&self.consumer, record, CommitMode::Sync)
+    }
+
+    fn lag(&self) -> HashMap<(String, i32), i64> {
+        self.consumer.context().lag.lock().unwrap().clone()
+    }
+}
+
+/// Consumer context of a `KafkaPartitionedSource`. Every assigned partition
+/// is split into a queue of its own, and revocations wait until the owner
+/// of the partition has let go of it.
+pub struct PartitionedContext {
+    lag: LagContext,
+    topic: String,
+    consumer: OnceLock<Weak<StreamConsumer<PartitionedContext>>>,
+    assignments: mpsc::UnboundedSender<Assignment<KafkaPartitionSource>>,
+}
+
+impl ClientContext for PartitionedContext {
+    fn stats(&self, statistics: Statistics) {
+        self.lag.stats(statistics);
+    }
+}
+
+impl ConsumerContext for PartitionedContext {
+    fn pre_rebalance(&self, _: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        let Rebalance::Revoke(partitions) = rebalance else {
+            return;
+        };
+
+        let mut pending = Vec::new();
+        for element in partitions.elements_for_topic(&self.topic) {
+            let (done, revoked) = std_mpsc::channel();
+            let partition = element.partition();
+            if self.assignments.send(Assignment::Revoked { partition, done }).is_ok() {
+                pending.push((partition, revoked));
+            }
+        }
+
+        for (partition, revoked) in pending {
+            if revoked.recv_timeout(REVOKE_TIMEOUT).is_err() {
+                warn!(partition, "Partition was not released before the revoke timeout");
+            }
+        }
+    }
+
+    fn post_rebalance(&self, _: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        let Rebalance::Assign(partitions) = rebalance else {
+            return;
+        };
+        let Some(consumer) = self.consumer.get().and_then(Weak::upgrade) else {
+            return;
+        };
+
+        for element in partitions.elements_for_topic(&self.topic) {
+            let partition = element.partition();
+            match consumer.split_partition_queue(&self.topic, partition) {
+                Some(queue) => {
+                    let source = KafkaPartitionSource { consumer: consumer.clone(), queue, partition };
+                    let _ = self.assignments.send(Assignment::Assigned { partition, source });
+                }
+                None => error!(partition, "Failed to split the partition queue"),
+            }
+        }
+    }
+}
+
+/// A group consumer of one topic that hands out each assigned partition as
+/// a `KafkaPartitionSource`.
+pub struct KafkaPartitionedSource {
+    assignments: mpsc::UnboundedReceiver<Assignment<KafkaPartitionSource>>,
+}
+
+impl KafkaPartitionedSource {
+    /// Creates a consumer in `group_id` subscribed to `topic`. Must be
+    /// called within a Tokio runtime.
+    pub fn subscribe(config: &ClientConfig, group_id: &str, topic: &str) -> anyhow::Result<Self> {
+        let (sender, assignments) = mpsc::unbounded_channel();
+        let context = PartitionedContext {
+            lag: LagContext::default(),
+            topic: topic.to_string(),
+            consumer: OnceLock::new(),
+            assignments: sender,
+        };
+        let consumer: StreamConsumer<PartitionedContext> = config
+            .clone()
+            .set("group.id", group_id)
+            // Statistics carry the consumer lag reported by `lag`
+            .set("statistics.interval.ms", "1000")
+            .create_with_context(context)?;
+        let consumer = Arc::new(consumer);
+        let _ = consumer.context().consumer.set(Arc::downgrade(&consumer));
+        consumer.subscribe(&[topic])?;
+
+        // Rebalance callbacks run while the main queue is polled, and a
+        // revocation blocks until the partition is released, so the main
+        // queue gets a thread of its own. With every partition split off it
+        // carries no messages.
+        let runtime = tokio::runtime::Handle::current();
+        std::thread::Builder::new()
+            .name("kafka-rebalance".to_string())
+            .spawn(move || loop {
+                match runtime.block_on(consumer.recv()) {
+                    Ok(m) => warn!(partition = m.partition(), offset = m.offset(), "Unexpected message on the main queue"),
+                    Err(e) => error!(error = %e, "Kafka consumer error"),
+                }
+            })?;
+
+        Ok(Self { assignments })
+    }
+}
+
+impl PartitionedSource for KafkaPartitionedSource {
+    type Partition = KafkaPartitionSource;
+
+    async fn next_assignment(&mut self) -> Option<Assignment<KafkaPartitionSource>> {
+        self.assignments.recv().await
+    }
+}
+
+/// One assigned partition of a `KafkaPartitionedSource`.
+pub struct KafkaPartitionSource {
+    consumer: Arc<StreamConsumer<PartitionedContext>>,
+    queue: StreamPartitionQueue<PartitionedContext>,
+    partition: i32,
+}
+
+impl OrderSource for KafkaPartitionSource {
+    async fn next(&mut self) -> Option<anyhow::Result<Record>> {
+        let record = self.queue.recv().await.map(|m| to_record(&m));
+        Some(record.map_err(Into::into))
+    }
+
+    fn commit(&self, record: &Record) -> anyhow::Result<()> {
+        commit_record(&self.consumer, record, CommitMode::Async)
+    }
+
+    fn commit_sync(&self, record: &Record) -> anyhow::Result<()> {
+        commit_record(&self.consumer, record, CommitMode::Sync)
+    }
+
+    fn lag(&self) -> HashMap<(String, i32), i64> {
+        let mut lag = self.consumer.context().lag.lag.lock().unwrap().clone();
+        lag.retain(|(_, partition), _| *partition == self.partition);
+        lag
+    }
+}
+
+/// Snapshots kept as the latest message of each partition of a topic with
+/// as many partitions as the orders topic; the snapshot of orders partition
+/// N is written to partition N. The topic should be compacted.
+pub struct KafkaSnapshotStore {
+    producer: FutureProducer,
+    client: ClientConfig,
+    topic: String,
+    timeout: Duration,
+}
+
+impl KafkaSnapshotStore {
+    pub fn new(producer: FutureProducer, client: ClientConfig, topic: &str, timeout: Duration) -> Self {
+        Self { producer, client, topic: topic.to_string(), timeout }
+    }
+}
+
+impl SnapshotStore for KafkaSnapshotStore {
+    async fn save(&self, partition: i32, snapshot: &str) -> anyhow::Result<()> {
+        let key = partition.to_string();
+        let record = FutureRecord::to(&self.topic).key(&key).payload(snapshot).partition(partition);
+        self.producer.send(record, self.timeout).await.map_err(|(e, _)| e)?;
+        Ok(())
+    }
+
+    async fn load(&self, partition: i32) -> anyhow::Result<Option<String>> {
+        let client = self.client.clone();
+        let topic = self.topic.clone();
+        let timeout = self.timeout;
+
+        // Reads the last message of the partition with a short-lived
+        // consumer outside any group
+        tokio::task::spawn_blocking(move || -> anyhow::Result<Option<String>> {
+            let consumer: BaseConsumer = client.create()?;
+            let (low, high) = consumer.fetch_watermarks(&topic, partition, timeout)?;
+            if high <= low {
+                return Ok(None);
+            }
+
+            let mut assignment = TopicPartitionList::new();
+            assignment.add_partition_offset(&topic, partition, Offset::Offset(high - 1))?;
+            consumer.assign(&assignment)?;
+
+            let message = consumer
+                .poll(timeout)
+                .context("timed out reading the snapshot")??;
+            Ok(message.payload_view::<str>().and_then(Result::ok).map(str::to_string))
+        })
+        .await?
+    }
+}
+
+pub struct KafkaSink {
+    producer: FutureProducer,
+    timeout: Duration,
+}
+
+impl KafkaSink {
+    pub fn new(producer: FutureProducer, timeout: Duration) -> Self {
+        Self { producer, timeout }
+    }
+}
+
+impl EventSink for KafkaSink {
+    async fn send_with_headers(
+        &self,
+        topic: &str,
+        key: &str,
+        payload: &str,
+        partition: Option<i32>,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<Delivery> {
+        let mut record = FutureRecord::to(topic).key(key).payload(payload);
+        if let Some(partition) = partition {
+            record = record.partition(partition);
+        }
+        if !headers.is_empty() {
+            let headers = headers.iter().fold(OwnedHeaders::new(), |owned, (key, value)| {
+                owned.insert(Header { key, value: Some(*value) })
+            });
+            record = record.headers(headers);
+        }
+
+        self.producer
+            .send(record, self.timeout)
+            .await
+            .map(|delivery| Delivery { partition: delivery.partition, offset: delivery.offset })
+            .map_err(|(e, _)| e.into())
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::sync::{mpsc as std_mpsc, Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::anyhow;
+use tokio::sync::{mpsc, watch};
+use crate::transport::{Assignment, Delivery, EventSink, OrderSource, PartitionedSource, Record, SnapshotStore};
+use crate::utils::partitioner::custom_partition;
+
+/// An in-process message bus with Kafka-like topics: each topic has a fixed
+/// number of partitions, every partition is an append-only log with its own
+/// offsets, and unpartitioned messages are placed by key. Used to run the
+/// pipeline without a broker.
+#[derive(Clone)]
+pub struct MemoryBus {
+    inner: Arc<BusInner>,
+}
+
+struct BusInner {
+    topics: Mutex<HashMap<String, Vec<Vec<Record>>>>,
+    committed: Mutex<HashMap<(String, i32), i64>>,
+    // Sends still to fail, per topic
+    failures: Mutex<HashMap<String, usize>>,
+    // Timestamp of appended records in milliseconds; `None` follows the
+    // wall clock
+    time: Mutex<Option<i64>>,
+    // Bumped on every append so waiting sources wake up
+    appended: watch::Sender<u64>,
+}
+
+impl MemoryBus {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(BusInner {
+                topics: Mutex::new(HashMap::new()),
+                committed: Mutex::new(HashMap::new()),
+                failures: Mutex::new(HashMap::new()),
+                time: Mutex::new(None),
+                appended: watch::channel(0).0,
+            }),
+        }
+    }
+
+    pub fn create_topic(&self, topic: &str, partitions: i32) {
+        self.inner.topics
+            .lock()
+            .unwrap()
+            .entry(topic.to_string())
+            .or_insert_with(|| vec![Vec::new(); partitions as usize]);
+    }
+
+    pub fn sink(&self) -> MemorySink {
+        MemorySink { bus: self.clone() }
+    }
+
+    /// A source reading `topics` from the beginning.
+    pub fn source(&self, topics: &[&str]) -> MemorySource {
+        MemorySource {
+            bus: self.clone(),
+            topics: topics.iter().map(|topic| topic.to_string()).collect(),
+            partition: None,
+            positions: HashMap::new(),
+            appended: self.inner.appended.subscribe(),
+            next_partition: 0,
+        }
+    }
+
+    /// A source reading one partition of `topic`, starting after its last
+    /// committed offset.
+    pub fn partition_source(&self, topic: &str, partition: i32) -> MemorySource {
+        let start = self.committed_offset(topic, partition).map_or(0, |offset| offset + 1);
+        MemorySource {
+            bus: self.clone(),
+            topics: vec![topic.to_string()],
+            partition: Some(partition),
+            positions: HashMap::from([((topic.to_string(), partition), start)]),
+            appended: self.inner.appended.subscribe(),
+            next_partition: 0,
+        }
+    }
+
+    /// A group consumer of `topic` that owns no partitions until the
+    /// returned rebalancer assigns some.
+    pub fn partitioned_source(&self, topic: &str) -> (MemoryPartitionedSource, MemoryRebalancer) {
+        let (sender, assignments) = mpsc::unbounded_channel();
+        let rebalancer = MemoryRebalancer { bus: self.clone(), topic: topic.to_string(), assignments: sender };
+        (MemoryPartitionedSource { assignments }, rebalancer)
+    }
+
+    /// Makes the next `count` sends to `topic` fail, as a broker that is
+    /// unavailable would.
+    pub fn fail_sends(&self, topic: &str, count: usize) {
+        self.inner.failures.lock().unwrap().insert(topic.to_string(), count);
+    }
+
+    /// Stamps the records appended from now on with `millis` since the
+    /// epoch instead of the wall clock, so the engine clock they drive can
+    /// be set.
+    pub fn set_time(&self, millis: i64) {
+        *self.inner.time.lock().unwrap() = Some(millis);
+    }
+
+    /// Every record in `topic`, partition by partition.
+    pub fn records(&self, topic: &str) -> Vec<Record> {
+        self.inner.topics
+            .lock()
+            .unwrap()
+            .get(topic)
+            .map(|partitions| partitions.iter().flatten().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The last committed offset of a partition, if any.
+    pub fn committed_offset(&self, topic: &str, partition: i32) -> Option<i64> {
+        self.inner.committed
+            .lock()
+            .unwrap()
+            .get(&(topic.to_string(), partition))
+            .copied()
+    }
+
+    fn append(
+        &self,
+        topic: &str,
+        key: &str,
+        payload: &str,
+        partition: Option<i32>,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<Delivery> {
+        if let Some(failures) = self.inner.failures.lock().unwrap().get_mut(topic)
+            && *failures > 0
+        {
+            *failures -= 1;
+            return Err(anyhow!("send to {} failed", topic));
+        }
+
+        let mut topics = self.inner.topics.lock().unwrap();
+        let partitions = topics
+            .get_mut(topic)
+            .ok_or_else(|| anyhow!("unknown topic {}", topic))?;
+
+        let partition_count = partitions.len() as i32;
+        let partition = partition.unwrap_or_else(|| custom_partition(key, partition_count));
+        let log = partitions
+            .get_mut(partition as usize)
+            .ok_or_else(|| anyhow!("unknown partition {} of {}", partition, topic))?;
+
+        let timestamp = self.inner.time
+            .lock()
+            .unwrap()
+            .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64);
+        let offset = log.len() as i64;
+        log.push(Record {
+            topic: topic.to_string(),
+            partition,
+            offset,
+            key: Some(key.to_string()),
+            payload: Some(payload.to_string()),
+            headers: headers.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+            timestamp: Some(timestamp),
+        });
+        drop(topics);
+
+        self.inner.appended.send_modify(|version| *version += 1);
+        Ok(Delivery { partition, offset })
+    }
+}
+
+impl Default for MemoryBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct MemorySink {
+    bus: MemoryBus,
+}
+
+impl EventSink for MemorySink {
+    async fn send_with_headers(
+        &self,
+        topic: &str,
+        key: &str,
+        payload: &str,
+        partition: Option<i32>,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<Delivery> {
+        self.bus.append(topic, key, payload, partition, headers)
+    }
+}
+
+pub struct MemorySource {
+    bus: MemoryBus,
+    topics: Vec<String>,
+    // Only this partition of each topic, when set
+    partition: Option<i32>,
+    positions: HashMap<(String, i32), i64>,
+    appended: watch::Receiver<u64>,
+    // Partitions are polled round-robin so one busy partition can't starve the rest
+    next_partition: usize,
+}
+
+impl MemorySource {
+    fn poll_record(&mut self) -> Option<Record> {
+        let topics = self.bus.inner.topics.lock().unwrap();
+        let logs: Vec<(&String, i32, &Vec<Record>)> = self.topics
+            .iter()
+            .filter_map(|topic| topics.get(topic).map(|partitions| (topic, partitions)))
+            .flat_map(|(topic, partitions)| {
+                partitions.iter().enumerate().map(move |(partition, log)| (topic, partition as i32, log))
+            })
+            .filter(|(_, partition, _)| self.partition.is_none_or(|only| only == *partition))
+            .collect();
+
+        for step in 0..logs.len() {
+            let index = (self.next_partition + step) % logs.len();
+            let (topic, partition, log) = logs[index];
+
+            let position = self.positions.entry((topic.clone(), partition)).or_insert(0);
+            if let Some(record) = log.get(*position as usize) {
+                *position += 1;
+                self.next_partition = index + 1;
+                return Some(record.clone());
+            }
+        }
+
+        None
+    }
+}
+
+impl OrderSource for MemorySource {
+    async fn next(&mut self) -> Option<anyhow::Result<Record>> {
+        loop {
+            // Mark the current version seen before looking, so an append
+            // racing with the scan still wakes us
+            self.appended.borrow_and_update();
+            if let Some(record) = self.poll_record() {
+                return Some(Ok(record));
+            }
+            if self.appended.changed().await.is_err() {
+                return None;
+            }
+        }
+    }
+
+    fn commit(&self, record: &Record) -> anyhow::Result<()> {
+        self.bus.inner.committed
+            .lock()
+            .unwrap()
+            .insert((record.topic.clone(), record.partition), record.offset);
+        Ok(())
+    }
+
+    fn lag(&self) -> HashMap<(String, i32), i64> {
+        let topics = self.bus.inner.topics.lock().unwrap();
+        self.topics
+            .iter()
+            .filter_map(|topic| topics.get(topic).map(|partitions| (topic, partitions)))
+            .flat_map(|(topic, partitions)| {
+                partitions.iter().enumerate().map(move |(partition, log)| {
+                    let key = (topic.clone(), partition as i32);
+                    let position = self.positions.get(&key).copied().unwrap_or(0);
+                    (key, log.len() as i64 - position)
+                })
+            })
+            .filter(|((_, partition), _)| self.partition.is_none_or(|only| only == *partition))
+            .collect()
+    }
+}
+
+pub struct MemoryPartitionedSource {
+    assignments: mpsc::UnboundedReceiver<Assignment<MemorySource>>,
+}
+
+impl PartitionedSource for MemoryPartitionedSource {
+    type Partition = MemorySource;
+
+    async fn next_assignment(&mut self) -> Option<Assignment<MemorySource>> {
+        self.assignments.recv().await
+    }
+}
+
+/// Plays the group coordinator for a `MemoryPartitionedSource`. Dropping it
+/// closes the source.
+pub struct MemoryRebalancer {
+    bus: MemoryBus,
+    topic: String,
+    assignments: mpsc::UnboundedSender<Assignment<MemorySource>>,
+}
+
+impl MemoryRebalancer {
+    pub fn assign(&self, partition: i32) {
+        let source = self.bus.partition_source(&self.topic, partition);
+        let _ = self.assignments.send(Assignment::Assigned { partition, source });
+    }
+
+    /// Revokes `partition` and waits until the consumer has let go of it.
+    pub async fn revoke(&self, partition: i32) {
+        let (done, revoked) = std_mpsc::channel();
+        if self.assignments.send(Assignment::Revoked { partition, done }).is_ok() {
+            let _ = tokio::task::spawn_blocking(move || revoked.recv()).await;
+        }
+    }
+}
+
+/// Snapshots kept in memory, shared by every clone.
+#[derive(Clone, Default)]
+pub struct MemorySnapshotStore {
+    snapshots: Arc<Mutex<HashMap<i32, String>>>,
+}
+
+impl MemorySnapshotStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SnapshotStore for MemorySnapshotStore {
+    async fn save(&self, partition: i32, snapshot: &str) -> anyhow::Result<()> {
+        self.snapshots.lock().unwrap().insert(partition, snapshot.to_string());
+        Ok(())
+    }
+
+    async fn load(&self, partition: i32) -> anyhow::Result<Option<String>> {
+        Ok(self.snapshots.lock().unwrap().get(&partition).cloned())
+    }
+}
@@ -0,0 +1,105 @@
+pub mod kafka;
+pub mod memory;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::mpsc;
+
+/// A message read from a topic partition, detached from the transport that
+/// delivered it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<String>,
+    pub payload: Option<String>,
+    pub headers: Vec<(String, String)>,
+    /// Milliseconds since the epoch the message was produced or appended at
+    pub timestamp: Option<i64>,
+}
+
+impl Record {
+    /// The value of the first header called `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Where a published message ended up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delivery {
+    pub partition: i32,
+    pub offset: i64,
+}
+
+/// A stream of records from one or more subscribed topics. Records of a
+/// partition are delivered in offset order.
+pub trait OrderSource {
+    /// Waits for the next record. `None` means the source is closed.
+    fn next(&mut self) -> impl Future<Output = Option<anyhow::Result<Record>>> + Send;
+
+    /// Marks `record` and everything before it in its partition as processed.
+    fn commit(&self, record: &Record) -> anyhow::Result<()>;
+
+    /// Like `commit`, but returns only once the commit is durable, so the
+    /// next owner of the partition starts after `record`.
+    fn commit_sync(&self, record: &Record) -> anyhow::Result<()> {
+        self.commit(record)
+    }
+
+    /// How many records each assigned topic partition is behind the end of
+    /// its log, as far as the transport currently knows.
+    fn lag(&self) -> HashMap<(String, i32), i64>;
+}
+
+/// Publishes messages to topics. Without an explicit partition the message
+/// is placed by key.
+pub trait EventSink {
+    fn send_with_headers(
+        &self,
+        topic: &str,
+        key: &str,
+        payload: &str,
+        partition: Option<i32>,
+        headers: &[(&str, &str)],
+    ) -> impl Future<Output = anyhow::Result<Delivery>> + Send;
+
+    fn send(&self, topic: &str, key: &str, payload: &str, partition: Option<i32>)
+        -> impl Future<Output = anyhow::Result<Delivery>> + Send {
+        self.send_with_headers(topic, key, payload, partition, &[])
+    }
+}
+
+
+/// A change in the partitions a consumer owns.
+pub enum Assignment<P> {
+    /// The partition is now owned and its records are read from `source`.
+    Assigned { partition: i32, source: P },
+    /// The partition is being taken away. Everything read from it must be
+    /// processed and committed, or left uncommitted to be read again,
+    /// before `done` is signalled; after that another consumer may own it.
+    Revoked { partition: i32, done: mpsc::Sender<()> },
+}
+
+/// A consumer in a group whose partitions are handed out, and taken back,
+/// one at a time, each read through a source of its own.
+pub trait PartitionedSource {
+    type Partition: OrderSource + Send + 'static;
+
+    /// Waits for the next assignment change. `None` means the source is
+    /// closed.
+    fn next_assignment(&mut self) -> impl Future<Output = Option<Assignment<Self::Partition>>> + Send;
+}
+
+/// Keeps the latest snapshot of every orders partition, so whichever
+/// consumer is assigned a partition next can pick up where the last owner
+/// stopped.
+pub trait SnapshotStore {
+    fn save(&self, partition: i32, snapshot: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    fn load(&self, partition: i32) -> impl Future<Output = anyhow::Result<Option<String>>> + Send;
+}
\ No newline at end of file
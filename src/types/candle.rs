@@ -0,0 +1,61 @@
+use serde::{Serialize, Deserialize};
+use crate::types::match_event::MatchEvent;
+
+/// An instrument's trades over one interval, from `start` up to but not
+/// including `end`, both in seconds since the epoch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    pub instrument: String,
+    /// Name of the interval, e.g. "1m"
+    pub interval: String,
+    pub start: i64,
+    pub end: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    /// Quantity traded
+    pub volume: u64,
+    /// Value traded, price times quantity summed over the trades
+    pub turnover: f64,
+    /// Volume weighted average price
+    pub vwap: f64,
+    pub trade_count: u32,
+}
+
+impl Candle {
+    /// The bar of `length` seconds that `trade` falls in, holding only it.
+    pub fn new(interval: &str, length: i64, trade: &MatchEvent) -> Self {
+        let start = trade.timestamp.div_euclid(length) * length;
+        Self {
+            instrument: trade.instrument.clone(),
+            interval: interval.to_string(),
+            start,
+            end: start + length,
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: trade.quantity as u64,
+            turnover: trade.price * trade.quantity as f64,
+            vwap: trade.price,
+            trade_count: 1,
+        }
+    }
+
+    pub fn add(&mut self, trade: &MatchEvent) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += trade.quantity as u64;
+        self.turnover += trade.price * trade.quantity as f64;
+        if self.volume > 0 {
+            self.vwap = self.turnover / self.volume as f64;
+        }
+        self.trade_count += 1;
+    }
+
+    pub fn contains(&self, timestamp: i64) -> bool {
+        self.start <= timestamp && timestamp < self.end
+    }
+}
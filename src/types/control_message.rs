@@ -0,0 +1,60 @@
+use serde::{Serialize, Deserialize};
+use crate::types::trading_phase::TradingPhase;
+use crate::types::price_bands::PriceBands;
+use crate::types::instrument::Instrument;
+use crate::types::mass_cancel::MassCancelFilter;
+
+/// Commands broadcast on the `control-plane` topic to every matching engine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlMessage {
+    SetTradingPhase {
+        instrument: String,
+        phase: TradingPhase,
+    },
+    SetPriceBands {
+        instrument: String,
+        bands: PriceBands,
+    },
+    /// Adds or replaces an instrument's reference data in every engine
+    UpdateInstrument {
+        definition: Instrument,
+    },
+    /// Moves an instrument, with its books, to another orders partition
+    MigrateInstrument {
+        instrument: String,
+        target_partition: i32,
+    },
+    /// Tells producers and engines which partition an instrument's orders
+    /// now go to. Published once a migration's hand-over is delivered.
+    RouteInstrument {
+        instrument: String,
+        partition: i32,
+    },
+    /// Cancels every order matching `filter` in every partition; each
+    /// partition answers with a `MassCancelReport`
+    MassCancel {
+        filter: MassCancelFilter,
+    },
+    /// Sent periodically by an order-entry gateway; orders tagged with a
+    /// session that stops sending them are cancelled
+    Heartbeat {
+        session: String,
+    },
+}
+
+impl ControlMessage {
+    /// The instrument a command is scoped to, if any. Instrument-scoped
+    /// commands are only applied by the engine owning that instrument.
+    pub fn instrument(&self) -> Option<&str> {
+        match self {
+            ControlMessage::SetTradingPhase { instrument, .. } => Some(instrument),
+            ControlMessage::SetPriceBands { instrument, .. } => Some(instrument),
+            ControlMessage::UpdateInstrument { .. } => None,
+            ControlMessage::MigrateInstrument { instrument, .. } => Some(instrument),
+            ControlMessage::RouteInstrument { instrument, .. } => Some(instrument),
+            ControlMessage::MassCancel { .. } => None,
+            ControlMessage::Heartbeat { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::{HashSet, VecDeque};
+use serde::{Serialize, Deserialize};
+
+const DEFAULT_WINDOW_SECS: i64 = 3600;
+
+/// An order id an engine has processed, and when.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeenOrder {
+    pub id: String,
+    pub instrument: String,
+    pub seen_at: i64,
+}
+
+/// Order ids processed in the last `window_secs` seconds, so an order
+/// resubmitted by a retrying client is rejected instead of trading twice.
+/// A window of 0 turns detection off.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateWindow {
+    pub window_secs: i64,
+    // Oldest first
+    seen: VecDeque<SeenOrder>,
+    ids: HashSet<String>,
+}
+
+impl DuplicateWindow {
+    pub fn new(window_secs: i64) -> Self {
+        Self { window_secs, seen: VecDeque::new(), ids: HashSet::new() }
+    }
+
+    /// Whether `id` was recorded within the window before `now`.
+    pub fn contains(&mut self, id: &str, now: i64) -> bool {
+        self.expire(now);
+        self.ids.contains(id)
+    }
+
+    /// Records `id` as seen at `now`.
+    pub fn record(&mut self, id: &str, instrument: &str, now: i64) {
+        self.expire(now);
+        if self.window_secs > 0 && self.ids.insert(id.to_string()) {
+            self.seen.push_back(SeenOrder { id: id.to_string(), instrument: instrument.to_string(), seen_at: now });
+        }
+    }
+
+    /// Forgets ids seen longer than the window before `now`.
+    pub fn expire(&mut self, now: i64) {
+        while let Some(oldest) = self.seen.front()
+            && oldest.seen_at <= now - self.window_secs
+        {
+            let oldest = self.seen.pop_front().expect("front exists");
+            self.ids.remove(&oldest.id);
+        }
+    }
+
+    /// Removes and returns the ids seen for `instrument`, oldest first.
+    pub fn take_instrument(&mut self, instrument: &str) -> Vec<SeenOrder> {
+        let (taken, kept): (Vec<_>, Vec<_>) = self.seen.drain(..).partition(|seen| seen.instrument == instrument);
+        self.seen = kept.into();
+        for seen in &taken {
+            self.ids.remove(&seen.id);
+        }
+        taken
+    }
+
+    /// Adds ids seen by another engine, keeping the window oldest first.
+    pub fn extend(&mut self, seen: Vec<SeenOrder>) {
+        for order in seen {
+            if self.ids.insert(order.id.clone()) {
+                self.seen.push_back(order);
+            }
+        }
+        self.seen.make_contiguous().sort_by_key(|seen| seen.seen_at);
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+impl Default for DuplicateWindow {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW_SECS)
+    }
+}
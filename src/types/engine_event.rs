@@ -0,0 +1,197 @@
+use serde::{Serialize, Deserialize};
+use crate::types::match_event::MatchEvent;
+use crate::types::order::Order;
+use crate::types::order_request::OrderRequest;
+use crate::types::trading_phase::{SessionState, TradingPhase};
+
+/// Everything the matching engine reports back while processing an order.
+/// Trades are published to `match-events`, phase changes, session states
+/// and indicative auction prices to `market-data`, volatility interruptions to
+/// `control-plane` and forwarded orders and requests back to `orders`; the
+/// other variants describe order state changes and go to `order-events`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EngineEvent {
+    Trade(MatchEvent),
+    StopTriggered {
+        order_id: String,
+        instrument: String,
+        stop_price: f64,
+        trigger_price: f64,
+        #[serde(default)]
+        participant: Option<String>,
+        #[serde(default)]
+        client_order_id: Option<String>,
+        timestamp: i64,
+    },
+    Rejected {
+        order_id: String,
+        instrument: String,
+        reason: RejectReason,
+        #[serde(default)]
+        participant: Option<String>,
+        #[serde(default)]
+        client_order_id: Option<String>,
+        timestamp: i64,
+    },
+    Repriced {
+        order_id: String,
+        instrument: String,
+        original_price: f64,
+        new_price: f64,
+        #[serde(default)]
+        participant: Option<String>,
+        #[serde(default)]
+        client_order_id: Option<String>,
+        timestamp: i64,
+    },
+    Cancelled {
+        order_id: String,
+        instrument: String,
+        remaining_quantity: u32,
+        reason: String,
+        #[serde(default)]
+        participant: Option<String>,
+        #[serde(default)]
+        client_order_id: Option<String>,
+        timestamp: i64,
+    },
+    /// A GTD order reached its expire time, or a DAY order the close
+    Expired {
+        order_id: String,
+        instrument: String,
+        remaining_quantity: u32,
+        time_in_force: String,
+        #[serde(default)]
+        participant: Option<String>,
+        #[serde(default)]
+        client_order_id: Option<String>,
+        timestamp: i64,
+    },
+    /// A resting order's price or remaining quantity was changed by an amend
+    /// request; `kept_priority` is false when it went to the back of the queue
+    Amended {
+        order_id: String,
+        instrument: String,
+        price: f64,
+        remaining_quantity: u32,
+        kept_priority: bool,
+        #[serde(default)]
+        participant: Option<String>,
+        #[serde(default)]
+        client_order_id: Option<String>,
+        timestamp: i64,
+    },
+    /// A cancel or amend request that could not be applied
+    RequestRejected {
+        instrument: String,
+        participant: String,
+        client_order_id: String,
+        reason: RejectReason,
+        timestamp: i64,
+    },
+    PhaseChanged {
+        instrument: String,
+        phase: TradingPhase,
+        timestamp: i64,
+    },
+    /// The instrument entered a new state of its trading session
+    SessionState {
+        instrument: String,
+        state: SessionState,
+        timestamp: i64,
+    },
+    /// Published during the call: the price the book would uncross at now,
+    /// the volume that would execute and the signed surplus (positive for
+    /// buys) left over at that price.
+    IndicativePrice {
+        instrument: String,
+        price: Option<f64>,
+        matched_volume: u32,
+        imbalance: i64,
+        timestamp: i64,
+    },
+    /// Continuous matching stopped because a trade would have printed outside
+    /// the dynamic band; the instrument is in auction until `resume_at`.
+    VolatilityInterruption {
+        instrument: String,
+        trigger_price: f64,
+        reference_price: f64,
+        resume_at: i64,
+        timestamp: i64,
+    },
+    /// An order for an instrument that has migrated to another partition,
+    /// passed on to that partition's orders topic
+    Forwarded {
+        order: Order,
+        partition: i32,
+    },
+    /// A cancel or amend request for a migrated instrument, passed on like
+    /// a forwarded order
+    ForwardedRequest {
+        request: OrderRequest,
+        partition: i32,
+    },
+}
+
+impl EngineEvent {
+    pub fn instrument(&self) -> &str {
+        match self {
+            EngineEvent::Trade(match_event) => &match_event.instrument,
+            EngineEvent::StopTriggered { instrument, .. } => instrument,
+            EngineEvent::Rejected { instrument, .. } => instrument,
+            EngineEvent::Repriced { instrument, .. } => instrument,
+            EngineEvent::Cancelled { instrument, .. } => instrument,
+            EngineEvent::Expired { instrument, .. } => instrument,
+            EngineEvent::Amended { instrument, .. } => instrument,
+            EngineEvent::RequestRejected { instrument, .. } => instrument,
+            EngineEvent::PhaseChanged { instrument, .. } => instrument,
+            EngineEvent::SessionState { instrument, .. } => instrument,
+            EngineEvent::IndicativePrice { instrument, .. } => instrument,
+            EngineEvent::VolatilityInterruption { instrument, .. } => instrument,
+            EngineEvent::Forwarded { order, .. } => &order.instrument,
+            EngineEvent::ForwardedRequest { request, .. } => request.instrument(),
+        }
+    }
+}
+
+/// Why an order was refused by the engine. Rejected orders never rest and
+/// never trade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// A post-only order in "reject" mode would have taken liquidity
+    PostOnlyWouldCross,
+    /// A post-only order could not be repriced to a passive price
+    PostOnlyRepriceFailed,
+    /// Less than the order's minimum quantity was available to fill
+    MinQuantityNotMet,
+    /// The order's attributes are inconsistent, e.g. a post-only market order
+    InvalidAttributes,
+    /// The instrument is closed for trading
+    InstrumentClosed,
+    /// The order type is not accepted in the current trading phase, e.g. a
+    /// market order during an auction call
+    NotAllowedInPhase,
+    /// The limit price is outside the instrument's static price band
+    PriceOutsideBand,
+    /// The instrument is not in the instrument registry
+    UnknownInstrument,
+    /// The instrument's trading status is halted
+    InstrumentHalted,
+    /// The limit or stop price is not a multiple of the tick size
+    InvalidTickSize,
+    /// The quantity is not a multiple of the lot size
+    InvalidLotSize,
+    /// The quantity is outside the instrument's minimum and maximum
+    QuantityOutOfRange,
+    /// An order with the same id was already processed within the
+    /// duplicate detection window
+    DuplicateOrderId,
+    /// The participant already has a live order with this client order id
+    DuplicateClientOrderId,
+    /// A cancel or amend request named no live order of the participant
+    UnknownOrder,
+    /// A GTD order's expire time has already passed
+    ExpireTimeInPast,
+}
@@ -0,0 +1,78 @@
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+use crate::types::order::Order;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// A resting order due to expire, ordered by expiry time.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ScheduledExpiry {
+    pub expire_at: i64,
+    pub instrument: String,
+    pub order_id: String,
+}
+
+/// When resting GTD and DAY orders expire. GTD orders expire at their
+/// expire time, DAY orders at the first daily close after they arrived.
+/// Entries for orders that have since filled or been cancelled are left in
+/// place and come to nothing when due.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExpirySchedule {
+    /// Seconds after midnight UTC that DAY orders expire at
+    pub day_close_secs: i64,
+    pending: BTreeSet<ScheduledExpiry>,
+}
+
+impl ExpirySchedule {
+    pub fn new(day_close_secs: i64) -> Self {
+        Self { day_close_secs, pending: BTreeSet::new() }
+    }
+
+    /// When `order`, resting at `now`, expires; `None` for orders good
+    /// till cancelled.
+    pub fn expire_at(&self, order: &Order, now: i64) -> Option<i64> {
+        match order.time_in_force.as_deref() {
+            Some("gtd") => order.expire_time,
+            Some("day") => {
+                let close = now.div_euclid(SECONDS_PER_DAY) * SECONDS_PER_DAY + self.day_close_secs;
+                Some(if close > now { close } else { close + SECONDS_PER_DAY })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn schedule(&mut self, order: &Order, now: i64) {
+        if let Some(expire_at) = self.expire_at(order, now) {
+            self.pending.insert(ScheduledExpiry {
+                expire_at,
+                instrument: order.instrument.clone(),
+                order_id: order.id.clone(),
+            });
+        }
+    }
+
+    /// Removes and returns the expiries due at or before `now`, earliest
+    /// first.
+    pub fn pop_due(&mut self, now: i64) -> Vec<ScheduledExpiry> {
+        let mut due = Vec::new();
+        while let Some(first) = self.pending.first()
+            && first.expire_at <= now
+        {
+            due.extend(self.pending.pop_first());
+        }
+        due
+    }
+
+    /// Removes and returns the expiries scheduled for `instrument`.
+    pub fn take_instrument(&mut self, instrument: &str) -> Vec<ScheduledExpiry> {
+        let (taken, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|expiry| expiry.instrument == instrument);
+        self.pending = kept.into_iter().collect();
+        taken
+    }
+
+    pub fn extend(&mut self, expiries: Vec<ScheduledExpiry>) {
+        self.pending.extend(expiries);
+    }
+}
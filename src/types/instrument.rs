@@ -0,0 +1,55 @@
+use serde::{Serialize, Deserialize};
+
+/// How a price level's resting quantity is shared out when an aggressive
+/// order trades against it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AllocationStrategy {
+    /// In proportion to each resting order's size
+    #[default]
+    ProRata,
+    /// Strict time priority
+    Fifo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstrumentStatus {
+    #[default]
+    Active,
+    /// Known to the exchange but not accepting orders
+    Halted,
+}
+
+/// Reference data for a tradable instrument.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Instrument {
+    pub symbol: String,
+    pub tick_size: f64,
+    pub lot_size: u32,
+    pub min_quantity: u32,
+    pub max_quantity: u32,
+    /// Number of decimal places prices are kept to
+    pub price_precision: u32,
+    pub currency: String,
+    #[serde(default)]
+    pub allocation: AllocationStrategy,
+    #[serde(default)]
+    pub status: InstrumentStatus,
+}
+
+impl Instrument {
+    /// Whether `price` is a whole number of ticks.
+    pub fn is_on_tick(&self, price: f64) -> bool {
+        let ticks = price / self.tick_size;
+        (ticks - ticks.round()).abs() < 1e-6
+    }
+
+    pub fn is_valid_quantity(&self, quantity: u32) -> bool {
+        quantity >= self.min_quantity && quantity <= self.max_quantity
+    }
+
+    pub fn is_whole_lots(&self, quantity: u32) -> bool {
+        self.lot_size > 0 && quantity.is_multiple_of(self.lot_size)
+    }
+}
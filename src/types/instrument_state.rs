@@ -0,0 +1,40 @@
+use serde::{Serialize, Deserialize};
+use crate::types::duplicate_window::SeenOrder;
+use crate::types::expiry_schedule::ScheduledExpiry;
+use crate::types::instrument::Instrument;
+use crate::types::order_book::OrderBook;
+use crate::types::price_bands::PriceBands;
+use crate::types::trading_phase::{SessionState, TradingPhase};
+use crate::types::trigger_book::TriggerBook;
+
+/// Everything an engine holds for one instrument, shipped to another
+/// partition when the instrument is migrated there.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstrumentState {
+    pub definition: Option<Instrument>,
+    pub order_book: Option<OrderBook>,
+    pub trigger_book: Option<TriggerBook>,
+    pub phase: Option<TradingPhase>,
+    pub price_bands: Option<PriceBands>,
+    /// When a volatility auction in progress ends
+    pub volatility_auction: Option<i64>,
+    /// Recent order ids, so resubmissions are still caught after the move
+    #[serde(default)]
+    pub seen_orders: Vec<SeenOrder>,
+    /// When its GTD and DAY orders expire
+    #[serde(default)]
+    pub expiries: Vec<ScheduledExpiry>,
+    /// The session state last applied from the calendar
+    #[serde(default)]
+    pub session_state: Option<SessionState>,
+}
+
+/// An instrument's state handed over by the partition it is migrating
+/// from. Sent on the target's orders partition, ahead of any order
+/// forwarded after it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstrumentHandover {
+    pub instrument: String,
+    pub source_partition: i32,
+    pub state: InstrumentState,
+}
@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use crate::types::engine_event::EngineEvent;
+use crate::types::order::Order;
+
+/// Which resting and waiting stop orders a mass cancel takes out. Orders
+/// must match every criterion given; a participant, account or session is
+/// required so a mass cancel can never empty the whole market.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MassCancelFilter {
+    #[serde(default)]
+    pub participant: Option<String>,
+    #[serde(default)]
+    pub account: Option<String>,
+    #[serde(default)]
+    pub session: Option<String>,
+    #[serde(default)]
+    pub instrument: Option<String>,
+    #[serde(default)]
+    pub side: Option<String>, // "buy" or "sell"
+}
+
+impl MassCancelFilter {
+    pub fn is_valid(&self) -> bool {
+        (self.participant.is_some() || self.account.is_some() || self.session.is_some())
+            && self.side.as_deref().is_none_or(|side| side == "buy" || side == "sell")
+    }
+
+    pub fn matches(&self, order: &Order) -> bool {
+        self.is_valid()
+            && self.participant.as_ref().is_none_or(|participant| order.participant.as_ref() == Some(participant))
+            && self.account.as_ref().is_none_or(|account| order.account.as_ref() == Some(account))
+            && self.session.as_ref().is_none_or(|session| order.session.as_ref() == Some(session))
+            && self.instrument.as_ref().is_none_or(|instrument| order.instrument == *instrument)
+            && self.side.as_ref().is_none_or(|side| order.side == *side)
+    }
+}
+
+/// What one partition cancelled for a mass cancel, published on the
+/// `control-plane` topic. Every partition reports, even when it had
+/// nothing to cancel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MassCancelReport {
+    pub partition: i32,
+    pub filter: MassCancelFilter,
+    pub cancelled: u32,
+    /// Cancelled orders per instrument
+    pub instruments: BTreeMap<String, u32>,
+    pub order_ids: Vec<String>,
+    pub timestamp: i64,
+}
+
+impl MassCancelReport {
+    /// Summarises the cancellations among the events a mass cancel produced.
+    pub fn new(partition: i32, filter: &MassCancelFilter, events: &[EngineEvent], timestamp: i64) -> Self {
+        let mut instruments = BTreeMap::new();
+        let mut order_ids = Vec::new();
+        for event in events {
+            if let EngineEvent::Cancelled { order_id, instrument, .. } = event {
+                *instruments.entry(instrument.clone()).or_insert(0) += 1;
+                order_ids.push(order_id.clone());
+            }
+        }
+
+        Self {
+            partition,
+            filter: filter.clone(),
+            cancelled: order_ids.len() as u32,
+            instruments,
+            order_ids,
+            timestamp,
+        }
+    }
+}
@@ -8,7 +8,47 @@ pub struct MatchEvent {
     pub seller_order_id: String,
     pub price: f64,
     pub quantity: u32,
+    /// Seconds since the epoch; see `timestamp_ns` for the precise time
     pub timestamp: i64,
+    /// Nanoseconds since the epoch
+    #[serde(default)]
+    pub timestamp_ns: i64,
+    /// Position of the trade among the instrument's trades, from 1
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(default)]
+    pub buyer_account: Option<String>,
+    #[serde(default)]
+    pub seller_account: Option<String>,
+    /// Participant and client order id of each side, when the order had them
+    #[serde(default)]
+    pub buyer_participant: Option<String>,
+    #[serde(default)]
+    pub seller_participant: Option<String>,
+    #[serde(default)]
+    pub buyer_client_order_id: Option<String>,
+    #[serde(default)]
+    pub seller_client_order_id: Option<String>,
+    /// Side of the order that took liquidity; `None` for auction trades
+    #[serde(default)]
+    pub aggressor_side: Option<String>,
+    /// The order that took liquidity and the resting order it traded
+    /// against; both `None` for auction trades
+    #[serde(default)]
+    pub aggressive_order_id: Option<String>,
+    #[serde(default)]
+    pub passive_order_id: Option<String>,
+    /// Limit price of the aggressive order; `None` for market orders
+    #[serde(default)]
+    pub aggressive_order_price: Option<f64>,
+    /// What is left of the resting order after this trade
+    #[serde(default)]
+    pub passive_remaining_quantity: Option<u32>,
+    /// Fees charged to each side, negative for a rebate
+    #[serde(default)]
+    pub buyer_fee: f64,
+    #[serde(default)]
+    pub seller_fee: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,4 +56,10 @@ pub struct PartialFill {
     pub order_id: String,
     pub filled_quantity: u32,
     pub remaining_quantity: u32,
+    #[serde(default)]
+    pub account: Option<String>,
+    #[serde(default)]
+    pub participant: Option<String>,
+    #[serde(default)]
+    pub client_order_id: Option<String>,
 }
@@ -1,3 +1,17 @@
 pub mod order;
 pub mod match_event;
-pub mod order_book;
\ No newline at end of file
+pub mod order_book;
+pub mod trigger_book;
+pub mod engine_event;
+pub mod trading_phase;
+pub mod control_message;
+pub mod price_bands;
+pub mod instrument;
+pub mod instrument_state;
+pub mod position;
+pub mod duplicate_window;
+pub mod order_request;
+pub mod mass_cancel;
+pub mod session_watchdog;
+pub mod expiry_schedule;
+pub mod candle;
\ No newline at end of file
@@ -9,6 +9,30 @@ pub struct Order {
     pub quantity: u32,
     pub original_quantity: u32,
     pub timestamp: i64,
+    #[serde(default = "default_order_type")]
+    pub order_type: String, // "limit", "market", "stop" or "stop_limit"
+    #[serde(default)]
+    pub stop_price: Option<f64>,
+    #[serde(default)]
+    pub post_only: Option<String>, // "reject" or "reprice" if the order would take liquidity
+    #[serde(default)]
+    pub min_quantity: Option<u32>, // minimum quantity that must fill on arrival
+    #[serde(default)]
+    pub account: Option<String>, // account the ledger books fills to
+    #[serde(default)]
+    pub participant: Option<String>, // firm or session that sent the order
+    #[serde(default)]
+    pub client_order_id: Option<String>, // the participant's own id, unique among its live orders
+    #[serde(default)]
+    pub session: Option<String>, // order-entry session; its orders are cancelled if it disconnects
+    #[serde(default)]
+    pub time_in_force: Option<String>, // "gtc" (the default), "day" or "gtd"
+    #[serde(default)]
+    pub expire_time: Option<i64>, // seconds since the epoch a "gtd" order expires at
+}
+
+fn default_order_type() -> String {
+    "limit".to_string()
 }
 
 impl Order {
@@ -21,9 +45,77 @@ impl Order {
             original_quantity: quantity,
             quantity,
             timestamp,
+            order_type: default_order_type(),
+            stop_price: None,
+            post_only: None,
+            min_quantity: None,
+            account: None,
+            participant: None,
+            client_order_id: None,
+            session: None,
+            time_in_force: None,
+            expire_time: None,
         }
     }
 
+    pub fn with_order_type(mut self, order_type: &str) -> Self {
+        self.order_type = order_type.to_string();
+        self
+    }
+
+    pub fn with_stop_price(mut self, stop_price: f64) -> Self {
+        self.stop_price = Some(stop_price);
+        self
+    }
+
+    pub fn with_post_only(mut self, mode: &str) -> Self {
+        self.post_only = Some(mode.to_string());
+        self
+    }
+
+    pub fn with_min_quantity(mut self, min_quantity: u32) -> Self {
+        self.min_quantity = Some(min_quantity);
+        self
+    }
+
+    pub fn with_account(mut self, account: &str) -> Self {
+        self.account = Some(account.to_string());
+        self
+    }
+
+    pub fn with_client_order_id(mut self, participant: &str, client_order_id: &str) -> Self {
+        self.participant = Some(participant.to_string());
+        self.client_order_id = Some(client_order_id.to_string());
+        self
+    }
+
+    pub fn with_session(mut self, session: &str) -> Self {
+        self.session = Some(session.to_string());
+        self
+    }
+
+    pub fn with_time_in_force(mut self, time_in_force: &str) -> Self {
+        self.time_in_force = Some(time_in_force.to_string());
+        self
+    }
+
+    /// Makes the order good till `expire_time`.
+    pub fn with_expire_time(mut self, expire_time: i64) -> Self {
+        self.time_in_force = Some("gtd".to_string());
+        self.expire_time = Some(expire_time);
+        self
+    }
+
+    pub fn is_day(&self) -> bool {
+        self.time_in_force.as_deref() == Some("day")
+    }
+
+    /// The participant and client order id the order can be referenced by,
+    /// if it carries both.
+    pub fn client_key(&self) -> Option<(&str, &str)> {
+        self.participant.as_deref().zip(self.client_order_id.as_deref())
+    }
+
     pub fn is_buy(&self) -> bool {
         self.side == "buy"
     }
@@ -32,6 +124,41 @@ impl Order {
         self.side == "sell"
     }
 
+    pub fn is_market(&self) -> bool {
+        self.order_type == "market"
+    }
+
+    pub fn is_post_only(&self) -> bool {
+        self.post_only.is_some()
+    }
+
+    /// Stop and stop-limit orders wait in the trigger book until the last
+    /// trade price crosses their stop price.
+    pub fn is_conditional(&self) -> bool {
+        self.order_type == "stop" || self.order_type == "stop_limit"
+    }
+
+    /// Whether a trade at `last_price` activates this stop order. Buy stops
+    /// trigger when the market trades at or above the stop price, sell stops
+    /// at or below it.
+    pub fn is_triggered_by(&self, last_price: f64) -> bool {
+        match self.stop_price {
+            Some(stop_price) if self.is_buy() => last_price >= stop_price,
+            Some(stop_price) => last_price <= stop_price,
+            None => false,
+        }
+    }
+
+    /// Converts a triggered stop into the order it becomes once active: a
+    /// stop turns into a market order and a stop-limit into a limit order.
+    pub fn activate(mut self) -> Self {
+        self.order_type = match self.order_type.as_str() {
+            "stop" => "market".to_string(),
+            _ => "limit".to_string(),
+        };
+        self
+    }
+
     pub fn fill(&mut self, quantity: u32) -> u32 {
         let filled = std::cmp::min(self.quantity, quantity);
         self.quantity -= filled;
@@ -1,7 +1,10 @@
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use serde::{Serialize, Deserialize};
 use crate::types::order::Order;
+use crate::types::match_event::PartialFill;
+use crate::types::instrument::AllocationStrategy;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PriceLevel {
     pub price: f64,
     pub orders: VecDeque<Order>,
@@ -30,32 +33,181 @@ impl PriceLevel {
     pub fn is_empty(&self) -> bool {
         self.orders.is_empty()
     }
+
+    /// Splits `quantity` across the resting orders using `strategy`. Returns
+    /// one allocation per order in queue order.
+    pub fn allocations(&self, strategy: AllocationStrategy, quantity: u32) -> Vec<u32> {
+        match strategy {
+            AllocationStrategy::ProRata => self.pro_rata_allocations(quantity),
+            AllocationStrategy::Fifo => self.fifo_allocations(quantity),
+        }
+    }
+
+    /// Fills orders in queue order until `quantity` is used up.
+    pub fn fifo_allocations(&self, quantity: u32) -> Vec<u32> {
+        let mut remaining = quantity;
+        self.orders
+            .iter()
+            .map(|order| {
+                let allocated = remaining.min(order.quantity);
+                remaining -= allocated;
+                allocated
+            })
+            .collect()
+    }
+
+    /// Splits `quantity` across the resting orders in proportion to their
+    /// size. Returns one allocation per order in queue order.
+    pub fn pro_rata_allocations(&self, quantity: u32) -> Vec<u32> {
+        let mut allocations = Vec::with_capacity(self.orders.len());
+        let mut total_allocated = 0u32;
+
+        if self.total_quantity == 0 {
+            return vec![0; self.orders.len()];
+        }
+
+        // First pass: calculate proportional allocations
+        for order in &self.orders {
+            if order.is_filled() {
+                allocations.push(0);
+                continue;
+            }
+
+            let proportion = order.quantity as f64 / self.total_quantity as f64;
+            let allocated = ((quantity as f64 * proportion).floor() as u32)
+                .min(order.quantity);
+
+            allocations.push(allocated);
+            total_allocated += allocated;
+        }
+
+        // Second pass: distribute any remaining quantity due to rounding
+        let mut remaining_to_distribute = quantity.saturating_sub(total_allocated);
+        let mut order_index = 0;
+
+        while remaining_to_distribute > 0 && order_index < self.orders.len() {
+            let order = &self.orders[order_index];
+            if !order.is_filled() && allocations[order_index] < order.quantity {
+                let additional = std::cmp::min(remaining_to_distribute, order.quantity - allocations[order_index]);
+                allocations[order_index] += additional;
+                remaining_to_distribute -= additional;
+            }
+            order_index += 1;
+        }
+
+        allocations
+    }
+}
+
+/// Where a resting order entered with a client order id sits in the book.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestingOrderRef {
+    pub order_id: String,
+    pub buy: bool,
+    pub price_key: i64,
+}
+
+/// Result of an auction equilibrium calculation: the single price the book
+/// uncrosses at, the volume that executes there and the buy and sell interest
+/// at that price.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Equilibrium {
+    pub price: f64,
+    pub volume: u32,
+    pub buy_quantity: u32,
+    pub sell_quantity: u32,
 }
 
-#[derive(Debug)]
+impl Equilibrium {
+    /// Unmatched quantity at the equilibrium price, positive for surplus buys.
+    pub fn imbalance(&self) -> i64 {
+        self.buy_quantity as i64 - self.sell_quantity as i64
+    }
+}
+
+/// A broken order book invariant, as found by `OrderBook::check_invariants`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "violation", rename_all = "snake_case")]
+pub enum BookViolation {
+    /// The best bid is at or above the best ask
+    Crossed { best_bid: f64, best_ask: f64 },
+    EmptyLevel { side: String, price: f64 },
+    /// A level's total differs from the sum of its orders
+    StaleTotal { side: String, price: f64, total_quantity: u32, orders_quantity: u64 },
+    /// An order on the wrong side, or at a level that is not its price
+    MisplacedOrder { order_id: String, side: String, price: f64 },
+    FilledOrder { order_id: String },
+    OverFilled { order_id: String, quantity: u32, original_quantity: u32 },
+    /// A resting order with a client order id missing from the index, or
+    /// indexed at the wrong place
+    UnindexedOrder { order_id: String, participant: String, client_order_id: String },
+    /// An index entry without the live resting order it points to
+    StaleIndexEntry { participant: String, client_order_id: String, order_id: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBook {
     pub instrument: String,
     // BTreeMap for sorted price levels - bids descending, asks ascending
-    pub bids: BTreeMap<i64, PriceLevel>, // price as fixed-point integer (price * 100)
+    pub bids: BTreeMap<i64, PriceLevel>, // price as fixed-point integer (price * price_scale)
     pub asks: BTreeMap<i64, PriceLevel>,
+    pub last_trade_price: Option<f64>,
+    pub price_scale: i64,
+    pub allocation: AllocationStrategy,
+    // Sequence number of the last trade in this instrument
+    #[serde(default)]
+    pub trade_sequence: u64,
+    // Resting orders by participant, then client order id
+    #[serde(default)]
+    pub client_orders: HashMap<String, HashMap<String, RestingOrderRef>>,
 }
 
 impl OrderBook {
     pub fn new(instrument: String) -> Self {
+        Self::with_price_precision(instrument, 2)
+    }
+
+    /// A book keeping prices to `price_precision` decimal places.
+    pub fn with_price_precision(instrument: String, price_precision: u32) -> Self {
         Self {
             instrument,
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
+            last_trade_price: None,
+            price_scale: 10i64.pow(price_precision),
+            allocation: AllocationStrategy::default(),
+            trade_sequence: 0,
+            client_orders: HashMap::new(),
         }
     }
 
-    fn price_to_key(price: f64) -> i64 {
-        (price * 100.0).round() as i64
+    pub fn next_trade_sequence(&mut self) -> u64 {
+        self.trade_sequence += 1;
+        self.trade_sequence
+    }
+
+    pub fn price_to_key(&self, price: f64) -> i64 {
+        (price * self.price_scale as f64).round() as i64
+    }
+
+    pub fn key_to_price(&self, key: i64) -> f64 {
+        key as f64 / self.price_scale as f64
     }
 
     pub fn add_order(&mut self, order: Order) {
-        let price_key = Self::price_to_key(order.price);
-        
+        let price_key = self.price_to_key(order.price);
+
+        if let Some((participant, client_order_id)) = order.client_key() {
+            self.client_orders
+                .entry(participant.to_string())
+                .or_default()
+                .insert(client_order_id.to_string(), RestingOrderRef {
+                    order_id: order.id.clone(),
+                    buy: order.is_buy(),
+                    price_key,
+                });
+        }
+
         if order.is_buy() {
             self.bids
                 .entry(price_key)
@@ -69,12 +221,300 @@ impl OrderBook {
         }
     }
 
+    /// The live resting order `participant` entered as `client_order_id`.
+    pub fn client_order(&self, participant: &str, client_order_id: &str) -> Option<&Order> {
+        let resting = self.client_orders.get(participant)?.get(client_order_id)?;
+        let side = if resting.buy { &self.bids } else { &self.asks };
+        side.get(&resting.price_key)?
+            .orders
+            .iter()
+            .find(|order| order.id == resting.order_id && !order.is_filled())
+    }
+
+    /// Takes a participant's resting order out of the book.
+    pub fn remove_client_order(&mut self, participant: &str, client_order_id: &str) -> Option<Order> {
+        let resting = self.forget_client_order(participant, client_order_id)?;
+        let side = if resting.buy { &mut self.bids } else { &mut self.asks };
+        let level = side.get_mut(&resting.price_key)?;
+        let position = level.orders.iter().position(|order| order.id == resting.order_id)?;
+        let order = level.orders.remove(position)?;
+        level.total_quantity -= order.quantity;
+        if level.is_empty() {
+            side.remove(&resting.price_key);
+        }
+        Some(order).filter(|order| !order.is_filled())
+    }
+
+    /// Lowers a participant's resting order to `quantity` in place, keeping
+    /// its position in the queue. Returns false if there is no such order or
+    /// `quantity` is not a reduction.
+    pub fn reduce_client_order(&mut self, participant: &str, client_order_id: &str, quantity: u32) -> bool {
+        let Some(resting) = self.client_orders.get(participant).and_then(|orders| orders.get(client_order_id)) else {
+            return false;
+        };
+        let side = if resting.buy { &mut self.bids } else { &mut self.asks };
+        let Some(level) = side.get_mut(&resting.price_key) else {
+            return false;
+        };
+        let Some(order) = level.orders.iter_mut().find(|order| order.id == resting.order_id) else {
+            return false;
+        };
+        if quantity == 0 || quantity > order.quantity {
+            return false;
+        }
+
+        let reduction = order.quantity - quantity;
+        order.quantity = quantity;
+        order.original_quantity -= reduction;
+        level.total_quantity -= reduction;
+        true
+    }
+
+    /// Takes every resting order matching `predicate` out of the book,
+    /// bids first, each side in price then queue order.
+    pub fn remove_orders(&mut self, predicate: impl Fn(&Order) -> bool) -> Vec<Order> {
+        let mut removed = Vec::new();
+        for level in self.bids.values_mut().rev().chain(self.asks.values_mut()) {
+            let (taken, kept): (Vec<_>, Vec<_>) = level.orders.drain(..).partition(|order| predicate(order));
+            level.orders = kept.into();
+            level.total_quantity = level.orders.iter().map(|order| order.quantity).sum();
+            removed.extend(taken.into_iter().filter(|order| !order.is_filled()));
+        }
+        self.cleanup_empty_levels();
+
+        for order in &removed {
+            if let Some((participant, client_order_id)) = order.client_key() {
+                let (participant, client_order_id) = (participant.to_string(), client_order_id.to_string());
+                self.forget_client_order(&participant, &client_order_id);
+            }
+        }
+        removed
+    }
+
+    /// Drops a participant's client order id from the index, once the order
+    /// has left the book.
+    pub fn forget_client_order(&mut self, participant: &str, client_order_id: &str) -> Option<RestingOrderRef> {
+        let orders = self.client_orders.get_mut(participant)?;
+        let resting = orders.remove(client_order_id);
+        if orders.is_empty() {
+            self.client_orders.remove(participant);
+        }
+        resting
+    }
+
     pub fn get_best_bid(&self) -> Option<f64> {
-        self.bids.keys().max().map(|&key| key as f64 / 100.0)
+        self.bids.keys().max().map(|&key| self.key_to_price(key))
     }
 
     pub fn get_best_ask(&self) -> Option<f64> {
-        self.asks.keys().min().map(|&key| key as f64 / 100.0)
+        self.asks.keys().min().map(|&key| self.key_to_price(key))
+    }
+
+    /// Whether `order` would trade against the opposite side on arrival.
+    pub fn crosses(&self, order: &Order) -> bool {
+        if order.is_buy() {
+            self.get_best_ask().is_some_and(|ask| order.is_market() || ask <= order.price)
+        } else {
+            self.get_best_bid().is_some_and(|bid| order.is_market() || bid >= order.price)
+        }
+    }
+
+    /// Resting quantity on the opposite side at prices `order` can trade at.
+    pub fn matchable_quantity(&self, order: &Order) -> u32 {
+        if order.is_buy() {
+            self.asks.values()
+                .filter(|level| order.is_market() || level.price <= order.price)
+                .map(|level| level.total_quantity)
+                .sum()
+        } else {
+            self.bids.values()
+                .filter(|level| order.is_market() || level.price >= order.price)
+                .map(|level| level.total_quantity)
+                .sum()
+        }
+    }
+
+    /// Finds the auction price: maximum executable volume, then minimum
+    /// imbalance, then closest to `reference_price`, then the lower price.
+    /// Returns `None` when the book is not crossed.
+    pub fn equilibrium(&self, reference_price: Option<f64>) -> Option<Equilibrium> {
+        let reference_key = reference_price.map(|price| self.price_to_key(price));
+        let mut candidates: Vec<i64> = self.bids.keys().chain(self.asks.keys()).cloned().collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut best: Option<(i64, Equilibrium)> = None;
+        for key in candidates {
+            let buy_quantity: u32 = self.bids.range(key..).map(|(_, level)| level.total_quantity).sum();
+            let sell_quantity: u32 = self.asks.range(..=key).map(|(_, level)| level.total_quantity).sum();
+            let volume = buy_quantity.min(sell_quantity);
+            if volume == 0 {
+                continue;
+            }
+
+            let candidate = Equilibrium {
+                price: self.key_to_price(key),
+                volume,
+                buy_quantity,
+                sell_quantity,
+            };
+
+            let is_better = match &best {
+                None => true,
+                Some((best_key, current)) => {
+                    let distance = |k: i64| reference_key.map_or(0, |r| (k - r).abs());
+                    (candidate.volume, std::cmp::Reverse(candidate.imbalance().abs()), std::cmp::Reverse(distance(key)))
+                        > (current.volume, std::cmp::Reverse(current.imbalance().abs()), std::cmp::Reverse(distance(*best_key)))
+                }
+            };
+
+            if is_better {
+                best = Some((key, candidate));
+            }
+        }
+
+        best.map(|(_, equilibrium)| equilibrium)
+    }
+
+    /// Fills the buy and sell side of an auction at `equilibrium`, in price
+    /// priority and by the book's allocation strategy within the marginal
+    /// level. Returns the
+    /// buy and sell fills in execution order; both sides add up to the
+    /// equilibrium volume.
+    pub fn auction_fills(&mut self, equilibrium: &Equilibrium) -> (Vec<PartialFill>, Vec<PartialFill>) {
+        let price_key = self.price_to_key(equilibrium.price);
+        let allocation = self.allocation;
+        let buy_fills = Self::fill_levels(self.bids.range_mut(price_key..).rev().map(|(_, level)| level), equilibrium.volume, allocation);
+        let sell_fills = Self::fill_levels(self.asks.range_mut(..=price_key).map(|(_, level)| level), equilibrium.volume, allocation);
+
+        for fill in buy_fills.iter().chain(&sell_fills).filter(|fill| fill.remaining_quantity == 0) {
+            if let (Some(participant), Some(client_order_id)) = (&fill.participant, &fill.client_order_id) {
+                self.forget_client_order(participant, client_order_id);
+            }
+        }
+
+        (buy_fills, sell_fills)
+    }
+
+    fn fill_levels<'a>(levels: impl Iterator<Item = &'a mut PriceLevel>, volume: u32, allocation: AllocationStrategy) -> Vec<PartialFill> {
+        let mut fills = Vec::new();
+        let mut remaining = volume;
+
+        for level in levels {
+            if remaining == 0 {
+                break;
+            }
+
+            let level_quantity = remaining.min(level.total_quantity);
+            let allocations = level.allocations(allocation, level_quantity);
+            for (order, allocated) in level.orders.iter_mut().zip(allocations) {
+                let filled = order.fill(allocated);
+                if filled > 0 {
+                    fills.push(PartialFill {
+                        order_id: order.id.clone(),
+                        filled_quantity: filled,
+                        remaining_quantity: order.quantity,
+                        account: order.account.clone(),
+                        participant: order.participant.clone(),
+                        client_order_id: order.client_order_id.clone(),
+                    });
+                }
+            }
+
+            level.remove_filled_orders();
+            remaining -= level_quantity;
+        }
+
+        fills
+    }
+
+    /// Every broken invariant of the book: a crossed book unless `may_cross`
+    /// (for instruments not in continuous trading), level totals that do not
+    /// add up, empty levels, orders that are filled, over-filled or in the
+    /// wrong place, and a client order index out of step with the book.
+    pub fn check_invariants(&self, may_cross: bool) -> Vec<BookViolation> {
+        let mut violations = Vec::new();
+
+        if !may_cross
+            && let (Some(best_bid), Some(best_ask)) = (self.get_best_bid(), self.get_best_ask())
+            && best_bid >= best_ask
+        {
+            violations.push(BookViolation::Crossed { best_bid, best_ask });
+        }
+
+        let sides = [("buy", &self.bids), ("sell", &self.asks)];
+        for (side, levels) in sides {
+            for (&price_key, level) in levels {
+                let price = self.key_to_price(price_key);
+                if level.is_empty() {
+                    violations.push(BookViolation::EmptyLevel { side: side.to_string(), price });
+                }
+                let orders_quantity: u64 = level.orders.iter().map(|order| order.quantity as u64).sum();
+                if orders_quantity != level.total_quantity as u64 {
+                    violations.push(BookViolation::StaleTotal {
+                        side: side.to_string(),
+                        price,
+                        total_quantity: level.total_quantity,
+                        orders_quantity,
+                    });
+                }
+
+                for order in &level.orders {
+                    if order.side != side || self.price_to_key(order.price) != price_key {
+                        violations.push(BookViolation::MisplacedOrder {
+                            order_id: order.id.clone(),
+                            side: side.to_string(),
+                            price,
+                        });
+                    }
+                    if order.is_filled() {
+                        violations.push(BookViolation::FilledOrder { order_id: order.id.clone() });
+                    }
+                    if order.quantity > order.original_quantity {
+                        violations.push(BookViolation::OverFilled {
+                            order_id: order.id.clone(),
+                            quantity: order.quantity,
+                            original_quantity: order.original_quantity,
+                        });
+                    }
+
+                    if !order.is_filled()
+                        && let Some((participant, client_order_id)) = order.client_key()
+                    {
+                        let indexed = self.client_orders
+                            .get(participant)
+                            .and_then(|orders| orders.get(client_order_id))
+                            .is_some_and(|resting| {
+                                resting.order_id == order.id && resting.buy == order.is_buy() && resting.price_key == price_key
+                            });
+                        if !indexed {
+                            violations.push(BookViolation::UnindexedOrder {
+                                order_id: order.id.clone(),
+                                participant: participant.to_string(),
+                                client_order_id: client_order_id.to_string(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        for (participant, orders) in &self.client_orders {
+            for (client_order_id, resting) in orders {
+                let live = self.client_order(participant, client_order_id).is_some_and(|order| {
+                    order.client_key() == Some((participant.as_str(), client_order_id.as_str()))
+                });
+                if !live {
+                    violations.push(BookViolation::StaleIndexEntry {
+                        participant: participant.clone(),
+                        client_order_id: client_order_id.clone(),
+                        order_id: resting.order_id.clone(),
+                    });
+                }
+            }
+        }
+
+        violations
     }
 
     pub fn cleanup_empty_levels(&mut self) {
@@ -0,0 +1,49 @@
+use serde::{Serialize, Deserialize};
+
+/// Requests against a participant's resting order, sent on the `orders`
+/// topic alongside new orders. The order is identified by the participant
+/// and the client order id it was entered with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum OrderRequest {
+    Cancel {
+        instrument: String,
+        participant: String,
+        client_order_id: String,
+    },
+    /// Changes the price and/or remaining quantity. Reducing the quantity at
+    /// the same price keeps queue priority; anything else re-enters the
+    /// order at the back of the queue. A quantity of zero cancels it.
+    Amend {
+        instrument: String,
+        participant: String,
+        client_order_id: String,
+        #[serde(default)]
+        price: Option<f64>,
+        #[serde(default)]
+        quantity: Option<u32>,
+    },
+}
+
+impl OrderRequest {
+    pub fn instrument(&self) -> &str {
+        match self {
+            OrderRequest::Cancel { instrument, .. } => instrument,
+            OrderRequest::Amend { instrument, .. } => instrument,
+        }
+    }
+
+    pub fn participant(&self) -> &str {
+        match self {
+            OrderRequest::Cancel { participant, .. } => participant,
+            OrderRequest::Amend { participant, .. } => participant,
+        }
+    }
+
+    pub fn client_order_id(&self) -> &str {
+        match self {
+            OrderRequest::Cancel { client_order_id, .. } => client_order_id,
+            OrderRequest::Amend { client_order_id, .. } => client_order_id,
+        }
+    }
+}
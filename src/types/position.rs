@@ -0,0 +1,76 @@
+use serde::{Serialize, Deserialize};
+
+/// An account's holding in one instrument, valued at average cost.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub account: String,
+    pub instrument: String,
+    pub currency: String,
+    pub quantity: i64, // negative when short
+    pub average_price: f64,
+    pub realized_pnl: f64,
+    /// Fees paid on fills, net of rebates; not part of the P&L figures
+    #[serde(default)]
+    pub fees: f64,
+    #[serde(default)]
+    pub last_price: Option<f64>,
+    #[serde(default)]
+    pub unrealized_pnl: f64,
+}
+
+impl Position {
+    pub fn new(account: String, instrument: String, currency: String) -> Self {
+        Self {
+            account,
+            instrument,
+            currency,
+            quantity: 0,
+            average_price: 0.0,
+            realized_pnl: 0.0,
+            fees: 0.0,
+            last_price: None,
+            unrealized_pnl: 0.0,
+        }
+    }
+
+    /// Books a fill of `quantity` at `price`, positive for a buy and
+    /// negative for a sell. The part that reduces the position realizes
+    /// P&L against the average price; the part that extends it, or opens
+    /// the other side, moves the average price.
+    pub fn fill(&mut self, quantity: i64, price: f64) {
+        if quantity == 0 {
+            return;
+        }
+        if self.quantity == 0 || self.quantity.signum() == quantity.signum() {
+            let total = self.quantity + quantity;
+            self.average_price =
+                (self.average_price * self.quantity.abs() as f64 + price * quantity.abs() as f64) / total.abs() as f64;
+            self.quantity = total;
+            return;
+        }
+
+        let closed = quantity.abs().min(self.quantity.abs());
+        self.realized_pnl += (price - self.average_price) * closed as f64 * self.quantity.signum() as f64;
+        self.quantity += quantity;
+        if self.quantity == 0 {
+            self.average_price = 0.0;
+        } else if self.quantity.signum() == quantity.signum() {
+            // Flipped from long to short or back; the rest opened at `price`
+            self.average_price = price;
+        }
+    }
+
+    /// Values the open quantity at `last_price`.
+    pub fn mark(&mut self, last_price: Option<f64>) {
+        self.last_price = last_price;
+        self.unrealized_pnl = last_price.map_or(0.0, |price| (price - self.average_price) * self.quantity as f64);
+    }
+}
+
+/// An account's cash in one currency.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Balance {
+    pub account: String,
+    pub currency: String,
+    pub cash: f64,
+}
@@ -0,0 +1,38 @@
+use serde::{Serialize, Deserialize};
+
+/// Price protection for one instrument. Percentages are expressed as whole
+/// percent, e.g. `5.0` allows prices within ±5% of the reference.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PriceBands {
+    /// Orders priced outside ± this percentage of the reference price are
+    /// rejected
+    #[serde(default)]
+    pub static_band_pct: Option<f64>,
+    /// A trade that would print outside ± this percentage of the last trade
+    /// interrupts continuous matching
+    #[serde(default)]
+    pub dynamic_band_pct: Option<f64>,
+    /// Length in seconds of the auction that follows an interruption
+    #[serde(default = "default_interruption_secs")]
+    pub interruption_secs: i64,
+    /// Reference for the static band; the last trade price is used if unset
+    #[serde(default)]
+    pub reference_price: Option<f64>,
+}
+
+fn default_interruption_secs() -> i64 {
+    5
+}
+
+impl PriceBands {
+    /// Returns the `(low, high)` prices allowed around `reference` for a
+    /// band of `pct` percent.
+    pub fn band(reference: f64, pct: f64) -> (f64, f64) {
+        let width = reference * pct / 100.0;
+        (reference - width, reference + width)
+    }
+
+    pub fn within(price: f64, (low, high): (f64, f64)) -> bool {
+        price >= low && price <= high
+    }
+}
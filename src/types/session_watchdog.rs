@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
+const DEFAULT_TIMEOUT_SECS: i64 = 10;
+
+/// When each order-entry session was last heard from. A session that
+/// stays silent for `timeout_secs` is considered disconnected and its
+/// orders are cancelled. A timeout of 0 turns the watchdog off.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionWatchdog {
+    pub timeout_secs: i64,
+    last_heard: HashMap<String, i64>,
+}
+
+impl SessionWatchdog {
+    pub fn new(timeout_secs: i64) -> Self {
+        Self { timeout_secs, last_heard: HashMap::new() }
+    }
+
+    pub fn heartbeat(&mut self, session: &str, now: i64) {
+        self.last_heard.insert(session.to_string(), now);
+    }
+
+    /// Starts watching a session an order arrived from, so sessions that
+    /// never send a heartbeat are still cancelled.
+    pub fn watch(&mut self, session: &str, now: i64) {
+        self.last_heard.entry(session.to_string()).or_insert(now);
+    }
+
+    /// Gives every session a full timeout from `now`, for an engine that
+    /// could not hear heartbeats, e.g. while its partition was restored.
+    pub fn resume(&mut self, now: i64) {
+        for last_heard in self.last_heard.values_mut() {
+            *last_heard = now;
+        }
+    }
+
+    /// Removes and returns the sessions silent for longer than the timeout,
+    /// in name order.
+    pub fn expire(&mut self, now: i64) -> Vec<String> {
+        if self.timeout_secs <= 0 {
+            return Vec::new();
+        }
+
+        let timeout_secs = self.timeout_secs;
+        let mut expired: Vec<String> = self.last_heard
+            .iter()
+            .filter(|&(_, &last_heard)| last_heard <= now - timeout_secs)
+            .map(|(session, _)| session.clone())
+            .collect();
+        expired.sort();
+        for session in &expired {
+            self.last_heard.remove(session);
+        }
+        expired
+    }
+}
+
+impl Default for SessionWatchdog {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMEOUT_SECS)
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Serialize, Deserialize};
+
+/// Trading phase of a single instrument. Orders only match continuously in
+/// `Continuous`; in `PreOpen` and `Auction` they accumulate in the book and
+/// are executed at a single price when the call ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradingPhase {
+    PreOpen,
+    Auction,
+    #[default]
+    Continuous,
+    Closed,
+}
+
+impl TradingPhase {
+    /// Phases where orders rest without matching until the book is uncrossed.
+    pub fn is_call(&self) -> bool {
+        matches!(self, TradingPhase::PreOpen | TradingPhase::Auction)
+    }
+}
+
+/// Where an instrument is in its trading day according to the session
+/// calendar. Each state trades in one `TradingPhase`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    PreOpen,
+    OpeningAuction,
+    Continuous,
+    ClosingAuction,
+    PostClose,
+    /// Outside the trading day, and all day on holidays and non-trading days
+    Closed,
+}
+
+impl SessionState {
+    pub fn phase(&self) -> TradingPhase {
+        match self {
+            SessionState::PreOpen => TradingPhase::PreOpen,
+            SessionState::OpeningAuction | SessionState::ClosingAuction => TradingPhase::Auction,
+            SessionState::Continuous => TradingPhase::Continuous,
+            SessionState::PostClose | SessionState::Closed => TradingPhase::Closed,
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::{BTreeMap, VecDeque};
+use serde::{Serialize, Deserialize};
+use crate::types::order::Order;
+
+/// Resting stop and stop-limit orders for a single instrument, waiting for
+/// the last trade price to cross their stop price.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerBook {
+    pub instrument: String,
+    // Keyed by stop price as fixed-point integer (price * price_scale), FIFO within a level
+    pub buy_stops: BTreeMap<i64, VecDeque<Order>>,
+    pub sell_stops: BTreeMap<i64, VecDeque<Order>>,
+    pub price_scale: i64,
+}
+
+impl TriggerBook {
+    pub fn new(instrument: String) -> Self {
+        Self::with_price_precision(instrument, 2)
+    }
+
+    pub fn with_price_precision(instrument: String, price_precision: u32) -> Self {
+        Self {
+            instrument,
+            buy_stops: BTreeMap::new(),
+            sell_stops: BTreeMap::new(),
+            price_scale: 10i64.pow(price_precision),
+        }
+    }
+
+    fn price_to_key(&self, price: f64) -> i64 {
+        (price * self.price_scale as f64).round() as i64
+    }
+
+    pub fn add_order(&mut self, order: Order) {
+        let stop_key = self.price_to_key(order.stop_price.unwrap_or(order.price));
+
+        let stops = if order.is_buy() { &mut self.buy_stops } else { &mut self.sell_stops };
+        stops.entry(stop_key).or_default().push_back(order);
+    }
+
+    /// Removes and returns the next stop order activated by `last_price`.
+    ///
+    /// Triggering is deterministic: buy stops go first, lowest stop price
+    /// first, then sell stops, highest stop price first, and arrival order
+    /// within a stop price. Callers re-evaluate after every executed stop so
+    /// that cascades see the updated last trade price.
+    pub fn pop_triggered(&mut self, last_price: f64) -> Option<Order> {
+        let last_key = self.price_to_key(last_price);
+
+        let buy_key = self.buy_stops.keys().next().copied().filter(|&key| key <= last_key);
+        if let Some(key) = buy_key {
+            return Self::pop_front(&mut self.buy_stops, key);
+        }
+
+        let sell_key = self.sell_stops.keys().next_back().copied().filter(|&key| key >= last_key);
+        if let Some(key) = sell_key {
+            return Self::pop_front(&mut self.sell_stops, key);
+        }
+
+        None
+    }
+
+    fn pop_front(stops: &mut BTreeMap<i64, VecDeque<Order>>, key: i64) -> Option<Order> {
+        let level = stops.get_mut(&key)?;
+        let order = level.pop_front();
+        if level.is_empty() {
+            stops.remove(&key);
+        }
+        order
+    }
+
+    /// The waiting stop `participant` entered as `client_order_id`.
+    pub fn client_order(&self, participant: &str, client_order_id: &str) -> Option<&Order> {
+        self.buy_stops
+            .values()
+            .chain(self.sell_stops.values())
+            .flatten()
+            .find(|order| order.client_key() == Some((participant, client_order_id)))
+    }
+
+    /// Takes a participant's waiting stop out of the trigger book.
+    pub fn remove_client_order(&mut self, participant: &str, client_order_id: &str) -> Option<Order> {
+        for stops in [&mut self.buy_stops, &mut self.sell_stops] {
+            let found = stops.iter().find_map(|(&key, level)| {
+                level
+                    .iter()
+                    .position(|order| order.client_key() == Some((participant, client_order_id)))
+                    .map(|position| (key, position))
+            });
+            if let Some((key, position)) = found {
+                let level = stops.get_mut(&key)?;
+                let order = level.remove(position);
+                if level.is_empty() {
+                    stops.remove(&key);
+                }
+                return order;
+            }
+        }
+        None
+    }
+
+    /// Reduces a participant's waiting stop to `quantity`, keeping its place
+    /// in the queue. Returns false if there is no such stop or `quantity`
+    /// is not a reduction.
+    pub fn reduce_client_order(&mut self, participant: &str, client_order_id: &str, quantity: u32) -> bool {
+        let Some(order) = self.buy_stops
+            .values_mut()
+            .chain(self.sell_stops.values_mut())
+            .flatten()
+            .find(|order| order.client_key() == Some((participant, client_order_id)))
+        else {
+            return false;
+        };
+        if quantity == 0 || quantity > order.quantity {
+            return false;
+        }
+
+        order.original_quantity -= order.quantity - quantity;
+        order.quantity = quantity;
+        true
+    }
+
+    /// Takes every waiting stop matching `predicate` out of the trigger book.
+    pub fn remove_orders(&mut self, predicate: impl Fn(&Order) -> bool) -> Vec<Order> {
+        let mut removed = Vec::new();
+        for stops in [&mut self.buy_stops, &mut self.sell_stops] {
+            for level in stops.values_mut() {
+                let (taken, kept): (Vec<_>, Vec<_>) = level.drain(..).partition(|order| predicate(order));
+                *level = kept.into();
+                removed.extend(taken);
+            }
+            stops.retain(|_, level| !level.is_empty());
+        }
+        removed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buy_stops.is_empty() && self.sell_stops.is_empty()
+    }
+}
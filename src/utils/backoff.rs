@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+/// Waits between attempts at something that has to succeed eventually,
+/// doubling the wait after each attempt up to a maximum.
+pub struct Backoff {
+    next: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self { next: min, max }
+    }
+
+    /// How long the next `wait` lasts.
+    pub fn delay(&self) -> Duration {
+        self.next
+    }
+
+    pub async fn wait(&mut self) {
+        tokio::time::sleep(self.next).await;
+        self.next = (self.next * 2).min(self.max);
+    }
+}
@@ -0,0 +1,542 @@
+use std::net::{AddrParseError, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{bail, Context};
+use clap::{Args, Parser, ValueEnum};
+use rdkafka::ClientConfig;
+use serde::{Serialize, Deserialize};
+use tracing_subscriber::EnvFilter;
+
+/// Settings shared by every binary. Values come from built-in defaults, then
+/// the TOML config file, then `OB_*` environment variables, then command
+/// line flags, each overriding the one before.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub kafka: KafkaConfig,
+    pub topics: TopicConfig,
+    pub groups: GroupConfig,
+    pub engine: EngineConfig,
+    pub metrics: MetricsConfig,
+    pub logging: LoggingConfig,
+    pub ledger: LedgerConfig,
+    pub candles: CandlesConfig,
+    pub trade_store: TradeStoreConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    pub brokers: String,
+    /// Partitions of the orders topic; instruments are hashed across them
+    pub partition_count: i32,
+    /// How long the producer keeps retrying a message before failing it
+    pub message_timeout_ms: u64,
+    /// How long a publish may wait for space in the producer queue
+    pub publish_timeout_ms: u64,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            brokers: "localhost:9092".to_string(),
+            partition_count: 8,
+            message_timeout_ms: 5000,
+            publish_timeout_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TopicConfig {
+    pub orders: String,
+    pub match_events: String,
+    pub order_events: String,
+    pub market_data: String,
+    pub control_plane: String,
+    /// Compacted topic holding the latest engine snapshot of every orders
+    /// partition
+    pub snapshots: String,
+    /// Completed OHLCV bars built from match-events
+    pub candles: String,
+}
+
+impl Default for TopicConfig {
+    fn default() -> Self {
+        Self {
+            orders: "orders".to_string(),
+            match_events: "match-events".to_string(),
+            order_events: "order-events".to_string(),
+            market_data: "market-data".to_string(),
+            control_plane: "control-plane".to_string(),
+            snapshots: "engine-snapshots".to_string(),
+            candles: "candles".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GroupConfig {
+    pub matching_engine: String,
+    pub match_monitor: String,
+    pub order_consumer: String,
+    pub ledger: String,
+    pub candles: String,
+    pub trade_store: String,
+    /// Each engine instance reads the control-plane in a group of its own,
+    /// named with this prefix
+    pub control_prefix: String,
+}
+
+impl Default for GroupConfig {
+    fn default() -> Self {
+        Self {
+            matching_engine: "matching-engine-group".to_string(),
+            match_monitor: "match-event-monitor-group".to_string(),
+            order_consumer: "order-consumer-group".to_string(),
+            ledger: "ledger-group".to_string(),
+            candles: "candles-group".to_string(),
+            trade_store: "trade-store-group".to_string(),
+            control_prefix: "matching-engine-control".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    pub instruments_file: String,
+    /// Maker/taker fee schedule; empty charges no fees
+    pub fee_schedule_file: String,
+    /// Trading session calendar; empty lets instruments trade all the time
+    pub calendar_file: String,
+    /// Most orders processed before their events are published and their
+    /// offsets committed
+    pub batch_size: usize,
+    /// How long an order id is remembered to reject resubmissions; 0 turns
+    /// duplicate detection off
+    pub duplicate_window_secs: i64,
+    /// How long an order-entry session may go without a heartbeat before
+    /// its orders are cancelled; 0 turns cancel-on-disconnect off
+    pub session_timeout_secs: i64,
+    /// Time of day, "HH:MM" in UTC, that DAY orders expire at
+    pub day_close: String,
+    /// Check the order book after every order and stop the partition if
+    /// an invariant is broken; costs a scan of the book per order
+    pub check_invariants: bool,
+    /// Least time between snapshots of a partition's books; offsets are
+    /// committed only once a snapshot covers them, so this is also how far
+    /// a new owner may have to replay after a crash
+    pub snapshot_interval_secs: u64,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            instruments_file: "instruments.json".to_string(),
+            fee_schedule_file: String::new(),
+            calendar_file: String::new(),
+            batch_size: 100,
+            duplicate_window_secs: 3600,
+            session_timeout_secs: 10,
+            day_close: "00:00".to_string(),
+            check_invariants: false,
+            snapshot_interval_secs: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address the matching engine serves `/metrics` on; empty disables it
+    pub listen: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            listen: "0.0.0.0:9898".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LedgerConfig {
+    /// SQLite database holding positions, balances and booked trades
+    pub database: String,
+    /// Address the ledger serves its query API on; empty disables it
+    pub listen: String,
+    /// Directory end-of-day statements are written to
+    pub statements_dir: String,
+}
+
+impl Default for LedgerConfig {
+    fn default() -> Self {
+        Self {
+            database: "ledger.db".to_string(),
+            listen: "0.0.0.0:9899".to_string(),
+            statements_dir: "statements".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TradeStoreConfig {
+    /// SQLite database holding every trade
+    pub database: String,
+    /// Address the trade store serves its query API on; empty disables it
+    pub listen: String,
+}
+
+impl Default for TradeStoreConfig {
+    fn default() -> Self {
+        Self {
+            database: "trades.db".to_string(),
+            listen: "0.0.0.0:9900".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CandlesConfig {
+    /// Bar lengths, a number followed by "s", "m", "h" or "d"
+    pub intervals: Vec<String>,
+}
+
+impl Default for CandlesConfig {
+    fn default() -> Self {
+        Self {
+            intervals: ["1s", "1m", "5m", "1h"].map(str::to_string).to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// Default filter, e.g. "info" or "cross_partition_order_book=debug";
+    /// RUST_LOG takes precedence when set
+    pub level: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            level: "info".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per event
+    Json,
+}
+
+/// Command line flags understood by every binary. Binaries with arguments
+/// of their own flatten this into their parser.
+#[derive(Debug, Clone, Default, Args)]
+pub struct ConfigArgs {
+    /// TOML config file; config.toml is used if present
+    #[arg(long, env = "OB_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Kafka bootstrap servers
+    #[arg(long, env = "OB_BROKERS")]
+    pub brokers: Option<String>,
+    /// Number of order partitions
+    #[arg(long, env = "OB_PARTITION_COUNT")]
+    pub partitions: Option<i32>,
+    /// Instrument reference data file
+    #[arg(long, env = "OB_INSTRUMENTS_FILE")]
+    pub instruments: Option<String>,
+    /// Log output format
+    #[arg(long, env = "OB_LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// Print the effective configuration and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+#[derive(Debug, Parser)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+impl Config {
+    /// Parses the command line and loads the configuration, exiting the
+    /// process on invalid configuration or after `--print-config`.
+    pub fn from_cli() -> Self {
+        Self::from_args(&Cli::parse().config)
+    }
+
+    pub fn from_args(args: &ConfigArgs) -> Self {
+        let config = match Self::load(args) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Invalid configuration: {:#}", e);
+                std::process::exit(2);
+            }
+        };
+
+        if args.print_config {
+            print!("{}", toml::to_string_pretty(&config).expect("Failed to serialize config"));
+            std::process::exit(0);
+        }
+
+        config
+    }
+
+    pub fn load(args: &ConfigArgs) -> anyhow::Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new("config.toml").exists() => Self::from_file("config.toml")?,
+            None => Self::default(),
+        };
+
+        config.apply_env(|name| std::env::var(name).ok())?;
+
+        if let Some(brokers) = &args.brokers {
+            config.kafka.brokers = brokers.clone();
+        }
+        if let Some(partitions) = args.partitions {
+            config.kafka.partition_count = partitions;
+        }
+        if let Some(instruments) = &args.instruments {
+            config.engine.instruments_file = instruments.clone();
+        }
+        if let Some(log_format) = args.log_format {
+            config.logging.format = log_format;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config file {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("parsing config file {}", path.display()))
+    }
+
+    /// Overrides settings that have no command line flag from `OB_*`
+    /// variables.
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+        let strings: [(&str, &mut String); 24] = [
+            ("OB_TOPIC_ORDERS", &mut self.topics.orders),
+            ("OB_TOPIC_MATCH_EVENTS", &mut self.topics.match_events),
+            ("OB_TOPIC_ORDER_EVENTS", &mut self.topics.order_events),
+            ("OB_TOPIC_MARKET_DATA", &mut self.topics.market_data),
+            ("OB_TOPIC_CONTROL_PLANE", &mut self.topics.control_plane),
+            ("OB_TOPIC_SNAPSHOTS", &mut self.topics.snapshots),
+            ("OB_TOPIC_CANDLES", &mut self.topics.candles),
+            ("OB_GROUP_MATCHING_ENGINE", &mut self.groups.matching_engine),
+            ("OB_GROUP_MATCH_MONITOR", &mut self.groups.match_monitor),
+            ("OB_GROUP_ORDER_CONSUMER", &mut self.groups.order_consumer),
+            ("OB_GROUP_LEDGER", &mut self.groups.ledger),
+            ("OB_GROUP_CANDLES", &mut self.groups.candles),
+            ("OB_GROUP_TRADE_STORE", &mut self.groups.trade_store),
+            ("OB_GROUP_CONTROL_PREFIX", &mut self.groups.control_prefix),
+            ("OB_FEE_SCHEDULE_FILE", &mut self.engine.fee_schedule_file),
+            ("OB_DAY_CLOSE", &mut self.engine.day_close),
+            ("OB_CALENDAR_FILE", &mut self.engine.calendar_file),
+            ("OB_METRICS_LISTEN", &mut self.metrics.listen),
+            ("OB_LOG_LEVEL", &mut self.logging.level),
+            ("OB_LEDGER_DATABASE", &mut self.ledger.database),
+            ("OB_LEDGER_LISTEN", &mut self.ledger.listen),
+            ("OB_LEDGER_STATEMENTS_DIR", &mut self.ledger.statements_dir),
+            ("OB_TRADE_STORE_DATABASE", &mut self.trade_store.database),
+            ("OB_TRADE_STORE_LISTEN", &mut self.trade_store.listen),
+        ];
+        for (name, field) in strings {
+            if let Some(value) = var(name) {
+                *field = value;
+            }
+        }
+
+        let millis: [(&str, &mut u64); 2] = [
+            ("OB_MESSAGE_TIMEOUT_MS", &mut self.kafka.message_timeout_ms),
+            ("OB_PUBLISH_TIMEOUT_MS", &mut self.kafka.publish_timeout_ms),
+        ];
+        for (name, field) in millis {
+            if let Some(value) = var(name) {
+                *field = value.parse().with_context(|| format!("{} must be a number of milliseconds", name))?;
+            }
+        }
+
+        if let Some(value) = var("OB_BATCH_SIZE") {
+            self.engine.batch_size = value.parse().context("OB_BATCH_SIZE must be a number")?;
+        }
+        if let Some(value) = var("OB_DUPLICATE_WINDOW_SECS") {
+            self.engine.duplicate_window_secs = value.parse().context("OB_DUPLICATE_WINDOW_SECS must be a number")?;
+        }
+        if let Some(value) = var("OB_SESSION_TIMEOUT_SECS") {
+            self.engine.session_timeout_secs = value.parse().context("OB_SESSION_TIMEOUT_SECS must be a number")?;
+        }
+        if let Some(value) = var("OB_CHECK_INVARIANTS") {
+            self.engine.check_invariants = value.parse().context("OB_CHECK_INVARIANTS must be true or false")?;
+        }
+        if let Some(value) = var("OB_SNAPSHOT_INTERVAL_SECS") {
+            self.engine.snapshot_interval_secs = value.parse().context("OB_SNAPSHOT_INTERVAL_SECS must be a number")?;
+        }
+        if let Some(value) = var("OB_CANDLE_INTERVALS") {
+            self.candles.intervals = value.split(',').map(|interval| interval.trim().to_string()).collect();
+        }
+
+        Ok(())
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.kafka.brokers.trim().is_empty() {
+            bail!("kafka.brokers must not be empty");
+        }
+        if self.kafka.partition_count <= 0 {
+            bail!("kafka.partition_count must be positive");
+        }
+        if self.kafka.message_timeout_ms == 0 || self.kafka.publish_timeout_ms == 0 {
+            bail!("kafka timeouts must be positive");
+        }
+
+        let topics = [
+            &self.topics.orders,
+            &self.topics.match_events,
+            &self.topics.order_events,
+            &self.topics.market_data,
+            &self.topics.control_plane,
+            &self.topics.snapshots,
+            &self.topics.candles,
+        ];
+        for (i, topic) in topics.iter().enumerate() {
+            if topic.is_empty() {
+                bail!("topic names must not be empty");
+            }
+            if topics[..i].contains(topic) {
+                bail!("topic {} is configured for more than one purpose", topic);
+            }
+        }
+
+        if self.engine.instruments_file.is_empty() {
+            bail!("engine.instruments_file must not be empty");
+        }
+        if self.engine.batch_size == 0 {
+            bail!("engine.batch_size must be positive");
+        }
+        if self.engine.duplicate_window_secs < 0 {
+            bail!("engine.duplicate_window_secs must not be negative");
+        }
+        if self.engine.session_timeout_secs < 0 {
+            bail!("engine.session_timeout_secs must not be negative");
+        }
+        self.day_close_secs()?;
+
+        EnvFilter::try_new(&self.logging.level)
+            .with_context(|| format!("logging.level {} is not a valid filter", self.logging.level))?;
+
+        if let Some(addr) = self.metrics_addr() {
+            addr.with_context(|| format!("metrics.listen {} is not a socket address", self.metrics.listen))?;
+        }
+
+        if self.ledger.database.is_empty() {
+            bail!("ledger.database must not be empty");
+        }
+        if let Some(addr) = self.ledger_addr() {
+            addr.with_context(|| format!("ledger.listen {} is not a socket address", self.ledger.listen))?;
+        }
+
+        if self.trade_store.database.is_empty() {
+            bail!("trade_store.database must not be empty");
+        }
+        if let Some(addr) = self.trade_store_addr() {
+            addr.with_context(|| format!("trade_store.listen {} is not a socket address", self.trade_store.listen))?;
+        }
+
+        if self.candles.intervals.is_empty() {
+            bail!("candles.intervals must not be empty");
+        }
+        self.candle_intervals()?;
+
+        Ok(())
+    }
+
+    /// Kafka client settings common to every consumer and producer.
+    pub fn kafka_client(&self) -> ClientConfig {
+        let mut client = ClientConfig::new();
+        client.set("bootstrap.servers", &self.kafka.brokers);
+        client
+    }
+
+    pub fn message_timeout(&self) -> Duration {
+        Duration::from_millis(self.kafka.message_timeout_ms)
+    }
+
+    pub fn publish_timeout(&self) -> Duration {
+        Duration::from_millis(self.kafka.publish_timeout_ms)
+    }
+
+    /// How often gateways send session heartbeats: three per session
+    /// timeout, so one lost heartbeat never cancels a session's orders.
+    /// `None` when cancel-on-disconnect is off.
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        (self.engine.session_timeout_secs > 0)
+            .then(|| Duration::from_millis(self.engine.session_timeout_secs as u64 * 1000 / 3))
+    }
+
+    /// The metrics listen address, or `None` when metrics are disabled.
+    pub fn metrics_addr(&self) -> Option<Result<SocketAddr, AddrParseError>> {
+        (!self.metrics.listen.is_empty()).then(|| self.metrics.listen.parse())
+    }
+
+    /// Seconds after midnight UTC that DAY orders expire at.
+    pub fn day_close_secs(&self) -> anyhow::Result<i64> {
+        let close = chrono::NaiveTime::parse_from_str(&self.engine.day_close, "%H:%M")
+            .with_context(|| format!("engine.day_close {} is not a HH:MM time", self.engine.day_close))?;
+        Ok(chrono::Timelike::num_seconds_from_midnight(&close) as i64)
+    }
+
+    /// Every candle interval's name and length in seconds.
+    pub fn candle_intervals(&self) -> anyhow::Result<Vec<(String, i64)>> {
+        self.candles
+            .intervals
+            .iter()
+            .map(|interval| {
+                let invalid = || format!("candles interval {} is not a number followed by s, m, h or d", interval);
+                let unit = interval.chars().last().unwrap_or_default();
+                let unit_secs = match unit {
+                    's' => 1,
+                    'm' => 60,
+                    'h' => 60 * 60,
+                    'd' => 24 * 60 * 60,
+                    _ => bail!(invalid()),
+                };
+                let count: i64 = interval[..interval.len() - 1].parse().with_context(invalid)?;
+                if count <= 0 {
+                    bail!(invalid());
+                }
+                Ok((interval.clone(), count * unit_secs))
+            })
+            .collect()
+    }
+
+    /// The ledger query API address, or `None` when it is disabled.
+    pub fn ledger_addr(&self) -> Option<Result<SocketAddr, AddrParseError>> {
+        (!self.ledger.listen.is_empty()).then(|| self.ledger.listen.parse())
+    }
+
+    /// The trade store query API address, or `None` when it is disabled.
+    pub fn trade_store_addr(&self) -> Option<Result<SocketAddr, AddrParseError>> {
+        (!self.trade_store.listen.is_empty()).then(|| self.trade_store.listen.parse())
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::Context;
+use serde::{Serialize, Deserialize};
+use crate::types::match_event::MatchEvent;
+
+/// What a fill costs, in the instrument's currency. Negative rates are
+/// rebates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeeRate {
+    /// Basis points of the traded value for fills that added liquidity
+    pub maker_bps: f64,
+    /// Basis points of the traded value for fills that took liquidity
+    pub taker_bps: f64,
+    pub maker_per_contract: f64,
+    pub taker_per_contract: f64,
+}
+
+impl FeeRate {
+    pub fn fee(&self, maker: bool, price: f64, quantity: u32) -> f64 {
+        let (bps, per_contract) = if maker {
+            (self.maker_bps, self.maker_per_contract)
+        } else {
+            (self.taker_bps, self.taker_per_contract)
+        };
+        price * quantity as f64 * bps / 10_000.0 + quantity as f64 * per_contract
+    }
+}
+
+/// A rate for fills in one instrument, one account tier, or both.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeRule {
+    #[serde(default)]
+    pub instrument: Option<String>,
+    #[serde(default)]
+    pub tier: Option<String>,
+    #[serde(flatten)]
+    pub rate: FeeRate,
+}
+
+/// Maker/taker fees by instrument and account tier. The most specific rule
+/// wins: instrument and tier, then instrument, then tier, then `default`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeSchedule {
+    pub default: FeeRate,
+    /// Tier of each account; other accounts have none
+    pub tiers: HashMap<String, String>,
+    pub rules: Vec<FeeRule>,
+}
+
+impl FeeSchedule {
+    /// Loads a JSON fee schedule.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading fee schedule {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("parsing fee schedule {}", path.display()))
+    }
+
+    pub fn rate(&self, instrument: &str, account: Option<&str>) -> FeeRate {
+        let tier = account.and_then(|account| self.tiers.get(account)).map(String::as_str);
+        let rule = |instrument: Option<&str>, tier: Option<&str>| {
+            self.rules.iter().find(|rule| rule.instrument.as_deref() == instrument && rule.tier.as_deref() == tier)
+        };
+
+        tier.and_then(|tier| rule(Some(instrument), Some(tier)))
+            .or_else(|| rule(Some(instrument), None))
+            .or_else(|| tier.and_then(|tier| rule(None, Some(tier))))
+            .map_or(self.default, |rule| rule.rate)
+    }
+
+    /// Sets the fees of both sides of a trade. The aggressor pays the taker
+    /// rate and the resting side the maker rate; auction trades have no
+    /// aggressor, so both sides pay the maker rate.
+    pub fn apply(&self, trade: &mut MatchEvent) {
+        let aggressor = trade.aggressor_side.as_deref();
+        trade.buyer_fee = self
+            .rate(&trade.instrument, trade.buyer_account.as_deref())
+            .fee(aggressor != Some("buy"), trade.price, trade.quantity);
+        trade.seller_fee = self
+            .rate(&trade.instrument, trade.seller_account.as_deref())
+            .fee(aggressor != Some("sell"), trade.price, trade.quantity);
+    }
+}
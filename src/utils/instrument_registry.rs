@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{bail, Context};
+use serde::{Serialize, Deserialize};
+use crate::types::engine_event::RejectReason;
+use crate::types::instrument::{Instrument, InstrumentStatus};
+use crate::types::order::Order;
+
+/// The instruments an engine accepts orders for, keyed by symbol.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InstrumentRegistry {
+    pub instruments: HashMap<String, Instrument>,
+}
+
+impl InstrumentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a JSON array of instrument definitions.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading instrument file {}", path.display()))?;
+        let definitions: Vec<Instrument> = serde_json::from_str(&contents)
+            .with_context(|| format!("parsing instrument file {}", path.display()))?;
+
+        let mut registry = Self::new();
+        for instrument in definitions {
+            registry.upsert(instrument)?;
+        }
+        Ok(registry)
+    }
+
+    /// Adds or replaces an instrument definition after checking it is
+    /// self-consistent.
+    pub fn upsert(&mut self, instrument: Instrument) -> anyhow::Result<()> {
+        if instrument.tick_size <= 0.0 {
+            bail!("{}: tick_size must be positive", instrument.symbol);
+        }
+        if instrument.lot_size == 0 {
+            bail!("{}: lot_size must be positive", instrument.symbol);
+        }
+        if instrument.min_quantity > instrument.max_quantity {
+            bail!("{}: min_quantity exceeds max_quantity", instrument.symbol);
+        }
+        let scale = 10f64.powi(instrument.price_precision as i32);
+        let ticks_per_unit = instrument.tick_size * scale;
+        if (ticks_per_unit - ticks_per_unit.round()).abs() > 1e-6 {
+            bail!("{}: tick_size is finer than price_precision", instrument.symbol);
+        }
+
+        self.instruments.insert(instrument.symbol.clone(), instrument);
+        Ok(())
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Instrument> {
+        self.instruments.get(symbol)
+    }
+
+    /// Checks an incoming order against its instrument's reference data.
+    pub fn validate_order(&self, order: &Order) -> Result<(), RejectReason> {
+        let Some(instrument) = self.get(&order.instrument) else {
+            return Err(RejectReason::UnknownInstrument);
+        };
+
+        if instrument.status == InstrumentStatus::Halted {
+            return Err(RejectReason::InstrumentHalted);
+        }
+
+        if !instrument.is_valid_quantity(order.quantity) {
+            return Err(RejectReason::QuantityOutOfRange);
+        }
+        if !instrument.is_whole_lots(order.quantity) {
+            return Err(RejectReason::InvalidLotSize);
+        }
+
+        let price_on_tick = order.is_market() || order.order_type == "stop" || instrument.is_on_tick(order.price);
+        let stop_on_tick = order.stop_price.is_none_or(|stop_price| instrument.is_on_tick(stop_price));
+        if !price_on_tick || !stop_on_tick {
+            return Err(RejectReason::InvalidTickSize);
+        }
+
+        Ok(())
+    }
+}
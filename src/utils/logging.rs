@@ -0,0 +1,22 @@
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+use crate::utils::config::{LogFormat, LoggingConfig};
+
+/// Kafka header carrying the id that follows an order through every service.
+pub const TRACE_ID_HEADER: &str = "trace-id";
+
+/// Installs the global subscriber. RUST_LOG overrides the configured level.
+pub fn init(config: &LoggingConfig) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.level));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.format {
+        LogFormat::Text => builder.init(),
+        // Event fields sit at the top level and the enclosing span, with its
+        // trace id, is attached to every line
+        LogFormat::Json => builder.json().flatten_event(true).with_current_span(true).init(),
+    }
+}
+
+pub fn new_trace_id() -> String {
+    Uuid::new_v4().simple().to_string()
+}
@@ -1,52 +1,1055 @@
+use std::collections::BTreeSet;
 use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+use tracing::warn;
 use crate::types::order::Order;
-use crate::types::order_book::{OrderBook, PriceLevel};
+use crate::types::order_request::OrderRequest;
+use crate::types::order_book::{BookViolation, OrderBook, PriceLevel};
+use crate::types::trigger_book::TriggerBook;
 use crate::types::match_event::MatchEvent;
+use crate::types::engine_event::{EngineEvent, RejectReason};
+use crate::types::trading_phase::{SessionState, TradingPhase};
+use crate::types::control_message::ControlMessage;
+use crate::types::price_bands::PriceBands;
+use crate::types::instrument::AllocationStrategy;
+use crate::types::duplicate_window::DuplicateWindow;
+use crate::types::expiry_schedule::ExpirySchedule;
+use crate::types::instrument_state::InstrumentState;
+use crate::types::session_watchdog::SessionWatchdog;
+use crate::types::mass_cancel::MassCancelFilter;
+use crate::utils::instrument_registry::InstrumentRegistry;
+use crate::utils::session_calendar::SessionCalendar;
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MatchingEngine {
     pub order_books: std::collections::HashMap<String, OrderBook>,
+    pub trigger_books: std::collections::HashMap<String, TriggerBook>,
+    pub phases: std::collections::HashMap<String, TradingPhase>,
+    pub price_bands: std::collections::HashMap<String, PriceBands>,
+    // Instruments in a volatility auction, with the time matching resumes
+    pub volatility_auctions: std::collections::HashMap<String, i64>,
+    pub registry: InstrumentRegistry,
+    // Instruments migrated to another partition, with where their orders
+    // are forwarded
+    #[serde(default)]
+    pub migrated: std::collections::HashMap<String, i32>,
+    // Recently processed order ids
+    #[serde(default)]
+    pub duplicates: DuplicateWindow,
+    // Order-entry sessions, cancelled when their heartbeats stop
+    #[serde(default)]
+    pub sessions: SessionWatchdog,
+    // Engine time in seconds, moved forward by `advance_clock` only; the
+    // timestamp of every event but trades
+    #[serde(default)]
+    pub clock: i64,
+    // When resting GTD and DAY orders expire
+    #[serde(default)]
+    pub expiries: ExpirySchedule,
+    // Trading sessions; loaded from the calendar file, not snapshotted
+    #[serde(skip)]
+    pub calendar: SessionCalendar,
+    // The session state last applied to each instrument
+    #[serde(default)]
+    pub session_states: std::collections::HashMap<String, SessionState>,
+    // Checks the book's invariants after every order and request, and
+    // panics on a violation; for tests and debugging
+    #[serde(skip)]
+    pub debug_checks: bool,
 }
 
 impl MatchingEngine {
+    /// An engine with an empty instrument registry; it rejects every order
+    /// until instruments are added.
     pub fn new() -> Self {
+        Self::with_registry(InstrumentRegistry::new())
+    }
+
+    pub fn with_registry(registry: InstrumentRegistry) -> Self {
         Self {
             order_books: std::collections::HashMap::new(),
+            trigger_books: std::collections::HashMap::new(),
+            phases: std::collections::HashMap::new(),
+            price_bands: std::collections::HashMap::new(),
+            volatility_auctions: std::collections::HashMap::new(),
+            registry,
+            migrated: std::collections::HashMap::new(),
+            duplicates: DuplicateWindow::default(),
+            sessions: SessionWatchdog::default(),
+            clock: 0,
+            expiries: ExpirySchedule::default(),
+            calendar: SessionCalendar::default(),
+            session_states: std::collections::HashMap::new(),
+            debug_checks: false,
         }
     }
 
-    pub fn process_order(&mut self, mut order: Order) -> Vec<MatchEvent> {
-        let mut matches = Vec::new();
-        
-        // Get or create order book for this instrument
+    /// Instruments without an explicit phase trade continuously.
+    pub fn trading_phase(&self, instrument: &str) -> TradingPhase {
+        self.phases.get(instrument).copied().unwrap_or_default()
+    }
+
+    pub fn apply_control(&mut self, message: &ControlMessage) -> Vec<EngineEvent> {
+        let events = match message {
+            ControlMessage::SetTradingPhase { instrument, phase } => {
+                // An explicit phase change overrides a running volatility auction
+                self.volatility_auctions.remove(instrument);
+                self.set_trading_phase(instrument, *phase)
+            }
+            ControlMessage::SetPriceBands { instrument, bands } => {
+                self.price_bands.insert(instrument.clone(), *bands);
+                Vec::new()
+            }
+            ControlMessage::UpdateInstrument { definition } => {
+                // Books already created keep their price scale; a new
+                // allocation strategy applies from the next match
+                if let Some(order_book) = self.order_books.get_mut(&definition.symbol) {
+                    order_book.allocation = definition.allocation;
+                }
+                if let Err(e) = self.registry.upsert(definition.clone()) {
+                    warn!(instrument = %definition.symbol, error = %e, "Ignoring invalid instrument definition");
+                }
+                Vec::new()
+            }
+            // Exporting needs the partition's publisher and routes are kept
+            // outside the engine; both are handled by the partition's owner
+            ControlMessage::MigrateInstrument { .. } | ControlMessage::RouteInstrument { .. } => Vec::new(),
+            ControlMessage::MassCancel { filter } => self.mass_cancel(filter),
+            ControlMessage::Heartbeat { session } => {
+                self.sessions.heartbeat(session, self.clock);
+                Vec::new()
+            }
+        };
+        self.debug_check_events(&events, "control message");
+        events
+    }
+
+    /// Cancels every resting order and waiting stop matching `filter`,
+    /// instrument by instrument.
+    pub fn mass_cancel(&mut self, filter: &MassCancelFilter) -> Vec<EngineEvent> {
+        self.cancel_matching(filter, "mass cancel")
+    }
+
+    fn cancel_matching(&mut self, filter: &MassCancelFilter, reason: &str) -> Vec<EngineEvent> {
+        let mut events = Vec::new();
+        if !filter.is_valid() {
+            return events;
+        }
+
+        let mut instruments: Vec<String> = self.order_books.keys().chain(self.trigger_books.keys()).cloned().collect();
+        instruments.sort();
+        instruments.dedup();
+
+        for instrument in instruments {
+            if filter.instrument.as_ref().is_some_and(|only| *only != instrument) {
+                continue;
+            }
+
+            let cancelled = self.take_orders(&instrument, |order| filter.matches(order));
+            if cancelled.is_empty() {
+                continue;
+            }
+            events.extend(cancelled.iter().map(|order| self.cancelled_event(order, reason)));
+            if self.trading_phase(&instrument) == TradingPhase::Auction {
+                events.push(self.indicative_price_event(&instrument));
+            }
+        }
+
+        events
+    }
+
+    /// Takes the resting orders and waiting stops matching `predicate` out
+    /// of an instrument's books.
+    fn take_orders(&mut self, instrument: &str, predicate: impl Fn(&Order) -> bool) -> Vec<Order> {
+        let mut taken = self.order_books
+            .get_mut(instrument)
+            .map(|order_book| order_book.remove_orders(&predicate))
+            .unwrap_or_default();
+        if let Some(trigger_book) = self.trigger_books.get_mut(instrument) {
+            taken.extend(trigger_book.remove_orders(&predicate));
+            if trigger_book.is_empty() {
+                self.trigger_books.remove(instrument);
+            }
+        }
+        taken
+    }
+
+    /// Moves the engine clock forward to `now`, expires the GTD and DAY
+    /// orders due by then, ends the volatility auctions whose interruption
+    /// has elapsed and forgets order ids that have left the duplicate
+    /// window. The engine never reads the time for these itself: the caller
+    /// injects it, from the timestamps of the records being processed, so a
+    /// replay of the same records expires the same orders, resumes the same
+    /// auctions and rejects the same duplicates at the same points. Times
+    /// before the clock are ignored.
+    pub fn advance_clock(&mut self, now: i64) -> Vec<EngineEvent> {
+        let mut events = Vec::new();
+        if now <= self.clock {
+            return events;
+        }
+        self.clock = now;
+        self.duplicates.expire(now);
+
+        let mut due: std::collections::BTreeMap<String, std::collections::HashSet<String>> = std::collections::BTreeMap::new();
+        for expiry in self.expiries.pop_due(now) {
+            due.entry(expiry.instrument).or_default().insert(expiry.order_id);
+        }
+
+        for (instrument, order_ids) in due {
+            let expired = self.take_orders(&instrument, |order| order_ids.contains(&order.id));
+            self.push_expired(&instrument, expired, &mut events);
+        }
+
+        let mut resumed: Vec<String> = self.volatility_auctions
+            .iter()
+            .filter(|&(_, &resume_at)| resume_at <= now)
+            .map(|(instrument, _)| instrument.clone())
+            .collect();
+        resumed.sort();
+        for instrument in resumed {
+            self.volatility_auctions.remove(&instrument);
+            events.extend(self.set_trading_phase(&instrument, TradingPhase::Continuous));
+        }
+
+        // Instruments this engine holds nothing for yet follow the calendar
+        // from their first order
+        let mut instruments: Vec<String> = self.order_books
+            .keys()
+            .chain(self.trigger_books.keys())
+            .chain(self.phases.keys())
+            .chain(self.session_states.keys())
+            .cloned()
+            .collect();
+        instruments.sort();
+        instruments.dedup();
+        for instrument in instruments {
+            self.sync_session(&instrument, &mut events);
+        }
+
+        self.debug_check_events(&events, "clock advance");
+        events
+    }
+
+    /// Moves an instrument to the session state the calendar has for it at
+    /// the engine clock, and into that state's trading phase. Only changes
+    /// of state act, so a phase set on the control plane holds until the
+    /// next session transition.
+    fn sync_session(&mut self, instrument: &str, events: &mut Vec<EngineEvent>) {
+        let Some(state) = self.calendar.state(instrument, self.clock) else {
+            return;
+        };
+        if self.session_states.get(instrument) == Some(&state) {
+            return;
+        }
+
+        self.session_states.insert(instrument.to_string(), state);
+        events.push(EngineEvent::SessionState {
+            instrument: instrument.to_string(),
+            state,
+            timestamp: self.clock,
+        });
+        self.volatility_auctions.remove(instrument);
+        events.extend(self.set_trading_phase(instrument, state.phase()));
+    }
+
+    fn push_expired(&self, instrument: &str, expired: Vec<Order>, events: &mut Vec<EngineEvent>) {
+        if expired.is_empty() {
+            return;
+        }
+        events.extend(expired.iter().map(|order| EngineEvent::Expired {
+            order_id: order.id.clone(),
+            instrument: order.instrument.clone(),
+            remaining_quantity: order.quantity,
+            time_in_force: order.time_in_force.clone().unwrap_or_default(),
+            participant: order.participant.clone(),
+            client_order_id: order.client_order_id.clone(),
+            timestamp: self.clock,
+        }));
+        if self.trading_phase(instrument) == TradingPhase::Auction {
+            events.push(self.indicative_price_event(instrument));
+        }
+    }
+
+    /// Takes everything held for `instrument` out of this engine. Orders for
+    /// it arriving afterwards are forwarded to `target_partition`.
+    pub fn export_instrument(&mut self, instrument: &str, target_partition: i32) -> InstrumentState {
+        self.migrated.insert(instrument.to_string(), target_partition);
+        InstrumentState {
+            definition: self.registry.get(instrument).cloned(),
+            order_book: self.order_books.remove(instrument),
+            trigger_book: self.trigger_books.remove(instrument),
+            phase: self.phases.remove(instrument),
+            price_bands: self.price_bands.remove(instrument),
+            volatility_auction: self.volatility_auctions.remove(instrument),
+            seen_orders: self.duplicates.take_instrument(instrument),
+            expiries: self.expiries.take_instrument(instrument),
+            session_state: self.session_states.remove(instrument),
+        }
+    }
+
+    /// Takes over an instrument exported by another engine, replacing
+    /// anything held for it here.
+    pub fn import_instrument(&mut self, instrument: &str, state: InstrumentState) {
+        self.migrated.remove(instrument);
+        if let Some(definition) = state.definition
+            && let Err(e) = self.registry.upsert(definition)
+        {
+            warn!(instrument, error = %e, "Ignoring invalid imported instrument definition");
+        }
+
+        self.duplicates.extend(state.seen_orders);
+        self.expiries.extend(state.expiries);
+
+        let key = instrument.to_string();
+        match state.order_book {
+            Some(order_book) => self.order_books.insert(key.clone(), order_book),
+            None => self.order_books.remove(instrument),
+        };
+        match state.trigger_book {
+            Some(trigger_book) => self.trigger_books.insert(key.clone(), trigger_book),
+            None => self.trigger_books.remove(instrument),
+        };
+        match state.phase {
+            Some(phase) => self.phases.insert(key.clone(), phase),
+            None => self.phases.remove(instrument),
+        };
+        match state.price_bands {
+            Some(bands) => self.price_bands.insert(key.clone(), bands),
+            None => self.price_bands.remove(instrument),
+        };
+        match state.volatility_auction {
+            Some(resume_at) => self.volatility_auctions.insert(key.clone(), resume_at),
+            None => self.volatility_auctions.remove(instrument),
+        };
+        match state.session_state {
+            Some(session_state) => self.session_states.insert(key, session_state),
+            None => self.session_states.remove(instrument),
+        };
+    }
+
+    /// Housekeeping called periodically by the engine loop. Cancels the
+    /// orders of sessions not heard from within their timeout of the
+    /// engine clock; heartbeats are stamped with the clock too, so a
+    /// partition whose clock stands still disconnects nobody.
+    pub fn on_timer(&mut self) -> Vec<EngineEvent> {
+        let mut events = Vec::new();
+
+        for session in self.sessions.expire(self.clock) {
+            warn!(session, "Session heartbeats stopped, cancelling its orders");
+            let filter = MassCancelFilter { session: Some(session), ..MassCancelFilter::default() };
+            events.extend(self.cancel_matching(&filter, "session disconnected"));
+        }
+
+        self.debug_check_events(&events, "session timeout");
+        events
+    }
+
+    /// Moves an instrument to a new trading phase. Leaving a call phase
+    /// uncrosses the book at the equilibrium price before the new phase
+    /// starts.
+    pub fn set_trading_phase(&mut self, instrument: &str, phase: TradingPhase) -> Vec<EngineEvent> {
+        let mut events = Vec::new();
+        let previous = self.trading_phase(instrument);
+        if previous == phase {
+            return events;
+        }
+
+        if previous.is_call() && !phase.is_call() {
+            self.uncross(instrument, &mut events);
+        }
+
+        self.phases.insert(instrument.to_string(), phase);
+        events.push(EngineEvent::PhaseChanged {
+            instrument: instrument.to_string(),
+            phase,
+            timestamp: self.clock,
+        });
+
+        // The session is over for DAY orders
+        if phase == TradingPhase::Closed {
+            let expired = self.take_orders(instrument, Order::is_day);
+            self.push_expired(instrument, expired, &mut events);
+        }
+
+        if phase == TradingPhase::Continuous {
+            self.run_triggers(instrument, &mut events);
+        }
+
+        events
+    }
+
+    fn uncross(&mut self, instrument: &str, events: &mut Vec<EngineEvent>) {
+        let Some(order_book) = self.order_books.get_mut(instrument) else {
+            return;
+        };
+        let Some(equilibrium) = order_book.equilibrium(order_book.last_trade_price) else {
+            return;
+        };
+
+        let (buy_fills, sell_fills) = order_book.auction_fills(&equilibrium);
+        let mut buys = buy_fills.into_iter();
+        let mut sells = sell_fills.into_iter();
+        let mut buy = buys.next();
+        let mut sell = sells.next();
+
+        // Pair buy and sell fills in execution order, all at the single
+        // equilibrium price
+        while let (Some(buy_fill), Some(sell_fill)) = (&mut buy, &mut sell) {
+            let quantity = buy_fill.filled_quantity.min(sell_fill.filled_quantity);
+            let timestamp_ns = current_timestamp_nanos();
+            events.push(EngineEvent::Trade(MatchEvent {
+                id: Uuid::new_v4().to_string(),
+                instrument: instrument.to_string(),
+                buyer_order_id: buy_fill.order_id.clone(),
+                seller_order_id: sell_fill.order_id.clone(),
+                price: equilibrium.price,
+                quantity,
+                timestamp: timestamp_ns / NANOS_PER_SECOND,
+                timestamp_ns,
+                sequence: order_book.next_trade_sequence(),
+                buyer_account: buy_fill.account.clone(),
+                seller_account: sell_fill.account.clone(),
+                buyer_participant: buy_fill.participant.clone(),
+                seller_participant: sell_fill.participant.clone(),
+                buyer_client_order_id: buy_fill.client_order_id.clone(),
+                seller_client_order_id: sell_fill.client_order_id.clone(),
+                aggressor_side: None,
+                aggressive_order_id: None,
+                passive_order_id: None,
+                aggressive_order_price: None,
+                passive_remaining_quantity: None,
+                buyer_fee: 0.0,
+                seller_fee: 0.0,
+            }));
+
+            buy_fill.filled_quantity -= quantity;
+            sell_fill.filled_quantity -= quantity;
+            if buy_fill.filled_quantity == 0 {
+                buy = buys.next();
+            }
+            if sell_fill.filled_quantity == 0 {
+                sell = sells.next();
+            }
+        }
+
+        order_book.last_trade_price = Some(equilibrium.price);
+        order_book.cleanup_empty_levels();
+    }
+
+    fn indicative_price_event(&self, instrument: &str) -> EngineEvent {
+        let equilibrium = self.order_books
+            .get(instrument)
+            .and_then(|order_book| order_book.equilibrium(order_book.last_trade_price));
+
+        EngineEvent::IndicativePrice {
+            instrument: instrument.to_string(),
+            price: equilibrium.map(|e| e.price),
+            matched_volume: equilibrium.map_or(0, |e| e.volume),
+            imbalance: equilibrium.map_or(0, |e| e.imbalance()),
+            timestamp: self.clock,
+        }
+    }
+
+    pub fn process_order(&mut self, order: Order) -> Vec<EngineEvent> {
+        if let Some(&partition) = self.migrated.get(&order.instrument) {
+            return vec![EngineEvent::Forwarded { order, partition }];
+        }
+
+        let mut events = Vec::new();
+        let instrument = order.instrument.clone();
+        self.sync_session(&instrument, &mut events);
+
+        if self.duplicates.contains(&order.id, self.clock) {
+            events.push(self.rejected_event(&order, RejectReason::DuplicateOrderId));
+            return events;
+        }
+
+        if self.has_live_client_order(&order) {
+            events.push(self.rejected_event(&order, RejectReason::DuplicateClientOrderId));
+            return events;
+        }
+
+        if let Err(reason) = self.check_order(&order) {
+            events.push(self.rejected_event(&order, reason));
+            return events;
+        }
+
+        // Only accepted orders count; a rejected one can be sent again
+        self.duplicates.record(&order.id, &instrument, self.clock);
+
+        if let Some(session) = &order.session {
+            self.sessions.watch(session, self.clock);
+        }
+
+        let order_id = order.id.clone();
+        if order.is_conditional() {
+            self.accept_stop_order(order, &mut events);
+        } else {
+            self.execute_order(order, &mut events);
+        }
+
+        // Trades may have moved the last price through resting stops
+        self.run_triggers(&instrument, &mut events);
+
+        if self.trading_phase(&instrument) == TradingPhase::Auction {
+            events.push(self.indicative_price_event(&instrument));
+        }
+
+        self.debug_check(&instrument, &order_id);
+        events
+    }
+
+    /// Applies a participant's cancel or amend request to the order it
+    /// entered with the request's client order id.
+    pub fn process_request(&mut self, request: OrderRequest) -> Vec<EngineEvent> {
+        if let Some(&partition) = self.migrated.get(request.instrument()) {
+            return vec![EngineEvent::ForwardedRequest { request, partition }];
+        }
+
+        let mut events = Vec::new();
+        let instrument = request.instrument().to_string();
+        self.sync_session(&instrument, &mut events);
+
+        match &request {
+            OrderRequest::Cancel { .. } => self.cancel_order(&request, &mut events),
+            OrderRequest::Amend { price, quantity, .. } => self.amend_order(&request, *price, *quantity, &mut events),
+        }
+
+        if self.trading_phase(&instrument) == TradingPhase::Auction {
+            events.push(self.indicative_price_event(&instrument));
+        }
+
+        self.debug_check(&instrument, request.client_order_id());
+        events
+    }
+
+    /// The broken invariants of every order book, by instrument. Books of
+    /// instruments outside continuous trading may be crossed.
+    pub fn check_invariants(&self) -> Vec<(String, BookViolation)> {
+        let mut instruments: Vec<&String> = self.order_books.keys().collect();
+        instruments.sort();
+        instruments
+            .into_iter()
+            .flat_map(|instrument| {
+                let may_cross = self.trading_phase(instrument) != TradingPhase::Continuous;
+                self.order_books[instrument]
+                    .check_invariants(may_cross)
+                    .into_iter()
+                    .map(move |violation| (instrument.clone(), violation))
+            })
+            .collect()
+    }
+
+    // With debug checks on, panics if processing `cause` broke the
+    // invariants of the instrument's book
+    fn debug_check(&self, instrument: &str, cause: &str) {
+        if !self.debug_checks {
+            return;
+        }
+        let Some(order_book) = self.order_books.get(instrument) else {
+            return;
+        };
+        let violations = order_book.check_invariants(self.trading_phase(instrument) != TradingPhase::Continuous);
+        assert!(violations.is_empty(), "{} order book invariants broken after {}: {:?}", instrument, cause, violations);
+    }
+
+    // Like `debug_check`, for the books of every instrument with events;
+    // the others were not touched
+    fn debug_check_events(&self, events: &[EngineEvent], cause: &str) {
+        if !self.debug_checks {
+            return;
+        }
+        let instruments: BTreeSet<&str> = events.iter().map(EngineEvent::instrument).collect();
+        for instrument in instruments {
+            self.debug_check(instrument, cause);
+        }
+    }
+
+    fn cancel_order(&mut self, request: &OrderRequest, events: &mut Vec<EngineEvent>) {
+        let (instrument, participant, client_order_id) = (request.instrument(), request.participant(), request.client_order_id());
+
+        // Resting orders first, then stops still waiting for their trigger
+        let resting = self.order_books
+            .get_mut(instrument)
+            .and_then(|order_book| order_book.remove_client_order(participant, client_order_id));
+        let order = resting.or_else(|| {
+            let trigger_book = self.trigger_books.get_mut(instrument)?;
+            let order = trigger_book.remove_client_order(participant, client_order_id);
+            if trigger_book.is_empty() {
+                self.trigger_books.remove(instrument);
+            }
+            order
+        });
+
+        match order {
+            Some(order) => events.push(self.cancelled_event(&order, "cancelled by participant")),
+            None => events.push(self.request_rejected_event(request, RejectReason::UnknownOrder)),
+        }
+    }
+
+    /// Reducing the quantity at the same price amends the order in place;
+    /// any other change takes it out of the book and enters it again, so it
+    /// can trade and loses its queue priority. Stops still waiting for their
+    /// trigger are amended the same way in the trigger book.
+    fn amend_order(&mut self, request: &OrderRequest, price: Option<f64>, quantity: Option<u32>, events: &mut Vec<EngineEvent>) {
+        let (instrument, participant, client_order_id) = (request.instrument(), request.participant(), request.client_order_id());
+
+        // Resting orders first, then stops still waiting for their trigger
+        let resting = self.order_books
+            .get(instrument)
+            .and_then(|order_book| order_book.client_order(participant, client_order_id));
+        let waiting = self.trigger_books
+            .get(instrument)
+            .and_then(|trigger_book| trigger_book.client_order(participant, client_order_id));
+        let is_waiting = resting.is_none();
+        let Some(current) = resting.or(waiting).cloned() else {
+            events.push(self.request_rejected_event(request, RejectReason::UnknownOrder));
+            return;
+        };
+
+        let new_quantity = quantity.unwrap_or(current.quantity);
+        if new_quantity == 0 {
+            self.cancel_order(request, events);
+            return;
+        }
+
+        let mut amended = current.clone();
+        amended.price = price.unwrap_or(current.price);
+        amended.quantity = new_quantity;
+        amended.original_quantity = current.original_quantity - current.quantity + new_quantity;
+        // The minimum quantity only ever applies on arrival
+        amended.min_quantity = None;
+
+        let price_scale = 10f64.powi(self.price_precision(instrument) as i32);
+        let same_price = (amended.price * price_scale).round() == (current.price * price_scale).round();
+        let kept_priority = same_price && new_quantity <= current.quantity;
+
+        // An unchanged price is not checked against bands that moved since
+        // the order was entered
+        let validation = if same_price {
+            self.registry
+                .validate_order(&amended)
+                .and_then(|_| Self::validate_phase(&amended, self.trading_phase(instrument)))
+        } else {
+            self.check_order(&amended)
+        };
+        if let Err(reason) = validation {
+            events.push(self.request_rejected_event(request, reason));
+            return;
+        }
+
+        if is_waiting {
+            let Some(trigger_book) = self.trigger_books.get_mut(instrument) else {
+                return;
+            };
+            if kept_priority {
+                trigger_book.reduce_client_order(participant, client_order_id, new_quantity);
+                events.push(self.amended_event(&amended, true));
+                return;
+            }
+            trigger_book.remove_client_order(participant, client_order_id);
+            if trigger_book.is_empty() {
+                self.trigger_books.remove(instrument);
+            }
+            events.push(self.amended_event(&amended, false));
+            self.accept_stop_order(amended, events);
+            return;
+        }
+
+        let Some(order_book) = self.order_books.get_mut(instrument) else {
+            return;
+        };
+        if kept_priority {
+            order_book.reduce_client_order(participant, client_order_id, new_quantity);
+            events.push(self.amended_event(&amended, true));
+            return;
+        }
+
+        order_book.remove_client_order(participant, client_order_id);
+        events.push(self.amended_event(&amended, false));
+        self.execute_order(amended, events);
+        self.run_triggers(instrument, events);
+    }
+
+    /// Whether the order's participant already has a live order for the
+    /// instrument with the same client order id.
+    fn has_live_client_order(&self, order: &Order) -> bool {
+        let Some((participant, client_order_id)) = order.client_key() else {
+            return false;
+        };
+
+        self.order_books
+            .get(&order.instrument)
+            .is_some_and(|order_book| order_book.client_order(participant, client_order_id).is_some())
+            || self.trigger_books
+                .get(&order.instrument)
+                .is_some_and(|trigger_book| trigger_book.client_order(participant, client_order_id).is_some())
+    }
+
+    /// Reference data, order attributes, trading phase and price band checks
+    /// an order must pass before it can trade or rest.
+    fn check_order(&self, order: &Order) -> Result<(), RejectReason> {
+        self.registry.validate_order(order)?;
+        Self::validate_order(order)?;
+        if order.expire_time.is_some_and(|expire_time| expire_time <= self.clock) {
+            return Err(RejectReason::ExpireTimeInPast);
+        }
+        Self::validate_phase(order, self.trading_phase(&order.instrument))?;
+        self.validate_price_band(order)
+    }
+
+    fn validate_order(order: &Order) -> Result<(), RejectReason> {
+        // A client order id can only be referenced together with its participant
+        if order.client_order_id.is_some() && order.participant.is_none() {
+            return Err(RejectReason::InvalidAttributes);
+        }
+
+        // Only GTD orders have an expire time, and they need one
+        let valid_expiry = match order.time_in_force.as_deref() {
+            None | Some("gtc") | Some("day") => order.expire_time.is_none(),
+            Some("gtd") => order.expire_time.is_some(),
+            Some(_) => false,
+        };
+        if !valid_expiry {
+            return Err(RejectReason::InvalidAttributes);
+        }
+
+        // Stops wait for the market to reach a stop price of their own
+        if order.is_conditional() && !order.stop_price.is_some_and(|stop_price| stop_price > 0.0) {
+            return Err(RejectReason::InvalidAttributes);
+        }
+
+        if let Some(mode) = &order.post_only {
+            // A post-only order must be able to rest, and never trades on arrival
+            if mode != "reject" && mode != "reprice" {
+                return Err(RejectReason::InvalidAttributes);
+            }
+            if order.is_market() || order.order_type == "stop" || order.min_quantity.is_some() {
+                return Err(RejectReason::InvalidAttributes);
+            }
+        }
+
+        if let Some(min_quantity) = order.min_quantity
+            && (min_quantity == 0 || min_quantity > order.quantity)
+        {
+            return Err(RejectReason::InvalidAttributes);
+        }
+
+        Ok(())
+    }
+
+    fn validate_phase(order: &Order, phase: TradingPhase) -> Result<(), RejectReason> {
+        match phase {
+            TradingPhase::Closed => Err(RejectReason::InstrumentClosed),
+            // Orders in a call rest until the uncross, so they need a limit
+            // price and can't demand an immediate fill
+            _ if phase.is_call() && (order.is_market() || order.min_quantity.is_some()) => {
+                Err(RejectReason::NotAllowedInPhase)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn validate_price_band(&self, order: &Order) -> Result<(), RejectReason> {
+        if order.is_market() || order.order_type == "stop" {
+            return Ok(());
+        }
+
+        let Some(bands) = self.price_bands.get(&order.instrument) else {
+            return Ok(());
+        };
+        let reference_price = bands.reference_price.or_else(|| {
+            self.order_books
+                .get(&order.instrument)
+                .and_then(|order_book| order_book.last_trade_price)
+        });
+
+        match (bands.static_band_pct, reference_price) {
+            (Some(pct), Some(reference)) if !PriceBands::within(order.price, PriceBands::band(reference, pct)) => {
+                Err(RejectReason::PriceOutsideBand)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Prices continuous trades may print at without interrupting matching.
+    fn dynamic_band(&self, instrument: &str) -> Option<(f64, f64)> {
+        let pct = self.price_bands.get(instrument)?.dynamic_band_pct?;
+        let last_trade_price = self.order_books.get(instrument)?.last_trade_price?;
+        Some(PriceBands::band(last_trade_price, pct))
+    }
+
+    fn rejected_event(&self, order: &Order, reason: RejectReason) -> EngineEvent {
+        EngineEvent::Rejected {
+            order_id: order.id.clone(),
+            instrument: order.instrument.clone(),
+            reason,
+            participant: order.participant.clone(),
+            client_order_id: order.client_order_id.clone(),
+            timestamp: self.clock,
+        }
+    }
+
+    fn cancelled_event(&self, order: &Order, reason: &str) -> EngineEvent {
+        EngineEvent::Cancelled {
+            order_id: order.id.clone(),
+            instrument: order.instrument.clone(),
+            remaining_quantity: order.quantity,
+            reason: reason.to_string(),
+            participant: order.participant.clone(),
+            client_order_id: order.client_order_id.clone(),
+            timestamp: self.clock,
+        }
+    }
+
+    fn amended_event(&self, order: &Order, kept_priority: bool) -> EngineEvent {
+        EngineEvent::Amended {
+            order_id: order.id.clone(),
+            instrument: order.instrument.clone(),
+            price: order.price,
+            remaining_quantity: order.quantity,
+            kept_priority,
+            participant: order.participant.clone(),
+            client_order_id: order.client_order_id.clone(),
+            timestamp: self.clock,
+        }
+    }
+
+    fn request_rejected_event(&self, request: &OrderRequest, reason: RejectReason) -> EngineEvent {
+        EngineEvent::RequestRejected {
+            instrument: request.instrument().to_string(),
+            participant: request.participant().to_string(),
+            client_order_id: request.client_order_id().to_string(),
+            reason,
+            timestamp: self.clock,
+        }
+    }
+
+    fn accept_stop_order(&mut self, order: Order, events: &mut Vec<EngineEvent>) {
+        let last_trade_price = self.order_books
+            .get(&order.instrument)
+            .and_then(|order_book| order_book.last_trade_price);
+
+        // A stop whose price has already been crossed is active immediately,
+        // unless the instrument is in a call and can't trade yet
+        let can_trigger = self.trading_phase(&order.instrument) == TradingPhase::Continuous;
+        if let Some(last_price) = last_trade_price.filter(|&price| can_trigger && order.is_triggered_by(price)) {
+            events.push(self.stop_triggered_event(&order, last_price));
+            self.execute_order(order.activate(), events);
+            return;
+        }
+
+        let price_precision = self.price_precision(&order.instrument);
+        self.expiries.schedule(&order, self.clock);
+        self.trigger_books
+            .entry(order.instrument.clone())
+            .or_insert_with(|| TriggerBook::with_price_precision(order.instrument.clone(), price_precision))
+            .add_order(order);
+    }
+
+    fn run_triggers(&mut self, instrument: &str, events: &mut Vec<EngineEvent>) {
+        if self.trading_phase(instrument) != TradingPhase::Continuous {
+            return;
+        }
+
+        loop {
+            let Some(last_price) = self.order_books
+                .get(instrument)
+                .and_then(|order_book| order_book.last_trade_price)
+            else {
+                return;
+            };
+
+            let Some(trigger_book) = self.trigger_books.get_mut(instrument) else {
+                return;
+            };
+
+            // One stop at a time: each execution can move the last price and
+            // trigger further stops
+            let Some(stop_order) = trigger_book.pop_triggered(last_price) else {
+                return;
+            };
+
+            if trigger_book.is_empty() {
+                self.trigger_books.remove(instrument);
+            }
+
+            events.push(self.stop_triggered_event(&stop_order, last_price));
+            self.execute_order(stop_order.activate(), events);
+        }
+    }
+
+    fn stop_triggered_event(&self, order: &Order, trigger_price: f64) -> EngineEvent {
+        EngineEvent::StopTriggered {
+            order_id: order.id.clone(),
+            instrument: order.instrument.clone(),
+            stop_price: order.stop_price.unwrap_or(order.price),
+            trigger_price,
+            participant: order.participant.clone(),
+            client_order_id: order.client_order_id.clone(),
+            timestamp: self.clock,
+        }
+    }
+
+    fn execute_order(&mut self, mut order: Order, events: &mut Vec<EngineEvent>) {
+        let in_call = self.trading_phase(&order.instrument).is_call();
+        let dynamic_band = self.dynamic_band(&order.instrument);
+        let instrument = order.instrument.clone();
+        let price_precision = self.price_precision(&instrument);
+        let allocation = self.registry.get(&instrument).map(|i| i.allocation).unwrap_or_default();
+        let tick_size = self.registry.get(&instrument).map_or(0.01, |i| i.tick_size);
+
+        // Get or create order book for this instrument; orders only reach
+        // here once the registry has accepted the instrument
         let order_book = self.order_books
             .entry(order.instrument.clone())
-            .or_insert_with(|| OrderBook::new(order.instrument.clone()));
+            .or_insert_with(|| {
+                let mut order_book = OrderBook::with_price_precision(order.instrument.clone(), price_precision);
+                order_book.allocation = allocation;
+                order_book
+            });
+
+        // During a call orders accumulate without matching
+        if in_call {
+            self.expiries.schedule(&order, self.clock);
+            order_book.add_order(order);
+            return;
+        }
+
+        // Post-only orders must add liquidity: reject them or move them to
+        // one tick behind the opposite touch
+        if order.is_post_only() && order_book.crosses(&order) {
+            if order.post_only.as_deref() == Some("reject") {
+                events.push(self.rejected_event(&order, RejectReason::PostOnlyWouldCross));
+                return;
+            }
+
+            let original_price = order.price;
+            let new_price = if order.is_buy() {
+                order_book.get_best_ask().map(|ask| ask - tick_size)
+            } else {
+                order_book.get_best_bid().map(|bid| bid + tick_size)
+            };
+
+            match new_price.filter(|&price| price > 0.0) {
+                Some(new_price) => {
+                    order.price = order_book.key_to_price(order_book.price_to_key(new_price));
+                    events.push(EngineEvent::Repriced {
+                        order_id: order.id.clone(),
+                        instrument: order.instrument.clone(),
+                        original_price,
+                        new_price: order.price,
+                        participant: order.participant.clone(),
+                        client_order_id: order.client_order_id.clone(),
+                        timestamp: self.clock,
+                    });
+                }
+                None => {
+                    events.push(self.rejected_event(&order, RejectReason::PostOnlyRepriceFailed));
+                    return;
+                }
+            }
+        }
+
+        // Don't trade at all unless the minimum quantity can fill right away
+        if let Some(min_quantity) = order.min_quantity
+            && order_book.matchable_quantity(&order) < min_quantity
+        {
+            events.push(self.rejected_event(&order, RejectReason::MinQuantityNotMet));
+            return;
+        }
+
+        let reference_price = order_book.last_trade_price;
 
         // Try to match the order
-        if order.is_buy() {
-            matches.extend(self.match_buy_order(order_book, &mut order));
+        let mut matches = if order.is_buy() {
+            Self::match_buy_order(order_book, &mut order, dynamic_band)
+        } else {
+            Self::match_sell_order(order_book, &mut order, dynamic_band)
+        };
+
+        // Clean up empty price levels
+        order_book.cleanup_empty_levels();
+
+        // Matching stops at the dynamic band; if the order could still trade
+        // beyond it the instrument is interrupted
+        let interruption = if dynamic_band.is_some() && !order.is_filled() && order_book.crosses(&order) {
+            let trigger_price = if order.is_buy() { order_book.get_best_ask() } else { order_book.get_best_bid() };
+            trigger_price.zip(reference_price)
         } else {
-            matches.extend(self.match_sell_order(order_book, &mut order));
+            None
+        };
+
+        for match_event in &mut matches {
+            match_event.sequence = order_book.next_trade_sequence();
+
+            // Resting orders filled in full can't be referenced any more
+            let (participant, client_order_id) = if order.is_buy() {
+                (&match_event.seller_participant, &match_event.seller_client_order_id)
+            } else {
+                (&match_event.buyer_participant, &match_event.buyer_client_order_id)
+            };
+            if match_event.passive_remaining_quantity == Some(0)
+                && let (Some(participant), Some(client_order_id)) = (participant, client_order_id)
+            {
+                order_book.forget_client_order(participant, client_order_id);
+            }
         }
+        if let Some(last_match) = matches.last() {
+            order_book.last_trade_price = Some(last_match.price);
+        }
+        events.extend(matches.into_iter().map(EngineEvent::Trade));
 
-        // Add remaining quantity to order book if not fully filled
+        // Add remaining quantity to order book if not fully filled; market
+        // orders never rest, whatever is left of them is cancelled
         if !order.is_filled() {
-            order_book.add_order(order);
+            if order.is_market() {
+                let reason = if interruption.is_some() {
+                    "volatility interruption"
+                } else {
+                    "no liquidity for market order"
+                };
+                events.push(self.cancelled_event(&order, reason));
+            } else {
+                self.expiries.schedule(&order, self.clock);
+                order_book.add_order(order);
+            }
         }
 
-        // Clean up empty price levels
-        order_book.cleanup_empty_levels();
+        if let Some((trigger_price, reference_price)) = interruption {
+            self.start_volatility_auction(&instrument, trigger_price, reference_price, events);
+        }
+    }
 
-        matches
+    fn price_precision(&self, instrument: &str) -> u32 {
+        self.registry.get(instrument).map_or(2, |i| i.price_precision)
     }
 
-    fn match_buy_order(&self, order_book: &mut OrderBook, buy_order: &mut Order) -> Vec<MatchEvent> {
+    fn start_volatility_auction(&mut self, instrument: &str, trigger_price: f64, reference_price: f64, events: &mut Vec<EngineEvent>) {
+        let interruption_secs = self.price_bands
+            .get(instrument)
+            .map_or(0, |bands| bands.interruption_secs);
+        let resume_at = self.clock + interruption_secs;
+
+        events.push(EngineEvent::VolatilityInterruption {
+            instrument: instrument.to_string(),
+            trigger_price,
+            reference_price,
+            resume_at,
+            timestamp: self.clock,
+        });
+        events.extend(self.set_trading_phase(instrument, TradingPhase::Auction));
+        self.volatility_auctions.insert(instrument.to_string(), resume_at);
+    }
+
+    fn match_buy_order(order_book: &mut OrderBook, buy_order: &mut Order, price_limits: Option<(f64, f64)>) -> Vec<MatchEvent> {
         let mut matches = Vec::new();
         
         // Get ask prices that can be matched (price <= buy_order.price)
         let matchable_ask_prices: Vec<i64> = order_book.asks.keys()
-            .filter(|&&ask_price| (ask_price as f64 / 100.0) <= buy_order.price)
+            .filter(|&&ask_price| buy_order.is_market() || order_book.key_to_price(ask_price) <= buy_order.price)
+            .filter(|&&ask_price| price_limits.is_none_or(|band| PriceBands::within(order_book.key_to_price(ask_price), band)))
             .cloned()
             .collect();
 
@@ -55,10 +1058,11 @@ impl MatchingEngine {
                 break;
             }
 
-            let ask_price = ask_price_key as f64 / 100.0;
+            let ask_price = order_book.key_to_price(ask_price_key);
+            let allocation = order_book.allocation;
             
             if let Some(ask_level) = order_book.asks.get_mut(&ask_price_key) {
-                matches.extend(self.pro_rata_match(buy_order, ask_level, ask_price));
+                matches.extend(Self::pro_rata_match(buy_order, ask_level, ask_price, allocation));
                 ask_level.remove_filled_orders();
             }
         }
@@ -66,12 +1070,13 @@ impl MatchingEngine {
         matches
     }
 
-    fn match_sell_order(&self, order_book: &mut OrderBook, sell_order: &mut Order) -> Vec<MatchEvent> {
+    fn match_sell_order(order_book: &mut OrderBook, sell_order: &mut Order, price_limits: Option<(f64, f64)>) -> Vec<MatchEvent> {
         let mut matches = Vec::new();
         
         // Get bid prices that can be matched (price >= sell_order.price)
         let matchable_bid_prices: Vec<i64> = order_book.bids.keys()
-            .filter(|&&bid_price| (bid_price as f64 / 100.0) >= sell_order.price)
+            .filter(|&&bid_price| sell_order.is_market() || order_book.key_to_price(bid_price) >= sell_order.price)
+            .filter(|&&bid_price| price_limits.is_none_or(|band| PriceBands::within(order_book.key_to_price(bid_price), band)))
             .cloned()
             .collect::<Vec<_>>()
             .into_iter()
@@ -83,10 +1088,11 @@ impl MatchingEngine {
                 break;
             }
 
-            let bid_price = bid_price_key as f64 / 100.0;
+            let bid_price = order_book.key_to_price(bid_price_key);
+            let allocation = order_book.allocation;
             
             if let Some(bid_level) = order_book.bids.get_mut(&bid_price_key) {
-                matches.extend(self.pro_rata_match(sell_order, bid_level, bid_price));
+                matches.extend(Self::pro_rata_match(sell_order, bid_level, bid_price, allocation));
                 bid_level.remove_filled_orders();
             }
         }
@@ -94,47 +1100,19 @@ impl MatchingEngine {
         matches
     }
 
-    fn pro_rata_match(&self, aggressive_order: &mut Order, price_level: &mut PriceLevel, match_price: f64) -> Vec<MatchEvent> {
+    fn pro_rata_match(aggressive_order: &mut Order, price_level: &mut PriceLevel, match_price: f64, allocation: AllocationStrategy) -> Vec<MatchEvent> {
         let mut matches = Vec::new();
-        let mut remaining_aggressive_qty = aggressive_order.quantity;
+        let remaining_aggressive_qty = aggressive_order.quantity;
         
         if remaining_aggressive_qty == 0 || price_level.total_quantity == 0 {
             return matches;
         }
 
-        // Calculate pro-rata allocation for each order at this price level
-        let mut allocations = Vec::new();
-        let mut total_allocated = 0u32;
-
-        // First pass: calculate proportional allocations
-        for order in &price_level.orders {
-            if order.is_filled() {
-                continue;
-            }
-
-            let proportion = order.quantity as f64 / price_level.total_quantity as f64;
-            let allocated = ((remaining_aggressive_qty as f64 * proportion).floor() as u32)
-                .min(order.quantity);
-            
-            allocations.push(allocated);
-            total_allocated += allocated;
-        }
-
-        // Second pass: distribute any remaining quantity due to rounding
-        let mut remaining_to_distribute = remaining_aggressive_qty.saturating_sub(total_allocated);
-        let mut order_index = 0;
-        
-        while remaining_to_distribute > 0 && order_index < price_level.orders.len() {
-            let order = &price_level.orders[order_index];
-            if !order.is_filled() && allocations[order_index] < order.quantity {
-                let additional = std::cmp::min(remaining_to_distribute, order.quantity - allocations[order_index]);
-                allocations[order_index] += additional;
-                remaining_to_distribute -= additional;
-            }
-            order_index += 1;
-        }
+        // Calculate the allocation for each order at this price level,
+        // pro-rata unless the instrument trades FIFO
+        let allocations = price_level.allocations(allocation, remaining_aggressive_qty);
 
-        // Third pass: execute the trades
+        // Execute the trades
         for (i, order) in price_level.orders.iter_mut().enumerate() {
             if order.is_filled() || allocations[i] == 0 {
                 continue;
@@ -148,25 +1126,37 @@ impl MatchingEngine {
             let actual_trade_quantity = std::cmp::min(aggressive_fill, passive_fill);
 
             if actual_trade_quantity > 0 {
+                let (buyer, seller) = if aggressive_order.is_buy() {
+                    (&*aggressive_order, &*order)
+                } else {
+                    (&*order, &*aggressive_order)
+                };
+                let timestamp_ns = current_timestamp_nanos();
                 let match_event = MatchEvent {
                     id: Uuid::new_v4().to_string(),
                     instrument: aggressive_order.instrument.clone(),
-                    buyer_order_id: if aggressive_order.is_buy() {
-                        aggressive_order.id.clone()
-                    } else {
-                        order.id.clone()
-                    },
-                    seller_order_id: if aggressive_order.is_sell() {
-                        aggressive_order.id.clone()
-                    } else {
-                        order.id.clone()
-                    },
+                    buyer_order_id: buyer.id.clone(),
+                    seller_order_id: seller.id.clone(),
                     price: match_price,
                     quantity: actual_trade_quantity,
-                    timestamp: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs() as i64,
+                    timestamp: timestamp_ns / NANOS_PER_SECOND,
+                    timestamp_ns,
+                    // Numbered by the book once matching is done
+                    sequence: 0,
+                    buyer_account: buyer.account.clone(),
+                    seller_account: seller.account.clone(),
+                    buyer_participant: buyer.participant.clone(),
+                    seller_participant: seller.participant.clone(),
+                    buyer_client_order_id: buyer.client_order_id.clone(),
+                    seller_client_order_id: seller.client_order_id.clone(),
+                    aggressor_side: Some(aggressive_order.side.clone()),
+                    aggressive_order_id: Some(aggressive_order.id.clone()),
+                    passive_order_id: Some(order.id.clone()),
+                    aggressive_order_price: (!aggressive_order.is_market()).then_some(aggressive_order.price),
+                    passive_remaining_quantity: Some(order.quantity),
+                    // Set by the fee schedule when the trade is published
+                    buyer_fee: 0.0,
+                    seller_fee: 0.0,
                 };
 
                 matches.push(match_event);
@@ -181,6 +1171,15 @@ impl MatchingEngine {
     }
 }
 
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+fn current_timestamp_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as i64
+}
+
 impl Default for MatchingEngine {
     fn default() -> Self {
         Self::new()
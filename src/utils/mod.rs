@@ -1,2 +1,10 @@
 pub mod partitioner;
-pub mod matching_engine;
\ No newline at end of file
+pub mod matching_engine;
+pub mod instrument_registry;
+pub mod config;
+pub mod logging;
+pub mod snapshot;
+pub mod routing;
+pub mod fee_schedule;
+pub mod session_calendar;
+pub mod backoff;
\ No newline at end of file
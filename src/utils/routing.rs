@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use crate::types::control_message::ControlMessage;
+use crate::utils::partitioner::custom_partition;
+
+/// Which orders partition each instrument's orders go to. Instruments are
+/// placed by `custom_partition` unless a migration has moved them. Clones
+/// share the same routes.
+#[derive(Debug, Clone)]
+pub struct RoutingTable {
+    partition_count: i32,
+    // Instruments routed somewhere other than their hash partition
+    overrides: Arc<RwLock<HashMap<String, i32>>>,
+}
+
+impl RoutingTable {
+    pub fn new(partition_count: i32) -> Self {
+        Self { partition_count, overrides: Arc::new(RwLock::new(HashMap::new())) }
+    }
+
+    pub fn partition_count(&self) -> i32 {
+        self.partition_count
+    }
+
+    pub fn partition_for(&self, instrument: &str) -> i32 {
+        let overrides = self.overrides.read().unwrap();
+        overrides
+            .get(instrument)
+            .copied()
+            .unwrap_or_else(|| custom_partition(instrument, self.partition_count))
+    }
+
+    pub fn set_route(&self, instrument: &str, partition: i32) {
+        let mut overrides = self.overrides.write().unwrap();
+        if partition == custom_partition(instrument, self.partition_count) {
+            overrides.remove(instrument);
+        } else {
+            overrides.insert(instrument.to_string(), partition);
+        }
+    }
+
+    /// Applies a `RouteInstrument` command. Returns whether `command` was
+    /// one.
+    pub fn apply(&self, command: &ControlMessage) -> bool {
+        match command {
+            ControlMessage::RouteInstrument { instrument, partition } => {
+                self.set_route(instrument, *partition);
+                true
+            }
+            _ => false,
+        }
+    }
+}
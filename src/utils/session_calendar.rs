@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{bail, Context};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Timelike, Weekday};
+use serde::{Serialize, Deserialize};
+use crate::types::trading_phase::SessionState;
+
+fn default_trading_days() -> Vec<Weekday> {
+    vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri]
+}
+
+/// The trading day of a group of instruments. Times are "HH:MM" in UTC
+/// and must not decrease; two equal times skip the state between them,
+/// e.g. a closing auction starting at the post-close time never happens.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionSchedule {
+    pub name: String,
+    pub instruments: Vec<String>,
+    pub pre_open: String,
+    pub opening_auction: String,
+    pub continuous: String,
+    pub closing_auction: String,
+    pub post_close: String,
+    /// End of the post-close; closed from here until the next pre-open
+    pub close: String,
+    #[serde(default = "default_trading_days")]
+    pub trading_days: Vec<Weekday>,
+    #[serde(default)]
+    pub holidays: Vec<NaiveDate>,
+    // Seconds after midnight each state starts at, in order
+    #[serde(skip)]
+    starts: Vec<(u32, SessionState)>,
+}
+
+impl SessionSchedule {
+    fn parse_starts(&mut self) -> anyhow::Result<()> {
+        let times = [
+            (&self.pre_open, SessionState::PreOpen),
+            (&self.opening_auction, SessionState::OpeningAuction),
+            (&self.continuous, SessionState::Continuous),
+            (&self.closing_auction, SessionState::ClosingAuction),
+            (&self.post_close, SessionState::PostClose),
+            (&self.close, SessionState::Closed),
+        ];
+
+        let mut starts = Vec::with_capacity(times.len());
+        for (time, state) in times {
+            let start = NaiveTime::parse_from_str(time, "%H:%M")
+                .with_context(|| format!("session {}: {} is not a HH:MM time", self.name, time))?
+                .num_seconds_from_midnight();
+            if starts.last().is_some_and(|&(previous, _)| start < previous) {
+                bail!("session {}: {:?} starts before the state ahead of it", self.name, state);
+            }
+            starts.push((start, state));
+        }
+
+        self.starts = starts;
+        Ok(())
+    }
+
+    /// The session state at `now`, in seconds since the epoch.
+    pub fn state_at(&self, now: i64) -> SessionState {
+        let Some(time) = DateTime::from_timestamp(now, 0) else {
+            return SessionState::Closed;
+        };
+        let date = time.date_naive();
+        if !self.trading_days.contains(&date.weekday()) || self.holidays.contains(&date) {
+            return SessionState::Closed;
+        }
+
+        let seconds = time.num_seconds_from_midnight();
+        self.starts
+            .iter()
+            .rev()
+            .find(|&&(start, _)| seconds >= start)
+            .map_or(SessionState::Closed, |&(_, state)| state)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CalendarFile {
+    sessions: Vec<SessionSchedule>,
+}
+
+/// Trading sessions by instrument, from a local calendar file. Instruments
+/// without a session trade whenever their phase allows, as before.
+#[derive(Debug, Clone, Default)]
+pub struct SessionCalendar {
+    schedules: Vec<SessionSchedule>,
+    by_instrument: HashMap<String, usize>,
+}
+
+impl SessionCalendar {
+    /// Loads a JSON calendar file.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading session calendar {}", path.display()))?;
+        let file: CalendarFile = serde_json::from_str(&contents)
+            .with_context(|| format!("parsing session calendar {}", path.display()))?;
+        Self::new(file.sessions).with_context(|| format!("invalid session calendar {}", path.display()))
+    }
+
+    pub fn new(mut schedules: Vec<SessionSchedule>) -> anyhow::Result<Self> {
+        let mut by_instrument = HashMap::new();
+        for (index, schedule) in schedules.iter_mut().enumerate() {
+            schedule.parse_starts()?;
+            for instrument in &schedule.instruments {
+                if by_instrument.insert(instrument.clone(), index).is_some() {
+                    bail!("instrument {} is in more than one session", instrument);
+                }
+            }
+        }
+        Ok(Self { schedules, by_instrument })
+    }
+
+    /// The session state of `instrument` at `now`, or `None` if it has no
+    /// session.
+    pub fn state(&self, instrument: &str, now: i64) -> Option<SessionState> {
+        let index = *self.by_instrument.get(instrument)?;
+        Some(self.schedules[index].state_at(now))
+    }
+}
@@ -0,0 +1,13 @@
+use serde::{Serialize, Deserialize};
+use crate::utils::matching_engine::MatchingEngine;
+
+/// The engine of one orders partition as of an orders offset, saved when
+/// the partition is revoked and restored by its next owner.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    pub partition: i32,
+    /// Offset of the last order reflected in `engine`, if any
+    pub offset: Option<i64>,
+    pub timestamp: i64,
+    pub engine: MatchingEngine,
+}
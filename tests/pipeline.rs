@@ -0,0 +1,1490 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use tokio::sync::mpsc;
+use cross_partition_order_book::services::{candles, ledger, match_monitor, matching_engine, producer, reconciliation, routing, trade_store};
+use cross_partition_order_book::services::reconciliation::Discrepancy;
+use cross_partition_order_book::services::candles::CandleBuilder;
+use cross_partition_order_book::services::ledger::Ledger;
+use cross_partition_order_book::services::trade_store::{ExportFormat, TradeQuery, TradeStore};
+use cross_partition_order_book::services::metrics::EngineMetrics;
+use cross_partition_order_book::transport::EventSink;
+use cross_partition_order_book::transport::SnapshotStore;
+use cross_partition_order_book::transport::memory::{MemoryBus, MemoryRebalancer, MemorySnapshotStore};
+use cross_partition_order_book::types::candle::Candle;
+use cross_partition_order_book::types::mass_cancel::MassCancelReport;
+use cross_partition_order_book::types::match_event::MatchEvent;
+use cross_partition_order_book::types::order::Order;
+use cross_partition_order_book::types::order_book::{BookViolation, Equilibrium, OrderBook, PriceLevel};
+use cross_partition_order_book::types::order_request::OrderRequest;
+use cross_partition_order_book::utils::config::Config;
+use cross_partition_order_book::utils::fee_schedule::{FeeRate, FeeRule, FeeSchedule};
+use cross_partition_order_book::utils::instrument_registry::InstrumentRegistry;
+use cross_partition_order_book::utils::logging::TRACE_ID_HEADER;
+use cross_partition_order_book::utils::matching_engine::MatchingEngine;
+use cross_partition_order_book::utils::partitioner::custom_partition;
+use cross_partition_order_book::utils::routing::RoutingTable;
+use cross_partition_order_book::utils::session_calendar::{SessionCalendar, SessionSchedule};
+
+const PARTITIONS: i32 = 8;
+
+fn config() -> Config {
+    let mut config = Config::default();
+    config.kafka.partition_count = PARTITIONS;
+    config.engine.session_timeout_secs = 2;
+    config.engine.check_invariants = true;
+    // Commit every batch as soon as it is published
+    config.engine.snapshot_interval_secs = 0;
+    config
+}
+
+fn routes() -> RoutingTable {
+    RoutingTable::new(PARTITIONS)
+}
+
+fn bus() -> MemoryBus {
+    let bus = MemoryBus::new();
+    for topic in ["orders", "match-events", "order-events", "market-data"] {
+        bus.create_topic(topic, PARTITIONS);
+    }
+    bus.create_topic("control-plane", 1);
+    bus
+}
+
+fn start_engine(bus: &MemoryBus) -> tokio::task::JoinHandle<()> {
+    start_engine_with(bus, FeeSchedule::default(), SessionCalendar::default(), EngineMetrics::new())
+}
+
+fn start_engine_with(bus: &MemoryBus, fees: FeeSchedule, calendar: SessionCalendar, metrics: EngineMetrics) -> tokio::task::JoinHandle<()> {
+    let (engine, rebalancer) = start_engine_instance(bus, MemorySnapshotStore::new(), fees, calendar, metrics);
+    for partition in 0..PARTITIONS {
+        rebalancer.assign(partition);
+    }
+    // The engine runs for as long as its group coordinator is around
+    tokio::spawn(async move {
+        let _rebalancer = rebalancer;
+        engine.await.unwrap();
+    })
+}
+
+/// An engine instance owning no partitions until the returned rebalancer
+/// assigns some.
+fn start_engine_instance(
+    bus: &MemoryBus,
+    snapshots: MemorySnapshotStore,
+    fees: FeeSchedule,
+    calendar: SessionCalendar,
+    metrics: EngineMetrics,
+) -> (tokio::task::JoinHandle<()>, MemoryRebalancer) {
+    let registry = InstrumentRegistry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/instruments.json"))
+        .expect("instrument registry");
+    let (orders, rebalancer) = bus.partitioned_source("orders");
+    let engine = tokio::spawn(matching_engine::run(
+        orders,
+        bus.source(&["control-plane"]),
+        bus.sink(),
+        snapshots,
+        registry,
+        fees,
+        calendar,
+        routes(),
+        config(),
+        metrics,
+    ));
+    (engine, rebalancer)
+}
+
+fn start_monitor(bus: &MemoryBus) -> (tokio::task::JoinHandle<()>, mpsc::UnboundedReceiver<(MatchEvent, i32)>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let monitor = tokio::spawn(match_monitor::run(bus.source(&["match-events"]), move |trade, record| {
+        let _ = tx.send((trade, record.partition));
+    }));
+    (monitor, rx)
+}
+
+fn order(id: &str, instrument: &str, side: &str, price: f64, quantity: u32) -> Order {
+    Order::new(id.to_string(), instrument.to_string(), side.to_string(), price, quantity, 0)
+}
+
+/// A trade between `{id}-buy` and `{id}-sell`, as the engine publishes it,
+/// for services fed without one.
+fn trade(id: &str, instrument: &str, price: f64, quantity: u32, timestamp: i64) -> MatchEvent {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "instrument": instrument,
+        "buyer_order_id": format!("{}-buy", id),
+        "seller_order_id": format!("{}-sell", id),
+        "price": price,
+        "quantity": quantity,
+        "timestamp": timestamp,
+    }))
+    .unwrap()
+}
+
+async fn next_trade(trades: &mut mpsc::UnboundedReceiver<(MatchEvent, i32)>) -> (MatchEvent, i32) {
+    tokio::time::timeout(Duration::from_secs(5), trades.recv())
+        .await
+        .expect("timed out waiting for a trade")
+        .expect("monitor stopped")
+}
+
+async fn wait_for(mut condition: impl FnMut() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for condition");
+}
+
+fn payloads(bus: &MemoryBus, topic: &str) -> Vec<String> {
+    bus.records(topic).into_iter().filter_map(|record| record.payload).collect()
+}
+
+/// Sends `command` on the control plane and waits until the engine owning
+/// `instrument` has applied it: the mass cancel sent right after is only
+/// reported once everything before it is.
+async fn send_control(bus: &MemoryBus, command: &str, instrument: &str) {
+    let partition = routes().partition_for(instrument);
+    let reports = |bus: &MemoryBus| {
+        payloads(bus, "control-plane")
+            .iter()
+            .filter_map(|payload| serde_json::from_str::<MassCancelReport>(payload).ok())
+            .filter(|report| report.partition == partition)
+            .count()
+    };
+    let before = reports(bus);
+    let sink = bus.sink();
+    let barrier = r#"{"command":"mass_cancel","filter":{"session":"control-barrier"}}"#;
+    sink.send("control-plane", "control", command, None).await.unwrap();
+    sink.send("control-plane", "control", barrier, None).await.unwrap();
+    wait_for(|| reports(bus) > before).await;
+}
+
+/// The order-events payloads about `order_id`.
+fn order_events(bus: &MemoryBus, order_id: &str) -> Vec<String> {
+    let order_id = format!(r#""order_id":"{}""#, order_id);
+    payloads(bus, "order-events").into_iter().filter(|p| p.contains(&order_id)).collect()
+}
+
+#[tokio::test]
+async fn crossing_orders_trade_on_the_instrument_partition() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    producer::publish_order(&sink, &order("buy-1", "AAPL", "buy", 150.00, 100), &routes(), &config()).await.unwrap();
+    producer::publish_order(&sink, &order("sell-1", "AAPL", "sell", 149.50, 40), &routes(), &config()).await.unwrap();
+
+    let (trade, partition) = next_trade(&mut trades).await;
+    assert_eq!(trade.buyer_order_id, "buy-1");
+    assert_eq!(trade.seller_order_id, "sell-1");
+    assert_eq!(trade.price, 150.00);
+    assert_eq!(trade.quantity, 40);
+    assert_eq!(partition, custom_partition("AAPL", PARTITIONS));
+
+    // The incoming sell took liquidity from the resting buy
+    assert_eq!(trade.aggressive_order_id.as_deref(), Some("sell-1"));
+    assert_eq!(trade.passive_order_id.as_deref(), Some("buy-1"));
+    assert_eq!(trade.aggressive_order_price, Some(149.50));
+    assert_eq!(trade.passive_remaining_quantity, Some(60));
+    assert_eq!(trade.sequence, 1);
+    assert_eq!(trade.timestamp_ns / 1_000_000_000, trade.timestamp);
+
+    // Both orders are committed once processed
+    wait_for(|| bus.committed_offset("orders", partition) == Some(1)).await;
+
+    // The trade carries the trace id of the order that caused it
+    let sell_trace_id = bus.records("orders")[1].header(TRACE_ID_HEADER).map(str::to_string);
+    assert!(sell_trace_id.is_some());
+    assert_eq!(bus.records("match-events")[0].header(TRACE_ID_HEADER).map(str::to_string), sell_trace_id);
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn instruments_on_different_partitions_are_matched_independently() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    for (id, instrument, side, price) in [
+        ("aapl-buy", "AAPL", "buy", 150.00),
+        ("msft-buy", "MSFT", "buy", 300.00),
+        ("aapl-sell", "AAPL", "sell", 150.00),
+        ("msft-sell", "MSFT", "sell", 300.00),
+    ] {
+        producer::publish_order(&sink, &order(id, instrument, side, price, 10), &routes(), &config()).await.unwrap();
+    }
+
+    let mut received = [next_trade(&mut trades).await, next_trade(&mut trades).await];
+    received.sort_by(|a, b| a.0.instrument.cmp(&b.0.instrument));
+
+    assert_eq!(received[0].0.buyer_order_id, "aapl-buy");
+    assert_eq!(received[0].1, custom_partition("AAPL", PARTITIONS));
+    assert_eq!(received[1].0.buyer_order_id, "msft-buy");
+    assert_eq!(received[1].1, custom_partition("MSFT", PARTITIONS));
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn orders_for_unknown_instruments_are_rejected() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let sink = bus.sink();
+
+    producer::publish_order(&sink, &order("unknown-1", "ZZZZ", "buy", 10.00, 10), &routes(), &config()).await.unwrap();
+
+    wait_for(|| !bus.records("order-events").is_empty()).await;
+    let rejection = &bus.records("order-events")[0];
+    assert_eq!(rejection.partition, custom_partition("ZZZZ", PARTITIONS));
+    let payload = rejection.payload.as_deref().unwrap();
+    assert!(payload.contains(r#""event":"rejected""#), "{}", payload);
+    assert!(payload.contains(r#""reason":"unknown_instrument""#), "{}", payload);
+    assert!(bus.records("match-events").is_empty());
+
+    engine.abort();
+}
+
+#[tokio::test]
+async fn post_only_orders_rest_reprice_or_are_rejected_instead_of_crossing() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    let orders = [
+        order("s1", "AAPL", "sell", 150.00, 50),
+        order("po-1", "AAPL", "buy", 149.00, 10).with_post_only("reject"),
+        order("po-2", "AAPL", "buy", 150.00, 10).with_post_only("reject"),
+        order("po-3", "AAPL", "buy", 151.00, 10).with_post_only("reprice"),
+        order("po-4", "AAPL", "buy", 0.0, 10).with_order_type("market").with_post_only("reject"),
+        order("po-5", "AAPL", "buy", 149.00, 10).with_post_only("always"),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+
+    wait_for(|| ["po-2", "po-3", "po-4", "po-5"].iter().all(|order_id| !order_events(&bus, order_id).is_empty())).await;
+    assert!(order_events(&bus, "po-1").is_empty());
+    assert!(order_events(&bus, "po-2")[0].contains(r#""reason":"post_only_would_cross""#));
+    let repriced = &order_events(&bus, "po-3")[0];
+    assert!(repriced.contains(r#""event":"repriced""#), "{}", repriced);
+    assert!(repriced.contains(r#""original_price":151.0,"new_price":149.99"#), "{}", repriced);
+    for order_id in ["po-4", "po-5"] {
+        assert!(order_events(&bus, order_id)[0].contains(r#""reason":"invalid_attributes""#));
+    }
+    assert!(bus.records("match-events").is_empty());
+
+    // Both resting post-only orders provide liquidity to a later sell
+    producer::publish_order(&sink, &order("s2", "AAPL", "sell", 149.00, 20), &routes(), &config()).await.unwrap();
+    let (first, _) = next_trade(&mut trades).await;
+    let (second, _) = next_trade(&mut trades).await;
+    assert_eq!((first.passive_order_id.as_deref(), first.price), (Some("po-3"), 149.99));
+    assert_eq!((second.passive_order_id.as_deref(), second.price), (Some("po-1"), 149.00));
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn minimum_quantity_orders_trade_only_when_enough_is_available() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    let orders = [
+        order("s1", "AAPL", "sell", 150.00, 30),
+        order("mq-1", "AAPL", "buy", 150.00, 50).with_min_quantity(40),
+        order("mq-2", "AAPL", "buy", 150.00, 50).with_min_quantity(60),
+        order("mq-3", "AAPL", "buy", 150.00, 50).with_min_quantity(20),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+
+    // Only mq-3 can fill its minimum from the 30 on offer
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!((trade.buyer_order_id.as_str(), trade.quantity), ("mq-3", 30));
+    assert!(order_events(&bus, "mq-1")[0].contains(r#""reason":"min_quantity_not_met""#));
+    assert!(order_events(&bus, "mq-2")[0].contains(r#""reason":"invalid_attributes""#));
+    assert_eq!(bus.records("match-events").len(), 1);
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn a_stop_limit_order_waits_for_a_trade_at_its_stop_price() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    let stop = order("stop-1", "AAPL", "buy", 150.50, 30).with_order_type("stop_limit").with_stop_price(150.00);
+    for order in [order("s1", "AAPL", "sell", 150.00, 50), stop, order("b1", "AAPL", "buy", 150.00, 10)] {
+        producer::publish_order(&sink, &order, &routes(), &config()).await.unwrap();
+    }
+
+    // The stop crossed the ask on arrival but only the trade activates it
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!((trade.buyer_order_id.as_str(), trade.quantity), ("b1", 10));
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!((trade.buyer_order_id.as_str(), trade.seller_order_id.as_str()), ("stop-1", "s1"));
+    assert_eq!((trade.price, trade.quantity), (150.00, 30));
+
+    let events = order_events(&bus, "stop-1");
+    assert!(events[0].contains(r#""event":"stop_triggered""#), "{:?}", events);
+    assert!(events[0].contains(r#""trigger_price":150.0"#), "{:?}", events);
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn triggered_stops_cascade_through_the_book() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    let orders = [
+        order("s1", "AAPL", "sell", 150.00, 10),
+        order("s2", "AAPL", "sell", 151.00, 10),
+        order("s3", "AAPL", "sell", 152.00, 10),
+        order("stop-2", "AAPL", "buy", 0.0, 10).with_order_type("stop").with_stop_price(151.00),
+        order("stop-1", "AAPL", "buy", 0.0, 10).with_order_type("stop").with_stop_price(150.00),
+        order("b1", "AAPL", "buy", 150.00, 10),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+
+    // Each stop's trade moves the last price to the next stop
+    let mut fills = Vec::new();
+    for _ in 0..3 {
+        let (trade, _) = next_trade(&mut trades).await;
+        fills.push((trade.buyer_order_id, trade.seller_order_id, trade.price));
+    }
+    assert_eq!(fills, [
+        ("b1".to_string(), "s1".to_string(), 150.00),
+        ("stop-1".to_string(), "s2".to_string(), 151.00),
+        ("stop-2".to_string(), "s3".to_string(), 152.00),
+    ]);
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn a_waiting_stop_can_be_amended_by_client_order_id() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+    let amend = |price: Option<f64>, quantity: Option<u32>| OrderRequest::Amend {
+        instrument: "AAPL".to_string(),
+        participant: "firm-a".to_string(),
+        client_order_id: "c1".to_string(),
+        price,
+        quantity,
+    };
+
+    let stop = order("stop-1", "AAPL", "buy", 150.50, 40)
+        .with_order_type("stop_limit")
+        .with_stop_price(150.00)
+        .with_client_order_id("firm-a", "c1");
+    for order in [order("s1", "AAPL", "sell", 150.00, 10), order("s2", "AAPL", "sell", 151.00, 50), stop] {
+        producer::publish_order(&sink, &order, &routes(), &config()).await.unwrap();
+    }
+
+    producer::publish_request(&sink, &amend(None, Some(20)), &routes(), &config()).await.unwrap();
+    producer::publish_request(&sink, &amend(Some(151.00), None), &routes(), &config()).await.unwrap();
+    wait_for(|| order_events(&bus, "stop-1").len() == 2).await;
+    let events = order_events(&bus, "stop-1");
+    assert!(events[0].contains(r#""remaining_quantity":20,"kept_priority":true"#), "{:?}", events);
+    assert!(events[1].contains(r#""price":151.0,"remaining_quantity":20,"kept_priority":false"#), "{:?}", events);
+
+    // Triggered, the amended stop buys 20 at up to its new limit
+    producer::publish_order(&sink, &order("b1", "AAPL", "buy", 150.00, 10), &routes(), &config()).await.unwrap();
+    next_trade(&mut trades).await;
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!((trade.buyer_order_id.as_str(), trade.seller_order_id.as_str()), ("stop-1", "s2"));
+    assert_eq!((trade.price, trade.quantity), (151.00, 20));
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn stop_orders_without_a_stop_price_are_rejected() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let sink = bus.sink();
+
+    let missing = order("stop-1", "AAPL", "buy", 0.0, 10).with_order_type("stop");
+    let zero = order("stop-2", "AAPL", "sell", 149.00, 10).with_order_type("stop_limit").with_stop_price(0.0);
+    for order in [&missing, &zero] {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+
+    for order_id in ["stop-1", "stop-2"] {
+        wait_for(|| !order_events(&bus, order_id).is_empty()).await;
+        let events = order_events(&bus, order_id);
+        assert!(events[0].contains(r#""reason":"invalid_attributes""#), "{:?}", events);
+    }
+
+    engine.abort();
+}
+
+#[tokio::test]
+async fn orders_must_fit_the_instrument_reference_data() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+    let update = |status: &str| {
+        format!(
+            r#"{{"command":"update_instrument","definition":{{"symbol":"NVDA","tick_size":0.05,"lot_size":10,"min_quantity":10,"max_quantity":1000,"price_precision":2,"currency":"USD","status":"{}"}}}}"#,
+            status
+        )
+    };
+    let reason = |bus: &MemoryBus, order_id: &str| {
+        let events = order_events(bus, order_id);
+        events.first().and_then(|event| event.split(r#""reason":""#).nth(1)?.split('"').next().map(str::to_string))
+    };
+
+    send_control(&bus, &update("active"), "NVDA").await;
+
+    let orders = [
+        order("n1", "NVDA", "buy", 100.03, 10),
+        order("n2", "NVDA", "buy", 100.05, 15),
+        order("n3", "NVDA", "buy", 100.05, 2000),
+        order("n4", "NVDA", "buy", 100.05, 20),
+        order("n5", "NVDA", "sell", 100.05, 20),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!((trade.buyer_order_id.as_str(), trade.seller_order_id.as_str(), trade.quantity), ("n4", "n5", 20));
+    assert_eq!(reason(&bus, "n1").as_deref(), Some("invalid_tick_size"));
+    assert_eq!(reason(&bus, "n2").as_deref(), Some("invalid_lot_size"));
+    assert_eq!(reason(&bus, "n3").as_deref(), Some("quantity_out_of_range"));
+
+    send_control(&bus, &update("halted"), "NVDA").await;
+    producer::publish_order(&sink, &order("n6", "NVDA", "buy", 100.05, 20), &routes(), &config()).await.unwrap();
+    wait_for(|| reason(&bus, "n6").is_some()).await;
+    assert_eq!(reason(&bus, "n6").as_deref(), Some("instrument_halted"));
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn control_plane_auction_uncrosses_at_a_single_price() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    let set_phase = |phase: &str| {
+        format!(r#"{{"command":"set_trading_phase","instrument":"AAPL","phase":"{}"}}"#, phase)
+    };
+
+    sink.send("control-plane", "control", &set_phase("auction"), None).await.unwrap();
+    wait_for(|| payloads(&bus, "market-data").iter().any(|p| p.contains("phase_changed"))).await;
+
+    producer::publish_order(&sink, &order("b1", "AAPL", "buy", 151.00, 100), &routes(), &config()).await.unwrap();
+    producer::publish_order(&sink, &order("s1", "AAPL", "sell", 149.00, 60), &routes(), &config()).await.unwrap();
+    producer::publish_order(&sink, &order("s2", "AAPL", "sell", 150.00, 60), &routes(), &config()).await.unwrap();
+
+    // Orders rest during the call; only indicative prices are published
+    wait_for(|| payloads(&bus, "market-data").iter().filter(|p| p.contains("indicative_price")).count() == 3).await;
+    assert!(bus.records("match-events").is_empty());
+
+    sink.send("control-plane", "control", &set_phase("continuous"), None).await.unwrap();
+
+    let (first, _) = next_trade(&mut trades).await;
+    let (second, _) = next_trade(&mut trades).await;
+    assert_eq!(first.price, second.price);
+    assert_eq!(first.quantity + second.quantity, 100);
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn a_closing_call_publishes_indicative_prices_and_uncrosses_before_the_close() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+    let set_phase = |phase: &str| format!(r#"{{"command":"set_trading_phase","instrument":"MSFT","phase":"{}"}}"#, phase);
+    let indicative = |bus: &MemoryBus| payloads(bus, "market-data").into_iter().filter(|p| p.contains("indicative_price")).collect::<Vec<_>>();
+
+    sink.send("control-plane", "control", &set_phase("auction"), None).await.unwrap();
+    wait_for(|| payloads(&bus, "market-data").iter().any(|p| p.contains(r#""phase":"auction""#))).await;
+
+    // Orders that need to trade on arrival have no place in a call
+    let orders = [
+        order("m1", "MSFT", "buy", 0.0, 10).with_order_type("market"),
+        order("m2", "MSFT", "buy", 101.00, 10).with_min_quantity(5),
+        order("b1", "MSFT", "buy", 101.00, 10),
+        order("s1", "MSFT", "sell", 100.00, 10),
+        order("b2", "MSFT", "buy", 100.00, 5),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+    wait_for(|| indicative(&bus).len() == 3).await;
+    for order_id in ["m1", "m2"] {
+        assert!(order_events(&bus, order_id)[0].contains(r#""reason":"not_allowed_in_phase""#));
+    }
+    // 101.00 matches as much as 100.00 and leaves nothing over
+    let last = &indicative(&bus)[2];
+    assert!(last.contains(r#""price":101.0,"matched_volume":10,"imbalance":0"#), "{}", last);
+    assert!(bus.records("match-events").is_empty());
+
+    sink.send("control-plane", "control", &set_phase("closed"), None).await.unwrap();
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!((trade.buyer_order_id.as_str(), trade.seller_order_id.as_str()), ("b1", "s1"));
+    assert_eq!((trade.price, trade.quantity), (101.00, 10));
+
+    producer::publish_order(&sink, &order("b3", "MSFT", "buy", 101.00, 10), &routes(), &config()).await.unwrap();
+    wait_for(|| !order_events(&bus, "b3").is_empty()).await;
+    assert!(order_events(&bus, "b3")[0].contains(r#""reason":"instrument_closed""#));
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[test]
+fn the_equilibrium_maximises_volume_then_minimises_imbalance_then_follows_the_reference() {
+    let book = |bids: &[(f64, u32)], asks: &[(f64, u32)]| {
+        let mut book = OrderBook::new("AAPL".to_string());
+        for (index, &(price, quantity)) in bids.iter().enumerate() {
+            book.add_order(order(&format!("b{}", index), "AAPL", "buy", price, quantity));
+        }
+        for (index, &(price, quantity)) in asks.iter().enumerate() {
+            book.add_order(order(&format!("s{}", index), "AAPL", "sell", price, quantity));
+        }
+        book
+    };
+    let equilibrium = |price, volume, buy_quantity, sell_quantity| Some(Equilibrium { price, volume, buy_quantity, sell_quantity });
+
+    let most_volume = book(&[(101.00, 10), (100.00, 20)], &[(99.00, 15), (100.00, 10)]);
+    assert_eq!(most_volume.equilibrium(None), equilibrium(100.00, 25, 30, 25));
+
+    let least_imbalance = book(&[(101.00, 10), (100.00, 5)], &[(100.00, 10)]);
+    assert_eq!(least_imbalance.equilibrium(None), equilibrium(101.00, 10, 10, 10));
+
+    // Equal volume and imbalance: closest to the reference, else the lower
+    let tied = book(&[(101.00, 10)], &[(100.00, 10)]);
+    assert_eq!(tied.equilibrium(Some(105.00)), equilibrium(101.00, 10, 10, 10));
+    assert_eq!(tied.equilibrium(Some(95.00)), equilibrium(100.00, 10, 10, 10));
+    assert_eq!(tied.equilibrium(None), equilibrium(100.00, 10, 10, 10));
+
+    assert_eq!(book(&[(99.00, 10)], &[(100.00, 10)]).equilibrium(None), None);
+}
+
+#[tokio::test]
+async fn orders_outside_the_static_price_band_are_rejected() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    let bands = r#"{"command":"set_price_bands","instrument":"MSFT","bands":{"static_band_pct":5.0,"reference_price":100.0}}"#;
+    send_control(&bus, bands, "MSFT").await;
+
+    let orders = [
+        order("b1", "MSFT", "buy", 106.00, 10),
+        order("s1", "MSFT", "sell", 94.00, 10),
+        order("s2", "MSFT", "sell", 104.00, 10),
+        order("b2", "MSFT", "buy", 105.00, 10),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!((trade.buyer_order_id.as_str(), trade.seller_order_id.as_str(), trade.price), ("b2", "s2", 104.00));
+    for order_id in ["b1", "s1"] {
+        assert!(order_events(&bus, order_id)[0].contains(r#""reason":"price_outside_band""#));
+    }
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn a_trade_outside_the_dynamic_band_interrupts_matching_for_an_auction() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    let bands = r#"{"command":"set_price_bands","instrument":"AAPL","bands":{"dynamic_band_pct":2.0,"interruption_secs":1}}"#;
+    send_control(&bus, bands, "AAPL").await;
+    let start = 1_700_000_000_000;
+    bus.set_time(start);
+
+    let orders = [
+        order("b1", "AAPL", "buy", 150.00, 100),
+        order("s1", "AAPL", "sell", 150.00, 10),
+        order("s2", "AAPL", "sell", 152.00, 10),
+        order("s3", "AAPL", "sell", 160.00, 50),
+        order("b2", "AAPL", "buy", 160.00, 20),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+
+    // b2 trades within 2% of 150.00 and stops short of 160.00
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!(trade.seller_order_id, "s1");
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!((trade.buyer_order_id.as_str(), trade.seller_order_id.as_str(), trade.price), ("b2", "s2", 152.00));
+
+    wait_for(|| payloads(&bus, "control-plane").iter().any(|p| p.contains("volatility_interruption"))).await;
+    let interruption = payloads(&bus, "control-plane").into_iter().find(|p| p.contains("volatility_interruption")).unwrap();
+    assert!(interruption.contains(r#""trigger_price":160.0,"reference_price":150.0"#), "{}", interruption);
+    assert!(payloads(&bus, "market-data").iter().any(|p| p.contains(r#""phase":"auction""#)));
+
+    // Once the interruption is over the next order ends the auction, which
+    // uncrosses the rest of b2 against s3
+    bus.set_time(start + 2000);
+    producer::publish_order(&sink, &order("b3", "AAPL", "buy", 140.00, 10), &routes(), &config()).await.unwrap();
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!((trade.buyer_order_id.as_str(), trade.seller_order_id.as_str()), ("b2", "s3"));
+    assert_eq!((trade.price, trade.quantity), (160.00, 10));
+    wait_for(|| payloads(&bus, "market-data").iter().any(|p| p.contains(r#""phase":"continuous""#))).await;
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn metrics_count_orders_trades_and_rejects() {
+    let bus = bus();
+    let metrics = EngineMetrics::new();
+    let engine = start_engine_with(&bus, FeeSchedule::default(), SessionCalendar::default(), metrics.clone());
+    let sink = bus.sink();
+
+    producer::publish_order(&sink, &order("buy-1", "AAPL", "buy", 150.00, 100), &routes(), &config()).await.unwrap();
+    producer::publish_order(&sink, &order("sell-1", "AAPL", "sell", 150.00, 40), &routes(), &config()).await.unwrap();
+    producer::publish_order(&sink, &order("unknown-1", "ZZZZ", "buy", 10.00, 10), &routes(), &config()).await.unwrap();
+
+    wait_for(|| bus.records("order-events").len() == 1 && bus.records("match-events").len() == 1).await;
+
+    let aapl = custom_partition("AAPL", PARTITIONS).to_string();
+    let zzzz = custom_partition("ZZZZ", PARTITIONS).to_string();
+    assert_eq!(metrics.orders_processed.with_label_values(&[aapl.as_str(), "AAPL"]).get(), 2);
+    assert_eq!(metrics.trades.with_label_values(&[aapl.as_str(), "AAPL"]).get(), 1);
+    assert_eq!(metrics.rejects.with_label_values(&[zzzz.as_str(), "ZZZZ", "unknown_instrument"]).get(), 1);
+    assert_eq!(metrics.book_quantity.with_label_values(&[aapl.as_str(), "AAPL", "bid"]).get(), 60);
+
+    let text = metrics.encode();
+    assert!(text.contains("matching_engine_order_to_trade_latency_seconds_count"), "{}", text);
+
+    engine.abort();
+}
+
+#[tokio::test]
+async fn a_backlog_is_processed_in_batches_and_published_in_order() {
+    let bus = bus();
+    let sink = bus.sink();
+
+    // Queue everything before the engine starts so it is read in batches
+    for i in 0..150 {
+        let (side, price) = if i % 2 == 0 { ("buy", 150.00) } else { ("sell", 150.00) };
+        producer::publish_order(&sink, &order(&format!("o{:03}", i), "AAPL", side, price, 10), &routes(), &config()).await.unwrap();
+    }
+
+    let engine = start_engine(&bus);
+    let partition = custom_partition("AAPL", PARTITIONS);
+    wait_for(|| bus.committed_offset("orders", partition) == Some(149)).await;
+
+    // Every sell crosses the buy before it, and trades keep order sequence
+    let trades: Vec<MatchEvent> = payloads(&bus, "match-events")
+        .iter()
+        .map(|payload| serde_json::from_str(payload).unwrap())
+        .collect();
+    assert_eq!(trades.len(), 75);
+    for (i, trade) in trades.iter().enumerate() {
+        assert_eq!(trade.buyer_order_id, format!("o{:03}", 2 * i));
+        assert_eq!(trade.seller_order_id, format!("o{:03}", 2 * i + 1));
+    }
+
+    engine.abort();
+}
+
+#[tokio::test]
+async fn a_failed_publish_is_retried_before_the_batch_is_committed() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+    let partition = custom_partition("AAPL", PARTITIONS);
+
+    producer::publish_order(&sink, &order("buy-1", "AAPL", "buy", 150.00, 100), &routes(), &config()).await.unwrap();
+    wait_for(|| bus.committed_offset("orders", partition) == Some(0)).await;
+
+    bus.fail_sends("match-events", 1);
+    producer::publish_order(&sink, &order("sell-1", "AAPL", "sell", 150.00, 40), &routes(), &config()).await.unwrap();
+
+    // The trade is delivered on the second attempt, and only then is the
+    // sell committed
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!(trade.seller_order_id, "sell-1");
+    wait_for(|| bus.committed_offset("orders", partition) == Some(1)).await;
+
+    // Later batches go through as usual, with the trade published once
+    producer::publish_order(&sink, &order("sell-2", "AAPL", "sell", 150.00, 10), &routes(), &config()).await.unwrap();
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!(trade.seller_order_id, "sell-2");
+    wait_for(|| bus.committed_offset("orders", partition) == Some(2)).await;
+    assert_eq!(bus.records("match-events").len(), 2);
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn a_revoked_partition_is_restored_by_its_next_owner() {
+    let bus = bus();
+    let snapshots = MemorySnapshotStore::new();
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+    let partition = custom_partition("AAPL", PARTITIONS);
+
+    let (first, first_rebalancer) = start_engine_instance(&bus, snapshots.clone(), FeeSchedule::default(), SessionCalendar::default(), EngineMetrics::new());
+    first_rebalancer.assign(partition);
+
+    producer::publish_order(&sink, &order("buy-1", "AAPL", "buy", 150.00, 100), &routes(), &config()).await.unwrap();
+    wait_for(|| bus.committed_offset("orders", partition) == Some(0)).await;
+
+    // Revoking waits for the first instance to snapshot and let go
+    first_rebalancer.revoke(partition).await;
+    assert!(snapshots.load(partition).await.unwrap().is_some());
+
+    let (second, second_rebalancer) = start_engine_instance(&bus, snapshots.clone(), FeeSchedule::default(), SessionCalendar::default(), EngineMetrics::new());
+    second_rebalancer.assign(partition);
+
+    // The resting buy moved with the book
+    producer::publish_order(&sink, &order("sell-1", "AAPL", "sell", 150.00, 40), &routes(), &config()).await.unwrap();
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!(trade.buyer_order_id, "buy-1");
+    assert_eq!(trade.seller_order_id, "sell-1");
+    wait_for(|| bus.committed_offset("orders", partition) == Some(1)).await;
+    assert_eq!(bus.records("match-events").len(), 1);
+
+    first.abort();
+    second.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn a_partition_stuck_on_undelivered_events_is_replayed_by_its_next_owner() {
+    let bus = bus();
+    let snapshots = MemorySnapshotStore::new();
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+    let partition = custom_partition("AAPL", PARTITIONS);
+
+    let (first, first_rebalancer) = start_engine_instance(&bus, snapshots.clone(), FeeSchedule::default(), SessionCalendar::default(), EngineMetrics::new());
+    first_rebalancer.assign(partition);
+
+    producer::publish_order(&sink, &order("buy-1", "AAPL", "buy", 150.00, 100), &routes(), &config()).await.unwrap();
+    wait_for(|| bus.committed_offset("orders", partition) == Some(0)).await;
+
+    // The trade cannot be delivered, so the first instance retries it until
+    // the partition is taken away, and then drops its books unsaved
+    bus.fail_sends("match-events", usize::MAX);
+    producer::publish_order(&sink, &order("sell-1", "AAPL", "sell", 150.00, 40), &routes(), &config()).await.unwrap();
+    wait_for(|| bus.records("orders").len() == 2).await;
+    tokio::time::timeout(Duration::from_secs(5), first_rebalancer.revoke(partition))
+        .await
+        .expect("revoke waited for the undelivered trade");
+    assert_eq!(bus.committed_offset("orders", partition), Some(0));
+
+    // The next owner starts from the snapshot behind the commit and
+    // matches the sell again
+    bus.fail_sends("match-events", 0);
+    let (second, second_rebalancer) = start_engine_instance(&bus, snapshots.clone(), FeeSchedule::default(), SessionCalendar::default(), EngineMetrics::new());
+    second_rebalancer.assign(partition);
+
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!(trade.buyer_order_id, "buy-1");
+    assert_eq!(trade.seller_order_id, "sell-1");
+    wait_for(|| bus.committed_offset("orders", partition) == Some(1)).await;
+    assert_eq!(bus.records("match-events").len(), 1);
+
+    first.abort();
+    second.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn a_migrated_instrument_keeps_its_book_on_the_new_partition() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    // One producer follows the control-plane, the other keeps the old route
+    let followed = routes();
+    let follower = tokio::spawn(routing::follow(bus.source(&["control-plane"]), followed.clone()));
+    let stale = routes();
+
+    let source = custom_partition("AAPL", PARTITIONS);
+    let target = (source + 1) % PARTITIONS;
+
+    producer::publish_order(&sink, &order("buy-1", "AAPL", "buy", 150.00, 100), &stale, &config()).await.unwrap();
+    wait_for(|| bus.committed_offset("orders", source) == Some(0)).await;
+
+    let migrate = format!(r#"{{"command":"migrate_instrument","instrument":"AAPL","target_partition":{}}}"#, target);
+    sink.send("control-plane", "control", &migrate, None).await.unwrap();
+    wait_for(|| followed.partition_for("AAPL") == target).await;
+
+    // The stale route is forwarded, the new one goes straight to the target
+    producer::publish_order(&sink, &order("sell-1", "AAPL", "sell", 150.00, 40), &stale, &config()).await.unwrap();
+    producer::publish_order(&sink, &order("sell-2", "AAPL", "sell", 150.00, 30), &followed, &config()).await.unwrap();
+
+    let (first, first_partition) = next_trade(&mut trades).await;
+    let (second, second_partition) = next_trade(&mut trades).await;
+    assert_eq!((first_partition, second_partition), (target, target));
+    assert_eq!(first.buyer_order_id, "buy-1");
+    assert_eq!(second.buyer_order_id, "buy-1");
+    assert_eq!(first.quantity + second.quantity, 70);
+
+    engine.abort();
+    monitor.abort();
+    follower.abort();
+}
+
+#[tokio::test]
+async fn the_ledger_books_positions_cash_and_pnl_per_account() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let sink = bus.sink();
+
+    let registry = InstrumentRegistry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/instruments.json")).unwrap();
+    let book = Arc::new(Mutex::new(Ledger::open_in_memory().unwrap()));
+    let ledger_task = tokio::spawn(ledger::run(bus.source(&["match-events"]), book.clone(), registry));
+
+    // alice buys 100 from bob at 150, then sells 40 to carol at 155
+    let orders = [
+        order("a1", "AAPL", "buy", 150.00, 100).with_account("alice"),
+        order("b1", "AAPL", "sell", 150.00, 100).with_account("bob"),
+        order("c1", "AAPL", "buy", 155.00, 40).with_account("carol"),
+        order("a2", "AAPL", "sell", 155.00, 40).with_account("alice"),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+    wait_for(|| book.lock().unwrap().positions("carol").unwrap().len() == 1).await;
+
+    let ledger = book.lock().unwrap();
+    let alice = &ledger.positions("alice").unwrap()[0];
+    assert_eq!(alice.quantity, 60);
+    assert_eq!(alice.realized_pnl, 200.0);
+    assert_eq!(alice.unrealized_pnl, 300.0);
+    assert_eq!(ledger.positions("bob").unwrap()[0].quantity, -100);
+
+    let cash = |account: &str| ledger.balances(account).unwrap()[0].cash;
+    assert_eq!(cash("alice"), -15000.0 + 6200.0);
+    assert_eq!(cash("bob"), 15000.0);
+    assert_eq!(cash("carol"), -6200.0);
+    drop(ledger);
+
+    engine.abort();
+    ledger_task.abort();
+}
+
+#[test]
+fn a_statement_shows_positions_as_of_the_end_of_its_day() {
+    let mut ledger = Ledger::open_in_memory().unwrap();
+    let between = |buyer: &str, seller: &str, trade: MatchEvent| MatchEvent {
+        buyer_account: Some(buyer.to_string()),
+        seller_account: Some(seller.to_string()),
+        ..trade
+    };
+    // 2026-10-18 and 2026-10-19, midday UTC
+    let (day_1, day_2) = (1792324800, 1792411200);
+    ledger.apply_trade(&between("alice", "bob", trade("t1", "AAPL", 150.00, 100, day_1)), "USD").unwrap();
+    ledger.apply_trade(&between("bob", "alice", trade("t2", "AAPL", 155.00, 40, day_2)), "USD").unwrap();
+
+    let statement = ledger.statement("2026-10-18".parse().unwrap()).unwrap();
+    let alice = &statement.positions[0];
+    assert_eq!((alice.account.as_str(), alice.bought, alice.sold, alice.quantity), ("alice", 100, 0, 100));
+    assert_eq!((alice.last_price, alice.realized_pnl), (Some(150.00), 0.0));
+    assert_eq!(statement.balances[0].cash, -15000.0);
+
+    let statement = ledger.statement("2026-10-19".parse().unwrap()).unwrap();
+    let alice = &statement.positions[0];
+    assert_eq!((alice.bought, alice.sold, alice.quantity), (0, 40, 60));
+    assert_eq!((alice.realized_pnl, alice.unrealized_pnl), (200.0, 300.0));
+    assert_eq!(statement.balances[0].cash, ledger.balances("alice").unwrap()[0].cash);
+
+    assert!(ledger.statement("2026-10-17".parse().unwrap()).unwrap().positions.is_empty());
+}
+
+#[tokio::test]
+async fn trades_carry_the_aggressor_and_maker_taker_fees() {
+    let bus = bus();
+    let fees = FeeSchedule {
+        default: FeeRate { maker_bps: -1.0, taker_bps: 2.0, ..FeeRate::default() },
+        tiers: [("mm".to_string(), "market_maker".to_string())].into(),
+        rules: vec![FeeRule {
+            instrument: Some("AAPL".to_string()),
+            tier: Some("market_maker".to_string()),
+            rate: FeeRate { maker_bps: -2.0, taker_per_contract: 0.01, ..FeeRate::default() },
+        }],
+    };
+    let engine = start_engine_with(&bus, fees, SessionCalendar::default(), EngineMetrics::new());
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    producer::publish_order(&sink, &order("mm-1", "AAPL", "sell", 100.00, 100).with_account("mm"), &routes(), &config()).await.unwrap();
+    producer::publish_order(&sink, &order("buy-1", "AAPL", "buy", 100.00, 100).with_account("alice"), &routes(), &config()).await.unwrap();
+
+    // 10,000 traded: the taker pays 2 bps, the market maker gets 2 bps back
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!(trade.aggressor_side.as_deref(), Some("buy"));
+    assert!((trade.buyer_fee - 2.0).abs() < 1e-9);
+    assert!((trade.seller_fee + 2.0).abs() < 1e-9);
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[test]
+fn match_events_from_before_liquidity_flags_still_parse() {
+    let trade: MatchEvent = serde_json::from_str(
+        r#"{"id":"t1","instrument":"AAPL","buyer_order_id":"b1","seller_order_id":"s1","price":150.0,"quantity":10,"timestamp":1700000000}"#,
+    )
+    .unwrap();
+    assert_eq!(trade.aggressor_side, None);
+    assert_eq!(trade.passive_order_id, None);
+    assert_eq!(trade.sequence, 0);
+}
+
+#[tokio::test]
+async fn a_rejected_order_can_be_sent_again_under_the_same_id() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+
+    let orders = [
+        order("s1", "AAPL", "sell", 150.00, 10),
+        order("b1", "AAPL", "buy", 150.005, 10),
+        order("b1", "AAPL", "buy", 150.00, 10),
+        order("b1", "AAPL", "buy", 150.00, 10),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+
+    // Only the accepted copy is remembered
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!(trade.buyer_order_id, "b1");
+    wait_for(|| order_events(&bus, "b1").len() == 2).await;
+    let events = order_events(&bus, "b1");
+    assert!(events[0].contains(r#""reason":"invalid_tick_size""#), "{:?}", events);
+    assert!(events[1].contains(r#""reason":"duplicate_order_id""#), "{:?}", events);
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn resubmitted_orders_are_rejected_as_duplicates_across_a_handover() {
+    let bus = bus();
+    let snapshots = MemorySnapshotStore::new();
+    let sink = bus.sink();
+    let partition = custom_partition("AAPL", PARTITIONS);
+    let duplicates = |bus: &MemoryBus| payloads(bus, "order-events").iter().filter(|p| p.contains("duplicate_order_id")).count();
+
+    let (first, first_rebalancer) = start_engine_instance(&bus, snapshots.clone(), FeeSchedule::default(), SessionCalendar::default(), EngineMetrics::new());
+    first_rebalancer.assign(partition);
+
+    let buy = order("buy-1", "AAPL", "buy", 150.00, 100);
+    producer::publish_order(&sink, &buy, &routes(), &config()).await.unwrap();
+    producer::publish_order(&sink, &buy, &routes(), &config()).await.unwrap();
+    wait_for(|| duplicates(&bus) == 1).await;
+
+    // The next owner remembers the id from the snapshot
+    first_rebalancer.revoke(partition).await;
+    let (second, second_rebalancer) = start_engine_instance(&bus, snapshots.clone(), FeeSchedule::default(), SessionCalendar::default(), EngineMetrics::new());
+    second_rebalancer.assign(partition);
+
+    producer::publish_order(&sink, &buy, &routes(), &config()).await.unwrap();
+    wait_for(|| duplicates(&bus) == 2).await;
+    assert!(bus.records("match-events").is_empty());
+
+    first.abort();
+    second.abort();
+}
+
+#[tokio::test]
+async fn participants_cancel_and_amend_orders_by_client_order_id() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+    let events = |bus: &MemoryBus, needle: &str| payloads(bus, "order-events").iter().filter(|p| p.contains(needle)).count();
+
+    let buy = order("buy-1", "AAPL", "buy", 150.00, 100).with_client_order_id("firm-a", "c1");
+    producer::publish_order(&sink, &buy, &routes(), &config()).await.unwrap();
+
+    // The client order id is taken while the order is live
+    let again = order("buy-2", "AAPL", "buy", 150.00, 100).with_client_order_id("firm-a", "c1");
+    producer::publish_order(&sink, &again, &routes(), &config()).await.unwrap();
+    wait_for(|| events(&bus, "duplicate_client_order_id") == 1).await;
+
+    let amend = OrderRequest::Amend {
+        instrument: "AAPL".to_string(),
+        participant: "firm-a".to_string(),
+        client_order_id: "c1".to_string(),
+        price: None,
+        quantity: Some(60),
+    };
+    producer::publish_request(&sink, &amend, &routes(), &config()).await.unwrap();
+    wait_for(|| events(&bus, r#""kept_priority":true"#) == 1).await;
+
+    let cancel = OrderRequest::Cancel {
+        instrument: "AAPL".to_string(),
+        participant: "firm-a".to_string(),
+        client_order_id: "c1".to_string(),
+    };
+    producer::publish_request(&sink, &cancel, &routes(), &config()).await.unwrap();
+    wait_for(|| events(&bus, r#""remaining_quantity":60,"reason":"cancelled by participant""#) == 1).await;
+    producer::publish_request(&sink, &cancel, &routes(), &config()).await.unwrap();
+    wait_for(|| events(&bus, "unknown_order") == 1).await;
+
+    // Once the order is gone its client order id can be used again
+    let buy = order("buy-3", "AAPL", "buy", 150.00, 50).with_client_order_id("firm-a", "c1");
+    producer::publish_order(&sink, &buy, &routes(), &config()).await.unwrap();
+    let sell = order("sell-1", "AAPL", "sell", 150.00, 50).with_client_order_id("firm-b", "s1");
+    producer::publish_order(&sink, &sell, &routes(), &config()).await.unwrap();
+
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!(trade.buyer_order_id, "buy-3");
+    assert_eq!(trade.buyer_participant.as_deref(), Some("firm-a"));
+    assert_eq!(trade.buyer_client_order_id.as_deref(), Some("c1"));
+    assert_eq!(trade.seller_participant.as_deref(), Some("firm-b"));
+    assert_eq!(trade.seller_client_order_id.as_deref(), Some("s1"));
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn a_mass_cancel_reaches_every_partition_and_each_reports_back() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let (monitor, mut trades) = start_monitor(&bus);
+    let sink = bus.sink();
+    let reports = |bus: &MemoryBus| -> Vec<MassCancelReport> {
+        payloads(bus, "control-plane").iter().filter_map(|p| serde_json::from_str(p).ok()).collect()
+    };
+
+    let orders = [
+        order("a-1", "AAPL", "buy", 151.00, 100).with_client_order_id("firm-a", "c1"),
+        order("a-2", "MSFT", "sell", 301.00, 50).with_client_order_id("firm-a", "c2"),
+        order("b-1", "AAPL", "buy", 150.00, 100).with_client_order_id("firm-b", "c1"),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+    // Every order is in the books before the mass cancel
+    let aapl = custom_partition("AAPL", PARTITIONS);
+    let msft = custom_partition("MSFT", PARTITIONS);
+    assert_ne!(aapl, msft);
+    wait_for(|| bus.committed_offset("orders", aapl) == Some(1) && bus.committed_offset("orders", msft) == Some(0)).await;
+
+    let mass_cancel = r#"{"command":"mass_cancel","filter":{"participant":"firm-a"}}"#;
+    sink.send("control-plane", "control", mass_cancel, None).await.unwrap();
+    wait_for(|| reports(&bus).len() == PARTITIONS as usize).await;
+
+    let reports = reports(&bus);
+    assert_eq!(reports.iter().map(|r| r.cancelled).sum::<u32>(), 2);
+    let aapl_report = reports.iter().find(|r| r.partition == aapl).unwrap();
+    assert!(aapl_report.order_ids.contains(&"a-1".to_string()));
+    assert_eq!(payloads(&bus, "order-events").iter().filter(|p| p.contains("mass cancel")).count(), 2);
+
+    // Other participants' orders are left alone
+    producer::publish_order(&sink, &order("s-1", "AAPL", "sell", 149.00, 10), &routes(), &config()).await.unwrap();
+    let (trade, _) = next_trade(&mut trades).await;
+    assert_eq!(trade.buyer_order_id, "b-1");
+
+    engine.abort();
+    monitor.abort();
+}
+
+#[tokio::test]
+async fn orders_of_a_session_whose_heartbeats_stop_are_cancelled() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let sink = bus.sink();
+    let partition = custom_partition("AAPL", PARTITIONS);
+    let cancelled = |bus: &MemoryBus, order_id: &str| {
+        payloads(bus, "order-events")
+            .iter()
+            .any(|p| p.contains("session disconnected") && p.contains(&format!(r#""order_id":"{}""#, order_id)))
+    };
+
+    // The watchdog runs on the engine clock, which follows the record
+    // timestamps; the sessions time out after two seconds
+    let start = 1_700_000_000_000;
+    bus.set_time(start);
+    producer::publish_order(&sink, &order("buy-1", "AAPL", "buy", 150.00, 100).with_session("gateway-1"), &routes(), &config()).await.unwrap();
+    producer::publish_order(&sink, &order("buy-2", "AAPL", "buy", 149.00, 100).with_session("gateway-2"), &routes(), &config()).await.unwrap();
+    wait_for(|| bus.committed_offset("orders", partition) == Some(1)).await;
+
+    // A second later only the first gateway is still heard from
+    bus.set_time(start + 1000);
+    producer::publish_order(&sink, &order("tick-1", "AAPL", "buy", 140.00, 10), &routes(), &config()).await.unwrap();
+    wait_for(|| bus.committed_offset("orders", partition) == Some(2)).await;
+    send_control(&bus, r#"{"command":"heartbeat","session":"gateway-1"}"#, "AAPL").await;
+
+    bus.set_time(start + 2000);
+    producer::publish_order(&sink, &order("tick-2", "AAPL", "buy", 140.00, 10), &routes(), &config()).await.unwrap();
+    wait_for(|| cancelled(&bus, "buy-2")).await;
+    assert!(!cancelled(&bus, "buy-1"));
+
+    engine.abort();
+}
+
+#[tokio::test]
+async fn gtd_orders_expire_at_their_expire_time_and_day_orders_at_the_close() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let sink = bus.sink();
+    let now = 1_700_000_000;
+    bus.set_time(now * 1000);
+    let expired = |bus: &MemoryBus, order_id: &str| {
+        payloads(bus, "order-events")
+            .iter()
+            .any(|p| p.contains(r#""event":"expired""#) && p.contains(&format!(r#""order_id":"{}""#, order_id)))
+    };
+
+    let gtd = order("gtd-1", "AAPL", "buy", 150.00, 100).with_expire_time(now + 1);
+    let stale = order("gtd-2", "AAPL", "buy", 150.00, 100).with_expire_time(now - 60);
+    let day = order("day-1", "AAPL", "sell", 160.00, 100).with_time_in_force("day");
+    for order in [&gtd, &stale, &day] {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+
+    wait_for(|| payloads(&bus, "order-events").iter().any(|p| p.contains("expire_time_in_past"))).await;
+
+    // The engine clock follows the records, not the wall clock: gtd-1 is
+    // still resting after its expire time until the next order arrives
+    bus.set_time((now + 2) * 1000);
+    assert!(!expired(&bus, "gtd-1"));
+    producer::publish_order(&sink, &order("next", "AAPL", "buy", 140.00, 10), &routes(), &config()).await.unwrap();
+    wait_for(|| expired(&bus, "gtd-1")).await;
+    assert!(!expired(&bus, "day-1"));
+
+    let close = r#"{"command":"set_trading_phase","instrument":"AAPL","phase":"closed"}"#;
+    sink.send("control-plane", "control", close, None).await.unwrap();
+    wait_for(|| expired(&bus, "day-1")).await;
+
+    engine.abort();
+}
+
+#[tokio::test]
+async fn the_session_calendar_drives_trading_phases() {
+    SessionCalendar::load(concat!(env!("CARGO_MANIFEST_DIR"), "/calendar.json")).expect("example calendar");
+
+    // The engine clock follows the record timestamps, set here to a Monday
+    let at = |time: &str| -> i64 {
+        chrono::NaiveDateTime::parse_from_str(&format!("2024-03-04 {}", time), "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+            .timestamp_millis()
+    };
+    let session = |name: &str, instrument: &str, holidays: &[&str]| -> SessionSchedule {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "instruments": [instrument],
+            "pre_open": "08:00",
+            "opening_auction": "09:00",
+            "continuous": "09:30",
+            "closing_auction": "16:00",
+            "post_close": "16:10",
+            "close": "17:00",
+            "trading_days": ["Mon", "Tue", "Wed", "Thu", "Fri"],
+            "holidays": holidays,
+        }))
+        .unwrap()
+    };
+    let calendar = SessionCalendar::new(vec![
+        session("regular", "AAPL", &[]),
+        session("holiday", "MSFT", &["2024-03-04"]),
+    ])
+    .unwrap();
+
+    let bus = bus();
+    let engine = start_engine_with(&bus, FeeSchedule::default(), calendar, EngineMetrics::new());
+    let sink = bus.sink();
+
+    bus.set_time(at("09:10"));
+    producer::publish_order(&sink, &order("b1", "AAPL", "buy", 151.00, 100), &routes(), &config()).await.unwrap();
+    producer::publish_order(&sink, &order("s1", "AAPL", "sell", 149.00, 100), &routes(), &config()).await.unwrap();
+    producer::publish_order(&sink, &order("m1", "MSFT", "buy", 300.00, 100), &routes(), &config()).await.unwrap();
+
+    // Crossing orders rest in the opening auction instead of trading
+    wait_for(|| payloads(&bus, "market-data").iter().filter(|p| p.contains("indicative_price")).count() == 2).await;
+    assert!(payloads(&bus, "market-data").iter().any(|p| p.contains(r#""state":"opening_auction""#)));
+    assert!(bus.records("match-events").is_empty());
+
+    // Nothing trades on a holiday
+    wait_for(|| payloads(&bus, "order-events").iter().any(|p| p.contains("instrument_closed"))).await;
+
+    // The next order after continuous trading starts ends the auction,
+    // uncrossing the book
+    bus.set_time(at("09:31"));
+    producer::publish_order(&sink, &order("b2", "AAPL", "buy", 140.00, 10), &routes(), &config()).await.unwrap();
+    wait_for(|| payloads(&bus, "market-data").iter().any(|p| p.contains(r#""state":"continuous""#))).await;
+    wait_for(|| bus.records("match-events").len() == 1).await;
+
+    engine.abort();
+}
+
+#[tokio::test]
+async fn candles_aggregate_trades_per_interval_and_commit_behind_open_bars() {
+    let trades = [
+        trade("t1", "AAPL", 100.0, 10, 60),
+        trade("t2", "MSFT", 300.0, 5, 70),
+        trade("t3", "AAPL", 102.0, 30, 90),
+        trade("t4", "AAPL", 99.0, 10, 119),
+        trade("t5", "AAPL", 101.0, 5, 125),
+        trade("t6", "MSFT", 301.0, 5, 200),
+    ];
+    let minute = || vec![("1m".to_string(), 60)];
+
+    // The first AAPL bar completes on the first trade after it, but its
+    // trades span MSFT's open bar, so nothing can be committed yet
+    let mut builder = CandleBuilder::new(minute());
+    for (offset, trade) in trades[..4].iter().enumerate() {
+        assert!(builder.add_trade(trade, 0, offset as i64).is_empty());
+    }
+    let completed = builder.add_trade(&trades[4], 0, 4);
+    assert_eq!(completed.len(), 1);
+    let bar = &completed[0];
+    assert_eq!((bar.start, bar.end), (60, 120));
+    assert_eq!((bar.open, bar.high, bar.low, bar.close), (100.0, 102.0, 99.0, 99.0));
+    assert_eq!((bar.volume, bar.trade_count), (50, 3));
+    assert_eq!(bar.turnover, 5050.0);
+    assert_eq!(bar.vwap, 101.0);
+    assert_eq!(builder.restart_offset(0), Some(0));
+
+    // Once MSFT's bar completes too, the oldest open bar starts at offset 4
+    assert_eq!(builder.add_trade(&trades[5], 0, 5).len(), 1);
+    assert_eq!(builder.restart_offset(0), Some(4));
+
+    // The service publishes every bar, those left open closed by the clock.
+    // The first bar is only delivered on a later attempt, holding back the
+    // commit until then
+    let bus = bus();
+    bus.create_topic("candles", PARTITIONS);
+    bus.fail_sends("candles", 1);
+    let sink = bus.sink();
+    for trade in &trades {
+        sink.send("match-events", &trade.instrument, &serde_json::to_string(trade).unwrap(), Some(0)).await.unwrap();
+    }
+    let mut config = config();
+    config.candles.intervals = vec!["1m".to_string()];
+    let builder = CandleBuilder::new(config.candle_intervals().unwrap());
+    let service = tokio::spawn(candles::run(bus.source(&["match-events"]), bus.sink(), builder, config));
+
+    let published = || -> Vec<Candle> {
+        payloads(&bus, "candles").iter().map(|payload| serde_json::from_str(payload).unwrap()).collect()
+    };
+    wait_for(|| published().len() == 4).await;
+    let bars = published();
+    let aapl: Vec<_> = bars.iter().filter(|bar| bar.instrument == "AAPL").map(|bar| (bar.start, bar.volume)).collect();
+    assert_eq!(aapl, [(60, 50), (120, 5)]);
+    assert!(bars.iter().all(|bar| bar.interval == "1m"));
+    wait_for(|| bus.committed_offset("match-events", 0) == Some(5)).await;
+
+    service.abort();
+}
+
+#[tokio::test]
+async fn the_trade_store_answers_history_queries_and_exports() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let sink = bus.sink();
+
+    let store = Arc::new(Mutex::new(TradeStore::open_in_memory().unwrap()));
+    let store_task = tokio::spawn(trade_store::run(bus.source(&["match-events"]), store.clone()));
+
+    let orders = [
+        order("a1", "AAPL", "buy", 150.00, 100).with_account("alice"),
+        order("b1", "AAPL", "sell", 150.00, 60).with_account("bob"),
+        order("c1", "AAPL", "sell", 149.00, 40).with_account("carol"),
+        order("m1", "MSFT", "buy", 300.00, 10).with_account("bob"),
+        order("m2", "MSFT", "sell", 300.00, 10).with_account("carol"),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+    let all = TradeQuery::default();
+    wait_for(|| store.lock().unwrap().query(&all).unwrap().len() == 3).await;
+
+    let store = store.lock().unwrap();
+    let ids = |query: TradeQuery| -> Vec<(String, u32)> {
+        store.query(&query).unwrap().into_iter().map(|trade| (trade.instrument, trade.quantity)).collect()
+    };
+    let aapl = TradeQuery { instrument: Some("AAPL".to_string()), ..Default::default() };
+    assert_eq!(ids(aapl.clone()), [("AAPL".to_string(), 60), ("AAPL".to_string(), 40)]);
+    let bob = TradeQuery { account: Some("bob".to_string()), ..Default::default() };
+    assert_eq!(ids(bob).len(), 2);
+    let order_c1 = TradeQuery { order_id: Some("c1".to_string()), ..Default::default() };
+    assert_eq!(ids(order_c1), [("AAPL".to_string(), 40)]);
+    let before = TradeQuery { to: Some(1), ..Default::default() };
+    assert!(ids(before).is_empty());
+
+    let mut csv = Vec::new();
+    store.export(&aapl, ExportFormat::Csv, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert_eq!(csv.lines().count(), 3);
+    assert!(csv.starts_with("id,instrument,sequence,timestamp"));
+
+    let path = std::env::temp_dir().join(format!("trades-{}.parquet", std::process::id()));
+    store.export(&all, ExportFormat::Parquet, std::fs::File::create(&path).unwrap()).unwrap();
+    let parquet = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(parquet.metadata().file_metadata().num_rows(), 3);
+    std::fs::remove_file(&path).unwrap();
+    drop(store);
+
+    engine.abort();
+    store_task.abort();
+}
+
+#[tokio::test]
+async fn reconciliation_replays_orders_and_reports_trades_that_do_not_add_up() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let sink = bus.sink();
+
+    let orders = [
+        order("b1", "AAPL", "buy", 150.00, 100),
+        order("s1", "AAPL", "sell", 150.00, 60),
+        order("s2", "AAPL", "sell", 149.00, 40),
+        order("m1", "MSFT", "buy", 300.00, 10),
+        order("m2", "MSFT", "sell", 300.00, 10),
+    ];
+    for order in &orders {
+        producer::publish_order(&sink, order, &routes(), &config()).await.unwrap();
+    }
+    wait_for(|| bus.records("match-events").len() == 3).await;
+    engine.abort();
+
+    let registry = InstrumentRegistry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/instruments.json")).unwrap();
+    let replay = || reconciliation::replay_engine(registry.clone(), SessionCalendar::default(), &config());
+    let inputs = reconciliation::read_until_idle(&mut bus.source(&["orders", "control-plane"]), Duration::from_millis(100)).await;
+    let published = bus.records("match-events");
+
+    let report = reconciliation::reconcile(replay(), inputs.clone(), published.clone(), &config().topics, 0, i64::MAX);
+    assert!(report.is_clean(), "{:?}", report.discrepancies);
+    assert_eq!((report.orders_replayed, report.trades_expected, report.trades_published), (5, 3, 3));
+
+    // Drop the MSFT trade and publish the last AAPL trade twice
+    let mut tampered: Vec<_> = published.into_iter().filter(|record| !record.payload.as_ref().unwrap().contains("MSFT")).collect();
+    let mut duplicate = tampered[1].clone();
+    let mut trade: MatchEvent = serde_json::from_str(duplicate.payload.as_ref().unwrap()).unwrap();
+    trade.id = "forged".to_string();
+    duplicate.payload = Some(serde_json::to_string(&trade).unwrap());
+    tampered.push(duplicate);
+
+    let report = reconciliation::reconcile(replay(), inputs, tampered, &config().topics, 0, i64::MAX);
+    let kinds: Vec<_> = report
+        .discrepancies
+        .iter()
+        .map(|discrepancy| match discrepancy {
+            Discrepancy::MissingTrade { instrument, .. } => format!("missing {}", instrument),
+            Discrepancy::ExtraTrade { instrument, .. } => format!("extra {}", instrument),
+            Discrepancy::QuantityMismatch { seller_order_id, expected, published, .. } => {
+                format!("mismatch {} {} {}", seller_order_id, expected, published)
+            }
+            Discrepancy::OverFill { order_id, filled, .. } => format!("overfill {} {}", order_id, filled),
+        })
+        .collect();
+    assert_eq!(kinds, ["mismatch s2 40 80", "missing MSFT", "overfill b1 140", "overfill s2 80"]);
+}
+
+#[tokio::test]
+async fn reconciliation_resumes_volatility_auctions_on_the_record_clock() {
+    let bus = bus();
+    let engine = start_engine(&bus);
+    let sink = bus.sink();
+
+    let bands = r#"{"command":"set_price_bands","instrument":"AAPL","bands":{"dynamic_band_pct":2.0,"interruption_secs":1}}"#;
+    send_control(&bus, bands, "AAPL").await;
+    let start = 1_700_000_000_000;
+    bus.set_time(start);
+
+    for order in [
+        order("b1", "AAPL", "buy", 150.00, 100),
+        order("s1", "AAPL", "sell", 150.00, 10),
+        order("s2", "AAPL", "sell", 160.00, 50),
+        order("b2", "AAPL", "buy", 160.00, 10),
+    ] {
+        producer::publish_order(&sink, &order, &routes(), &config()).await.unwrap();
+    }
+    wait_for(|| payloads(&bus, "control-plane").iter().any(|p| p.contains("volatility_interruption"))).await;
+    assert_eq!(bus.records("match-events").len(), 1);
+
+    // The auction is over by the time the next order is written
+    bus.set_time(start + 2000);
+    producer::publish_order(&sink, &order("b3", "AAPL", "buy", 160.00, 5), &routes(), &config()).await.unwrap();
+    wait_for(|| bus.records("match-events").len() == 3).await;
+    engine.abort();
+
+    let registry = InstrumentRegistry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/instruments.json")).unwrap();
+    let replay = reconciliation::replay_engine(registry, SessionCalendar::default(), &config());
+    let inputs = reconciliation::read_until_idle(&mut bus.source(&["orders", "control-plane"]), Duration::from_millis(100)).await;
+    let report = reconciliation::reconcile(replay, inputs, bus.records("match-events"), &config().topics, 0, i64::MAX);
+    assert!(report.is_clean(), "{:?}", report.discrepancies);
+    assert_eq!((report.trades_expected, report.trades_published), (3, 3));
+}
+
+#[test]
+fn the_invariant_checker_finds_corrupted_books() {
+    let registry = InstrumentRegistry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/instruments.json")).unwrap();
+    let mut engine = reconciliation::replay_engine(registry, SessionCalendar::default(), &config());
+    engine.process_order(order("b1", "AAPL", "buy", 150.00, 100));
+    engine.process_order(order("b2", "AAPL", "buy", 150.00, 50));
+    engine.process_order(order("s1", "AAPL", "sell", 151.00, 80));
+    engine.process_order(order("s2", "AAPL", "sell", 150.00, 120));
+    assert!(engine.check_invariants().is_empty(), "{:?}", engine.check_invariants());
+
+    // A snapshot of the corrupted book still shows what is wrong with it
+    let book = engine.order_books.get_mut("AAPL").unwrap();
+    let key = book.price_to_key(151.00);
+    book.asks.get_mut(&key).unwrap().total_quantity = 70;
+    let key = book.price_to_key(149.00);
+    book.asks.insert(key, PriceLevel::new(149.00));
+    let restored: MatchingEngine = serde_json::from_str(&serde_json::to_string(&engine).unwrap()).unwrap();
+
+    let violations: Vec<_> = restored.check_invariants().into_iter().map(|(_, violation)| violation).collect();
+    assert_eq!(violations, [
+        BookViolation::Crossed { best_bid: 150.00, best_ask: 149.00 },
+        BookViolation::EmptyLevel { side: "sell".to_string(), price: 149.00 },
+        BookViolation::StaleTotal { side: "sell".to_string(), price: 151.00, total_quantity: 70, orders_quantity: 80 },
+    ]);
+}